@@ -0,0 +1,65 @@
+//! Skinning `bob` with `prepare_mesh`/`prepare_normals` against the packed
+//! and batched skinning of `vertex_computation::parallel`:
+//!
+//!     cargo bench --bench skinning
+
+extern crate amalia;
+extern crate cgmath;
+#[macro_use]
+extern crate criterion;
+
+use cgmath::Vector3;
+use criterion::Criterion;
+use amalia::loader::load::{load_model, load_anim};
+use amalia::animation::pose::frame_skeleton;
+use amalia::vertex_computation::compute::{prepare_mesh, prepare_normals};
+use amalia::vertex_computation::parallel::{PackedModel, JointPalette, SkinTarget,
+                                           skin_vertices_into, normals_into, skin_batch_into};
+
+/// Characters skinned per iteration.
+const CHARACTERS: usize = 64;
+
+fn skinning(c: &mut Criterion) {
+    let mesh = load_model("./Resources/bob_lamp_update/bob_lamp_update_export.md5mesh").unwrap().mesh;
+    let anim = load_anim("./Resources/bob_lamp_update/bob_lamp_update_export.md5anim").unwrap();
+    let skeleton = frame_skeleton(&anim, 30);
+    let model = PackedModel::new(&mesh);
+    let palette = JointPalette::new(&skeleton);
+    let mut positions = vec![Vector3::new(0.0, 0.0, 0.0); model.vertex_count * CHARACTERS];
+    let mut normals = positions.clone();
+
+    let mut group = c.benchmark_group(format!("skin {} characters", CHARACTERS));
+    group.sample_size(20);
+
+    group.bench_function("prepare_mesh", |b| b.iter(|| {
+        for _ in 0..CHARACTERS {
+            for m in &mesh.meshes {
+                let positions = prepare_mesh(m, &skeleton);
+                prepare_normals(m, &positions);
+            }
+        }
+    }));
+
+    group.bench_function("packed", |b| b.iter(|| {
+        for (p, n) in positions.chunks_mut(model.vertex_count).zip(normals.chunks_mut(model.vertex_count)) {
+            for (m, &offset) in model.meshes.iter().zip(&model.offsets) {
+                let range = offset..offset + m.vertex_count();
+                skin_vertices_into(m, &palette, 0, &mut p[range.clone()]);
+                normals_into(m, &p[range.clone()], &mut n[range]);
+            }
+        }
+    }));
+
+    group.bench_function("skin_batch_into", |b| b.iter(|| {
+        let mut targets: Vec<SkinTarget> = positions.chunks_mut(model.vertex_count)
+            .zip(normals.chunks_mut(model.vertex_count))
+            .map(|(positions, normals)| SkinTarget { model: &model, palette: &palette, positions, normals })
+            .collect();
+        skin_batch_into(&mut targets);
+    }));
+
+    group.finish();
+}
+
+criterion_group!(benches, skinning);
+criterion_main!(benches);
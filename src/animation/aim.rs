@@ -0,0 +1,232 @@
+use cgmath::{Vector3, Quaternion, InnerSpace, Rad, Rotation3};
+use md5::md5mesh::Joint;
+use animation::skeleton::{joint_chain, rotate_joint};
+use std::f32::consts::FRAC_PI_2;
+
+const EPSILON: f32 = 1e-6;
+
+/// Joint taking part in an aim constraint. `weight` is its share of the
+/// total rotation relative to the other joints of the constraint, the
+/// limits bound the yaw and pitch it may add on top of the animated pose.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AimJoint {
+    pub joint: usize,
+    pub weight: f32,
+    pub yaw_limit: Rad<f32>,
+    pub pitch_limit: Rad<f32>,
+}
+
+/// Turns a chain of joints such as `["neck", "head"]` so that the last one
+/// looks at a target.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AimConstraint {
+    /// Joints from the root of the chain to the aiming joint.
+    pub joints: Vec<AimJoint>,
+    /// Aim axis in the space of the aiming joint.
+    pub forward: Vector3<f32>,
+    /// Axis yaw is measured around, in the space of the aiming joint.
+    pub up: Vector3<f32>,
+}
+
+impl AimConstraint {
+    /// Builds a constraint sharing the rotation equally between `names`,
+    /// with a quarter turn of yaw and pitch allowed on each joint.
+    pub fn from_names(skeleton: &[Joint], names: &[&str], forward: Vector3<f32>,
+                      up: Vector3<f32>) -> Option<AimConstraint> {
+        joint_chain(skeleton, names).map(|chain| AimConstraint {
+            joints: chain.into_iter().map(|joint| AimJoint {
+                joint,
+                weight: 1.0,
+                yaw_limit: Rad(FRAC_PI_2),
+                pitch_limit: Rad(FRAC_PI_2)
+            }).collect(),
+            forward: forward.normalize(),
+            up: up.normalize()
+        })
+    }
+
+    pub fn with_weight(mut self, position: usize, weight: f32) -> AimConstraint {
+        self.joints[position].weight = weight;
+        self
+    }
+
+    pub fn with_limits(mut self, position: usize, yaw: Rad<f32>, pitch: Rad<f32>) -> AimConstraint {
+        self.joints[position].yaw_limit = yaw;
+        self.joints[position].pitch_limit = pitch;
+        self
+    }
+}
+
+/// Rotates the constrained joints of a sampled skeleton towards the object
+/// space `target`. The yaw and pitch turning the aiming joint to the target
+/// are found once; each joint, from the root of the chain on, takes its
+/// share of them, clamped to its limits. `weight` then scales the turn of
+/// every joint, so half the weight gives half the turn, limits or not.
+pub fn apply_aim(skeleton: &mut [Joint], constraint: &AimConstraint, target: Vector3<f32>, weight: f32) {
+    let total: f32 = constraint.joints.iter().map(|j| j.weight.max(0.0)).sum();
+    if constraint.joints.is_empty() || total < EPSILON {
+        return;
+    }
+
+    // Turning the joints above the aiming one moves it, so the angles seen
+    // from where it starts fall short of the target: correct them by what
+    // is still missing after the full turn, on a copy of the skeleton, until
+    // the limits leave nothing to correct.
+    let (mut yaw, mut pitch) = target_angles(skeleton, constraint, target);
+    let mut angles = joint_angles(constraint, yaw, pitch, 1.0 / total);
+    for _ in 0..REFINE_STEPS {
+        let mut turned = skeleton.to_vec();
+        turn_chain(&mut turned, constraint, &angles, 1.0);
+        let (missing_yaw, missing_pitch) = target_angles(&turned, constraint, target);
+        if missing_yaw.abs() < EPSILON && missing_pitch.abs() < EPSILON {
+            break;
+        }
+        yaw += missing_yaw;
+        pitch += missing_pitch;
+        let refined = joint_angles(constraint, yaw, pitch, 1.0 / total);
+        if refined == angles {
+            break;
+        }
+        angles = refined;
+    }
+
+    turn_chain(skeleton, constraint, &angles, weight.clamp(0.0, 1.0));
+}
+
+/// Corrections of the total turn `apply_aim` makes at most.
+const REFINE_STEPS: usize = 8;
+
+/// Yaw and pitch turning the aiming joint of `constraint` to `target`.
+fn target_angles(skeleton: &[Joint], constraint: &AimConstraint, target: Vector3<f32>) -> (f32, f32) {
+    let aiming = &skeleton[constraint.joints[constraint.joints.len() - 1].joint];
+    let orientation = aiming.orientation;
+    aim_angles(orientation * constraint.forward, orientation * constraint.up, target - aiming.position)
+}
+
+/// Yaw and pitch of each joint of the chain: `scale` times its weight of
+/// `yaw` and `pitch`, clamped to its limits.
+fn joint_angles(constraint: &AimConstraint, yaw: f32, pitch: f32, scale: f32) -> Vec<(f32, f32)> {
+    constraint.joints.iter().map(|aim_joint| {
+        let share = scale * aim_joint.weight.max(0.0);
+        (clamp_angle(yaw * share, aim_joint.yaw_limit.0), clamp_angle(pitch * share, aim_joint.pitch_limit.0))
+    }).collect()
+}
+
+/// Turns each joint of the chain by `fade` times its angles.
+fn turn_chain(skeleton: &mut [Joint], constraint: &AimConstraint, angles: &[(f32, f32)], fade: f32) {
+    let aiming = constraint.joints[constraint.joints.len() - 1].joint;
+    for (aim_joint, &(yaw, pitch)) in constraint.joints.iter().zip(angles) {
+        let (yaw, pitch) = (yaw * fade, pitch * fade);
+
+        let orientation = skeleton[aiming].orientation;
+        let up = orientation * constraint.up;
+        let yaw_rotation = Quaternion::from_axis_angle(up, Rad(yaw));
+        let forward = yaw_rotation * (orientation * constraint.forward);
+        let pitch_axis = forward.cross(up);
+        let rotation =
+            if pitch_axis.magnitude2() < EPSILON {
+                yaw_rotation
+            } else {
+                Quaternion::from_axis_angle(pitch_axis.normalize(), Rad(pitch)) * yaw_rotation
+            };
+
+        rotate_joint(skeleton, aim_joint.joint, rotation);
+    }
+}
+
+/// Yaw around `up` then pitch towards `up` turning `forward` into
+/// `direction`.
+fn aim_angles(forward: Vector3<f32>, up: Vector3<f32>, direction: Vector3<f32>) -> (f32, f32) {
+    if direction.magnitude2() < EPSILON {
+        return (0.0, 0.0);
+    }
+
+    let left = up.cross(forward);
+    let along = direction.dot(forward);
+    let side = direction.dot(left);
+    let height = direction.dot(up);
+
+    (side.atan2(along), height.atan2((along * along + side * side).sqrt()))
+}
+
+fn clamp_angle(angle: f32, limit: f32) -> f32 {
+    let limit = limit.abs();
+    angle.clamp(-limit, limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Quaternion, InnerSpace, Rad};
+    use md5::md5mesh::Joint;
+    use std::f32::consts::PI;
+    use super::AimConstraint;
+
+    fn neck_and_head() -> Vec<Joint> {
+        vec![
+            Joint { name: String::from("neck"), parent_index: -1, position: Vector3::new(0.0, 0.0, 0.0), orientation: Quaternion::new(1.0, 0.0, 0.0, 0.0) },
+            Joint { name: String::from("head"), parent_index: 0, position: Vector3::new(0.0, 0.0, 1.0), orientation: Quaternion::new(1.0, 0.0, 0.0, 0.0) }
+        ]
+    }
+
+    fn constraint(skeleton: &[Joint]) -> AimConstraint {
+        AimConstraint::from_names(skeleton, &["neck", "head"], Vector3::unit_y(), Vector3::unit_z()).unwrap()
+    }
+
+    #[test]
+    fn apply_aim() {
+        let mut skeleton = neck_and_head();
+        let aim = constraint(&skeleton);
+        let target = Vector3::new(5.0, 5.0, 3.0);
+        super::apply_aim(&mut skeleton, &aim, target, 1.0);
+
+        let forward = skeleton[1].orientation * Vector3::unit_y();
+        let expected = (target - skeleton[1].position).normalize();
+        assert!(forward.dot(expected) > 0.9999);
+    }
+
+    #[test]
+    fn apply_aim_limits() {
+        let mut skeleton = neck_and_head();
+        let aim = constraint(&skeleton)
+            .with_limits(0, Rad(PI / 8.0), Rad(0.0))
+            .with_limits(1, Rad(PI / 8.0), Rad(0.0));
+        super::apply_aim(&mut skeleton, &aim, Vector3::new(10.0, 0.0, 1.0), 1.0);
+
+        let forward = skeleton[1].orientation * Vector3::unit_y();
+        assert!((forward.dot(Vector3::unit_y()) - (PI / 4.0).cos()).abs() < 1e-4);
+        assert!(forward.z.abs() < 1e-4);
+    }
+
+    #[test]
+    fn apply_aim_weights() {
+        let mut skeleton = neck_and_head();
+        let aim = constraint(&skeleton).with_weight(0, 0.0);
+        super::apply_aim(&mut skeleton, &aim, Vector3::new(10.0, 0.0, 1.0), 1.0);
+
+        assert!((skeleton[1].position - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+        let forward = skeleton[1].orientation * Vector3::unit_y();
+        assert!(forward.x > 0.999);
+    }
+
+    #[test]
+    fn apply_aim_fade() {
+        let mut skeleton = neck_and_head();
+        let aim = constraint(&skeleton);
+        super::apply_aim(&mut skeleton, &aim, Vector3::new(10.0, 0.0, 1.0), 0.5);
+
+        // Half of the quarter turn to the target.
+        let forward = skeleton[1].orientation * Vector3::unit_y();
+        assert!((forward.dot(Vector3::unit_y()) - (PI / 4.0).cos()).abs() < 1e-4);
+
+        // With limits binding, the weight fades the limited turn.
+        let mut skeleton = neck_and_head();
+        let aim = constraint(&skeleton)
+            .with_limits(0, Rad(PI / 8.0), Rad(0.0))
+            .with_limits(1, Rad(PI / 8.0), Rad(0.0));
+        super::apply_aim(&mut skeleton, &aim, Vector3::new(10.0, 0.0, 1.0), 0.25);
+
+        let forward = skeleton[1].orientation * Vector3::unit_y();
+        assert!((forward.dot(Vector3::unit_y()) - (PI / 16.0).cos()).abs() < 1e-4);
+        assert!(forward.z.abs() < 1e-4);
+    }
+}
@@ -0,0 +1,165 @@
+use cgmath::{Vector3, InnerSpace};
+use md5::md5anim::{Md5Anim, Bound};
+use md5::md5mesh::{Md5Mesh, Joint};
+use animation::pose::{frame_at_time, frame_skeleton};
+use vertex_computation::compute::{prepare_mesh, mesh_bounds};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BoundingSphere {
+    pub center: Vector3<f32>,
+    pub radius: f32,
+}
+
+/// Frame whose stored bounds leave part of the skinned mesh out.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BoundsMismatch {
+    pub frame: usize,
+    pub stored: Bound,
+    pub skinned: Bound,
+}
+
+/// Stored bounds of frame `frame_index`, if the file has them.
+pub fn frame_bounds(anim: &Md5Anim, frame_index: usize) -> Option<&Bound> {
+    anim.bounds.get(frame_index)
+}
+
+pub fn interpolate_bounds(a: &Bound, b: &Bound, t: f32) -> Bound {
+    Bound {
+        bound_min: a.bound_min + (b.bound_min - a.bound_min) * t,
+        bound_max: a.bound_max + (b.bound_max - a.bound_max) * t
+    }
+}
+
+/// Stored bounds at `time` seconds, interpolated between the surrounding
+/// frames like the pose is.
+pub fn bounds_at_time(anim: &Md5Anim, time: f32, looping: bool) -> Option<Bound> {
+    let (current, next, t) = frame_at_time(anim, time, looping);
+    match (anim.bounds.get(current), anim.bounds.get(next)) {
+        (Some(a), Some(b)) => Some(interpolate_bounds(a, b, t)),
+        (Some(a), None) => Some(a.clone()),
+        _ => None
+    }
+}
+
+/// Whether `outer` contains `inner`, give or take `tolerance`.
+pub fn bound_contains(outer: &Bound, inner: &Bound, tolerance: f32) -> bool {
+    (0..3).all(|c| outer.bound_min[c] - tolerance <= inner.bound_min[c]
+                && inner.bound_max[c] <= outer.bound_max[c] + tolerance)
+}
+
+/// Smallest box around `points`, empty (zero sized at the origin) when there
+/// are none.
+pub fn points_bounds(points: &[Vector3<f32>]) -> Bound {
+    let first = match points.first() {
+        Some(&first) => first,
+        None => return Bound { bound_min: Vector3::new(0.0, 0.0, 0.0), bound_max: Vector3::new(0.0, 0.0, 0.0) }
+    };
+    points.iter().fold(Bound { bound_min: first, bound_max: first }, |b, p| Bound {
+        bound_min: Vector3::new(b.bound_min.x.min(p.x), b.bound_min.y.min(p.y), b.bound_min.z.min(p.z)),
+        bound_max: Vector3::new(b.bound_max.x.max(p.x), b.bound_max.y.max(p.y), b.bound_max.z.max(p.z))
+    })
+}
+
+/// Sphere around `points`, following Ritter: start from two far apart
+/// points, then grow the sphere for every point left outside. Within a few
+/// percent of the smallest sphere.
+pub fn bounding_sphere(points: &[Vector3<f32>]) -> BoundingSphere {
+    let first = match points.first() {
+        Some(&first) => first,
+        None => return BoundingSphere { center: Vector3::new(0.0, 0.0, 0.0), radius: 0.0 }
+    };
+
+    let farthest = |from: Vector3<f32>| points.iter().cloned().fold(from, |best, p| {
+        if (p - from).magnitude2() > (best - from).magnitude2() { p } else { best }
+    });
+    let a = farthest(first);
+    let b = farthest(a);
+
+    let mut center = (a + b) * 0.5;
+    let mut radius = (b - a).magnitude() * 0.5;
+    for &p in points {
+        let distance = (p - center).magnitude();
+        if distance > radius {
+            let new_radius = (radius + distance) * 0.5;
+            center += (p - center) * ((new_radius - radius) / distance);
+            radius = new_radius;
+        }
+    }
+
+    BoundingSphere { center, radius }
+}
+
+/// Every vertex of `mesh` skinned by `skeleton`.
+fn skinned_points(mesh: &Md5Mesh, skeleton: &Vec<Joint>) -> Vec<Vector3<f32>> {
+    mesh.meshes.iter().flat_map(|m| prepare_mesh(m, skeleton)).collect()
+}
+
+/// Tight box and sphere around `mesh` skinned by `skeleton`.
+pub fn skinned_bounds(mesh: &Md5Mesh, skeleton: &Vec<Joint>) -> (Bound, BoundingSphere) {
+    let points = skinned_points(mesh, skeleton);
+    (points_bounds(&points), bounding_sphere(&points))
+}
+
+/// Frames of `anim` whose stored bounds do not contain `mesh` skinned by
+/// the frame, allowing for `tolerance`. Frames without stored bounds are
+/// reported with zero sized stored bounds.
+pub fn validate_bounds(anim: &Md5Anim, mesh: &Md5Mesh, tolerance: f32) -> Vec<BoundsMismatch> {
+    (0..anim.frames.len()).filter_map(|f| {
+        let skinned = mesh_bounds(mesh, &frame_skeleton(anim, f));
+        let stored = anim.bounds.get(f).cloned().unwrap_or(Bound {
+            bound_min: Vector3::new(0.0, 0.0, 0.0),
+            bound_max: Vector3::new(0.0, 0.0, 0.0)
+        });
+        if bound_contains(&stored, &skinned, tolerance) {
+            None
+        } else {
+            Some(BoundsMismatch { frame: f, stored, skinned })
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, InnerSpace};
+    use md5::md5anim::Bound;
+    use animation::pose::frame_skeleton;
+    use vertex_computation::compute::mesh_bounds;
+    use test_support::{bob_mesh, bob_anim};
+
+    #[test]
+    fn bounds_at_time() {
+        let anim = bob_anim();
+        let halfway = super::bounds_at_time(&anim, 10.5 / 24.0, false).unwrap();
+        let expected = super::interpolate_bounds(&anim.bounds[10], &anim.bounds[11], 0.5);
+        assert!((halfway.bound_min - expected.bound_min).magnitude() < 1e-5);
+        assert!((halfway.bound_max - expected.bound_max).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn bounding_sphere() {
+        let (mesh, anim) = (bob_mesh(), bob_anim());
+        let skeleton = frame_skeleton(&anim, 20);
+        let (bound, sphere) = super::skinned_bounds(&mesh, &skeleton);
+        assert_eq!(bound, mesh_bounds(&mesh, &skeleton));
+
+        for m in &mesh.meshes {
+            for p in ::vertex_computation::compute::prepare_mesh(m, &skeleton) {
+                assert!((p - sphere.center).magnitude() <= sphere.radius + 1e-4);
+            }
+        }
+        // No bigger than the sphere around the box.
+        assert!(sphere.radius <= (bound.bound_max - bound.bound_min).magnitude() * 0.5 + 1e-4);
+    }
+
+    #[test]
+    fn validate_bounds() {
+        let (mesh, mut anim) = (bob_mesh(), bob_anim());
+        anim.bounds = (0..anim.frames.len()).map(|f| mesh_bounds(&mesh, &frame_skeleton(&anim, f))).collect();
+        assert_eq!(super::validate_bounds(&anim, &mesh, 1e-4), vec![]);
+
+        anim.bounds[7] = Bound { bound_min: Vector3::new(-0.1, -0.1, -0.1), bound_max: Vector3::new(0.1, 0.1, 0.1) };
+        let mismatches = super::validate_bounds(&anim, &mesh, 1e-4);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].frame, 7);
+    }
+}
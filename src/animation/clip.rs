@@ -0,0 +1,147 @@
+use md5::md5anim::{Md5Anim, Frame, BaseFrame};
+use animation::pose::{frame_pose, encode_anim};
+
+/// Inclusive range of frames of a longer clip, as listed in a clip sidecar
+/// file.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ClipRange {
+    pub name: String,
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct NamedClip {
+    pub name: String,
+    pub anim: Md5Anim,
+}
+
+/// Frames `start` to `end` included of `anim` as a clip of their own,
+/// numbered from zero. `None` when the range is empty or out of the clip.
+pub fn extract_range(anim: &Md5Anim, start: u32, end: u32) -> Option<Md5Anim> {
+    let (start, end) = (start as usize, end as usize);
+    if start > end || end >= anim.frames.len() {
+        return None;
+    }
+
+    let frames: Vec<Frame> = anim.frames[start..end + 1].iter().enumerate().map(|(n, frame)| Frame {
+        frame_number: n as u32,
+        frame_data: frame.frame_data.clone()
+    }).collect();
+
+    // Files without bounds for every frame keep what they have.
+    let bounds = anim.bounds.iter().skip(start).take(frames.len()).cloned().collect();
+
+    Some(Md5Anim {
+        version: anim.version,
+        command_line: anim.command_line.clone(),
+        num_frames: frames.len() as i32,
+        num_joints: anim.num_joints,
+        frame_rate: anim.frame_rate,
+        num_animated_components: anim.num_animated_components,
+        hierarchies: anim.hierarchies.clone(),
+        bounds,
+        base_frame: anim.base_frame.clone(),
+        frames
+    })
+}
+
+/// Cuts `anim` into one clip per range. `None` when one of the ranges is
+/// invalid.
+pub fn split_anim(anim: &Md5Anim, ranges: &[ClipRange]) -> Option<Vec<NamedClip>> {
+    ranges.iter().map(|range| {
+        extract_range(anim, range.start, range.end).map(|clip| NamedClip {
+            name: range.name.clone(),
+            anim: clip
+        })
+    }).collect()
+}
+
+/// Whether frames of `a` and `b` can be mixed: same joints with the same
+/// parents, played at the same rate.
+pub fn compatible_anims(a: &Md5Anim, b: &Md5Anim) -> bool {
+    a.frame_rate == b.frame_rate
+        && a.hierarchies.len() == b.hierarchies.len()
+        && a.hierarchies.iter().zip(&b.hierarchies).all(|(ja, jb)| ja.name == jb.name && ja.index == jb.index)
+}
+
+/// Plays `clips` one after the other as a single clip. Clips may animate
+/// different components, so frames are decoded and encoded again against
+/// the base frame of the first clip. `None` when `clips` is empty or not
+/// all compatible with the first one.
+///
+/// The result has bounds only when every clip has bounds for every frame.
+pub fn concatenate_anims(clips: &[&Md5Anim]) -> Option<Md5Anim> {
+    let first = match clips.first() {
+        Some(first) => *first,
+        None => return None
+    };
+    if !clips.iter().all(|clip| compatible_anims(first, clip)) {
+        return None;
+    }
+
+    let poses: Vec<BaseFrame> = clips.iter()
+        .flat_map(|clip| (0..clip.frames.len()).map(move |f| frame_pose(clip, f)))
+        .collect();
+    let encoded = encode_anim(&first.hierarchies, &first.base_frame, &poses);
+
+    // Bounds are per frame: a clip without them would shift those of the
+    // clips after it.
+    let bounds = if clips.iter().all(|clip| clip.bounds.len() == clip.frames.len()) {
+        clips.iter().flat_map(|clip| clip.bounds.iter().cloned()).collect()
+    } else {
+        Vec::new()
+    };
+
+    Some(Md5Anim {
+        version: first.version,
+        command_line: first.command_line.clone(),
+        frame_rate: first.frame_rate,
+        bounds,
+        .. encoded
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use animation::pose::frame_pose;
+    use test_support::bob_anim;
+    use super::ClipRange;
+
+    #[test]
+    fn extract_range() {
+        let anim = bob_anim();
+        let clip = super::extract_range(&anim, 10, 19).unwrap();
+        assert_eq!(clip.num_frames, 10);
+        assert_eq!(clip.frames[0].frame_number, 0);
+        assert_eq!(clip.frames[0].frame_data, anim.frames[10].frame_data);
+        assert_eq!(clip.bounds[9], anim.bounds[19]);
+
+        assert_eq!(super::extract_range(&anim, 20, 10), None);
+        assert_eq!(super::extract_range(&anim, 0, 141), None);
+    }
+
+    #[test]
+    fn split_and_concatenate() {
+        let anim = bob_anim();
+        let ranges = vec![
+            ClipRange { name: String::from("start"), start: 0, end: 69 },
+            ClipRange { name: String::from("end"), start: 70, end: 140 }
+        ];
+        let clips = super::split_anim(&anim, &ranges).unwrap();
+        assert_eq!(clips[1].name, "end");
+        assert_eq!(clips[1].anim.num_frames, 71);
+
+        let joined = super::concatenate_anims(&[&clips[0].anim, &clips[1].anim]).unwrap();
+        assert_eq!(joined.num_frames, 141);
+        assert_eq!(joined.frames[140].frame_number, 140);
+        assert_eq!(joined.bounds, anim.bounds);
+        assert_eq!(frame_pose(&joined, 100), frame_pose(&anim, 100));
+
+        let mut unbounded = clips[0].anim.clone();
+        unbounded.bounds.clear();
+        let joined = super::concatenate_anims(&[&unbounded, &clips[1].anim]).unwrap();
+        assert_eq!(joined.num_frames, 141);
+        assert!(joined.bounds.is_empty());
+    }
+}
@@ -0,0 +1,417 @@
+use std::mem;
+use cgmath::{Vector3, Quaternion, InnerSpace, Rad};
+use md5::md5anim::{Md5Anim, BaseFrame};
+use animation::pose::{frame_pose, complete_quaternion, canonical_orientation, interpolate_orientation,
+                      encode_anim};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CompressionSettings {
+    /// Largest distance, in model units, a reconstructed joint position may
+    /// be from the original one.
+    pub position_tolerance: f32,
+    /// Largest angle between a reconstructed joint orientation and the
+    /// original one.
+    pub angle_tolerance: Rad<f32>,
+    /// Stores orientation keys as 16 bit integers instead of floats.
+    pub quantize_orientations: bool,
+}
+
+impl Default for CompressionSettings {
+    fn default() -> CompressionSettings {
+        CompressionSettings {
+            position_tolerance: 1e-3,
+            angle_tolerance: Rad(1e-3),
+            quantize_orientations: false
+        }
+    }
+}
+
+/// x, y and z of a canonical orientation, mapped from [-1, 1] to the whole
+/// i16 range.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct QuantizedOrientation(pub [i16; 3]);
+
+impl QuantizedOrientation {
+    pub fn new(q: Quaternion<f32>) -> QuantizedOrientation {
+        let q = canonical_orientation(q.normalize());
+        let quantize = |c: f32| (c.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+        QuantizedOrientation([quantize(q.v.x), quantize(q.v.y), quantize(q.v.z)])
+    }
+
+    pub fn orientation(&self) -> Quaternion<f32> {
+        let c = |i: usize| self.0[i] as f32 / i16::MAX as f32;
+        complete_quaternion(c(0), c(1), c(2)).normalize()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum OrientationKeys {
+    Full(Vec<(u32, Quaternion<f32>)>),
+    Quantized(Vec<(u32, QuantizedOrientation)>),
+}
+
+impl OrientationKeys {
+    pub fn len(&self) -> usize {
+        match *self {
+            OrientationKeys::Full(ref keys) => keys.len(),
+            OrientationKeys::Quantized(ref keys) => keys.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Frame number and orientation of key `index`.
+    pub fn key(&self, index: usize) -> (u32, Quaternion<f32>) {
+        match *self {
+            OrientationKeys::Full(ref keys) => keys[index],
+            OrientationKeys::Quantized(ref keys) => (keys[index].0, keys[index].1.orientation()),
+        }
+    }
+}
+
+/// Keys of the parent-relative transform of one joint. Empty tracks keep the
+/// base frame value, frames between two keys are interpolated.
+#[derive(Clone, PartialEq, Debug)]
+pub struct JointTrack {
+    pub positions: Vec<(u32, Vector3<f32>)>,
+    pub orientations: OrientationKeys,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct CompressedAnim {
+    /// Header, hierarchy, bounds and base frame of the clip, with the flags
+    /// of constant components cleared. `frames` is empty.
+    pub anim: Md5Anim,
+    /// One track per joint of the hierarchy.
+    pub tracks: Vec<JointTrack>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CompressionReport {
+    /// Bytes of animated data in the original frames.
+    pub original_size: usize,
+    /// Bytes of animated data in the tracks, frame numbers included.
+    pub compressed_size: usize,
+    /// Largest distance between a joint position of the original clip and
+    /// the decompressed one, relative to the parent joint.
+    pub max_position_error: f32,
+    /// Largest angle between a joint orientation of the original clip and
+    /// the decompressed one, relative to the parent joint.
+    pub max_angle_error: Rad<f32>,
+}
+
+impl CompressionReport {
+    pub fn ratio(&self) -> f32 {
+        if self.original_size == 0 { 1.0 } else { self.compressed_size as f32 / self.original_size as f32 }
+    }
+}
+
+/// Angle of the rotation from `a` to `b`. Measured with atan2, acos being
+/// too coarse near 1 in single precision for small tolerances.
+pub fn angle_between(a: Quaternion<f32>, b: Quaternion<f32>) -> Rad<f32> {
+    let delta = a.normalize().conjugate() * b.normalize();
+    Rad(2.0 * delta.v.magnitude().atan2(delta.s.abs()))
+}
+
+/// Moves components which stay within tolerance over the whole clip into
+/// the base frame and clears their flag bits. The clip keeps its frames, as
+/// an `Md5Anim` with fewer animated components.
+pub fn strip_constant_components(anim: &Md5Anim, settings: &CompressionSettings) -> Md5Anim {
+    let mut poses: Vec<BaseFrame> = (0..anim.frames.len()).map(|f| frame_pose(anim, f)).collect();
+    let mut base_frame = anim.base_frame.clone();
+
+    if let Some(first) = poses.first().cloned() {
+        for i in 0..anim.hierarchies.len() {
+            for c in 0..3 {
+                let (min, max) = poses.iter().fold((first.position[i][c], first.position[i][c]), |(min, max), p| {
+                    (min.min(p.position[i][c]), max.max(p.position[i][c]))
+                });
+                if max - min <= settings.position_tolerance {
+                    let value = if max == min { max } else { (min + max) * 0.5 };
+                    base_frame.position[i][c] = value;
+                    for pose in &mut poses {
+                        pose.position[i][c] = value;
+                    }
+                }
+            }
+
+            // A change of d in one of the stored components turns the
+            // orientation by less than 2 d.
+            for c in 0..3 {
+                let component = |q: Quaternion<f32>| canonical_orientation(q).v[c];
+                let (min, max) = poses.iter().fold((component(first.orientation[i]), component(first.orientation[i])),
+                                                   |(min, max), p| {
+                    (min.min(component(p.orientation[i])), max.max(component(p.orientation[i])))
+                });
+                if max - min <= settings.angle_tolerance.0 * 0.5 {
+                    let value = if max == min { max } else { (min + max) * 0.5 };
+                    let set = |q: Quaternion<f32>| {
+                        let mut v = canonical_orientation(q).v;
+                        v[c] = value;
+                        complete_quaternion(v.x, v.y, v.z)
+                    };
+                    base_frame.orientation[i] = set(base_frame.orientation[i]);
+                    for pose in &mut poses {
+                        pose.orientation[i] = set(pose.orientation[i]);
+                    }
+                }
+            }
+        }
+    }
+
+    let encoded = encode_anim(&anim.hierarchies, &base_frame, &poses);
+    Md5Anim {
+        version: anim.version,
+        command_line: anim.command_line.clone(),
+        frame_rate: anim.frame_rate,
+        bounds: anim.bounds.clone(),
+        .. encoded
+    }
+}
+
+/// Indices of the samples to keep so that linearly interpolating between
+/// them stays within tolerance of the `targets` they replace. The first and
+/// last samples are always kept.
+fn reduce_keys<T, F, E>(samples: &[T], targets: &[T], interpolate: F, within_tolerance: E) -> Vec<usize>
+    where F: Fn(&T, &T, f32) -> T, E: Fn(&T, &T) -> bool {
+    if samples.len() < 2 {
+        return (0..samples.len()).collect();
+    }
+
+    let last = samples.len() - 1;
+    let mut keys = vec![0];
+    let mut start = 0;
+
+    while start < last {
+        // Extend the segment as far as every skipped sample is reconstructed.
+        let mut end = start + 1;
+        while end < last {
+            let candidate = end + 1;
+            let fits = (start + 1..candidate).all(|k| {
+                let t = (k - start) as f32 / (candidate - start) as f32;
+                within_tolerance(&interpolate(&samples[start], &samples[candidate], t), &targets[k])
+            });
+            if !fits {
+                break;
+            }
+            end = candidate;
+        }
+        keys.push(end);
+        start = end;
+    }
+
+    keys
+}
+
+/// Compresses `anim`: constant components are stripped, then for every
+/// animated joint, keys that interpolation reconstructs within tolerance are
+/// dropped. Orientations are quantized first when requested, and dropped
+/// keys are checked against the original clip, so that the tolerances hold
+/// for the combined error.
+///
+/// Quantization keeps the x, y, z components the format stores, so it loses
+/// precision on rotations close to a half turn, where w is small: check the
+/// report.
+pub fn compress_anim(anim: &Md5Anim, settings: &CompressionSettings) -> (CompressedAnim, CompressionReport) {
+    let stripped = strip_constant_components(anim, settings);
+    let originals: Vec<BaseFrame> = (0..anim.frames.len()).map(|f| frame_pose(anim, f)).collect();
+    let poses: Vec<BaseFrame> = (0..stripped.frames.len()).map(|f| frame_pose(&stripped, f)).collect();
+
+    let tracks: Vec<JointTrack> = stripped.hierarchies.iter().enumerate().map(|(i, joint)| {
+        let positions = if joint.flag & 7 != 0 {
+            let samples: Vec<Vector3<f32>> = poses.iter().map(|p| p.position[i]).collect();
+            let targets: Vec<Vector3<f32>> = originals.iter().map(|p| p.position[i]).collect();
+            reduce_keys(&samples, &targets,
+                        |a, b, t| a + (b - a) * t,
+                        |a, b| (a - b).magnitude() <= settings.position_tolerance)
+                .into_iter()
+                .map(|k| (k as u32, samples[k]))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let orientations = if joint.flag & 56 != 0 {
+            let samples: Vec<Quaternion<f32>> = poses.iter().map(|p| {
+                if settings.quantize_orientations {
+                    QuantizedOrientation::new(p.orientation[i]).orientation()
+                } else {
+                    p.orientation[i]
+                }
+            }).collect();
+            let targets: Vec<Quaternion<f32>> = originals.iter().map(|p| p.orientation[i]).collect();
+            let keys = reduce_keys(&samples, &targets,
+                                   |a, b, t| interpolate_orientation(*a, *b, t),
+                                   |a, b| angle_between(*a, *b) <= settings.angle_tolerance);
+
+            if settings.quantize_orientations {
+                OrientationKeys::Quantized(keys.into_iter()
+                    .map(|k| (k as u32, QuantizedOrientation::new(samples[k])))
+                    .collect())
+            } else {
+                OrientationKeys::Full(keys.into_iter().map(|k| (k as u32, samples[k])).collect())
+            }
+        } else {
+            OrientationKeys::Full(Vec::new())
+        };
+
+        JointTrack { positions, orientations }
+    }).collect();
+
+    let compressed = CompressedAnim {
+        anim: Md5Anim { frames: Vec::new(), .. stripped },
+        tracks
+    };
+
+    let mut max_position_error: f32 = 0.0;
+    let mut max_angle_error = Rad(0.0);
+    for f in 0..anim.frames.len() {
+        let original = frame_pose(anim, f);
+        let decompressed = compressed_pose(&compressed, f);
+        for i in 0..anim.hierarchies.len() {
+            max_position_error = max_position_error.max((original.position[i] - decompressed.position[i]).magnitude());
+            let angle = angle_between(original.orientation[i], decompressed.orientation[i]);
+            if angle > max_angle_error {
+                max_angle_error = angle;
+            }
+        }
+    }
+
+    let frame_number_size = mem::size_of::<u32>();
+    let orientation_size = if settings.quantize_orientations {
+        mem::size_of::<QuantizedOrientation>()
+    } else {
+        3 * mem::size_of::<f32>()
+    };
+    let compressed_size = compressed.tracks.iter().map(|t| {
+        t.positions.len() * (frame_number_size + 3 * mem::size_of::<f32>())
+            + t.orientations.len() * (frame_number_size + orientation_size)
+    }).sum();
+
+    let report = CompressionReport {
+        original_size: anim.frames.iter().map(|f| f.frame_data.len() * mem::size_of::<f32>()).sum(),
+        compressed_size,
+        max_position_error,
+        max_angle_error
+    };
+
+    (compressed, report)
+}
+
+/// Index of the key at or before `frame` and the blend factor towards the
+/// next key.
+fn surrounding_keys<F: Fn(usize) -> u32>(count: usize, frame_number: F, frame: u32) -> (usize, usize, f32) {
+    let next = (0..count).position(|k| frame_number(k) > frame).unwrap_or(count);
+    if next == 0 {
+        (0, 0, 0.0)
+    } else if next == count {
+        (count - 1, count - 1, 0.0)
+    } else {
+        let (a, b) = (frame_number(next - 1), frame_number(next));
+        (next - 1, next, (frame - a) as f32 / (b - a) as f32)
+    }
+}
+
+/// Parent-relative pose of frame `frame_index`, interpolated from the keys.
+pub fn compressed_pose(compressed: &CompressedAnim, frame_index: usize) -> BaseFrame {
+    let frame = frame_index as u32;
+    let mut pose = compressed.anim.base_frame.clone();
+
+    for (i, track) in compressed.tracks.iter().enumerate() {
+        if !track.positions.is_empty() {
+            let (a, b, t) = surrounding_keys(track.positions.len(), |k| track.positions[k].0, frame);
+            let (pa, pb) = (track.positions[a].1, track.positions[b].1);
+            pose.position[i] = pa + (pb - pa) * t;
+        }
+        if !track.orientations.is_empty() {
+            let (a, b, t) = surrounding_keys(track.orientations.len(), |k| track.orientations.key(k).0, frame);
+            pose.orientation[i] = interpolate_orientation(track.orientations.key(a).1, track.orientations.key(b).1, t);
+        }
+    }
+
+    pose
+}
+
+/// Expands the keys back into a clip with every frame stored.
+pub fn decompress_anim(compressed: &CompressedAnim) -> Md5Anim {
+    let anim = &compressed.anim;
+    let poses: Vec<BaseFrame> = (0..anim.num_frames as usize).map(|f| compressed_pose(compressed, f)).collect();
+    let encoded = encode_anim(&anim.hierarchies, &anim.base_frame, &poses);
+
+    Md5Anim {
+        version: anim.version,
+        command_line: anim.command_line.clone(),
+        frame_rate: anim.frame_rate,
+        bounds: anim.bounds.clone(),
+        .. encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Rad};
+    use md5::md5anim::{Md5Anim, Joint as AnimJoint, BaseFrame};
+    use animation::pose::{complete_quaternion, encode_anim, frame_pose};
+    use super::{CompressionSettings, angle_between};
+
+    fn slide() -> Md5Anim {
+        let hierarchy = vec![
+            AnimJoint { name: String::from("root"), index: -1, flag: 0, start_index: 0 },
+            AnimJoint { name: String::from("arm"), index: 0, flag: 0, start_index: 0 }
+        ];
+        // Root moving at constant speed along x, barely shaking along y, arm
+        // turning then stopping.
+        let poses: Vec<BaseFrame> = (0..20).map(|f| {
+            let angle = (f.min(10) as f32) * 0.05;
+            BaseFrame {
+                position: vec![Vector3::new(f as f32 * 0.5, if f % 2 == 0 { 1e-5 } else { 0.0 }, 1.0),
+                               Vector3::new(1.0, 0.0, 0.0)],
+                orientation: vec![complete_quaternion(0.0, 0.0, 0.0),
+                                  complete_quaternion(0.0, 0.0, (angle * 0.5).sin())]
+            }
+        }).collect();
+        encode_anim(&hierarchy, &poses[0], &poses)
+    }
+
+    #[test]
+    fn strip_constant_components() {
+        let anim = slide();
+        assert_eq!(anim.hierarchies[0].flag, 3);
+
+        let stripped = super::strip_constant_components(&anim, &CompressionSettings::default());
+        assert_eq!(stripped.hierarchies[0].flag, 1);
+        assert_eq!(stripped.hierarchies[1].flag, 32);
+        assert_eq!(stripped.num_animated_components, 2);
+        assert_eq!(stripped.frames.len(), 20);
+    }
+
+    #[test]
+    fn compress_anim() {
+        let anim = slide();
+        let settings = CompressionSettings::default();
+        let (compressed, report) = super::compress_anim(&anim, &settings);
+
+        assert_eq!(compressed.tracks[0].positions.len(), 2);
+        assert_eq!(compressed.tracks[1].orientations.len(), 3);
+        assert!(report.compressed_size < report.original_size);
+        assert!(report.max_position_error <= settings.position_tolerance);
+        assert!(report.max_angle_error <= settings.angle_tolerance);
+
+        let decompressed = super::decompress_anim(&compressed);
+        assert_eq!(decompressed.frames.len(), 20);
+        let (a, b) = (frame_pose(&anim, 15), frame_pose(&decompressed, 15));
+        assert!(angle_between(a.orientation[1], b.orientation[1]) <= settings.angle_tolerance);
+    }
+
+    #[test]
+    fn quantize_orientations() {
+        let settings = CompressionSettings { quantize_orientations: true, angle_tolerance: Rad(1e-2), .. CompressionSettings::default() };
+        let (_, full) = super::compress_anim(&slide(), &CompressionSettings { quantize_orientations: false, .. settings });
+        let (_, quantized) = super::compress_anim(&slide(), &settings);
+
+        assert!(quantized.compressed_size < full.compressed_size);
+        assert!(quantized.max_angle_error <= settings.angle_tolerance);
+    }
+}
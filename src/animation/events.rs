@@ -0,0 +1,134 @@
+use md5::md5anim::Md5Anim;
+use animation::pose::anim_duration;
+
+/// Named cue, such as a footstep or a sound, fired when playback reaches
+/// `frame`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AnimEvent {
+    pub name: String,
+    pub frame: u32,
+}
+
+/// Events of one clip, sorted by frame.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct EventTrack {
+    pub events: Vec<AnimEvent>,
+}
+
+impl EventTrack {
+    pub fn new(mut events: Vec<AnimEvent>) -> EventTrack {
+        events.sort_by_key(|e| e.frame);
+        EventTrack { events }
+    }
+
+    /// Events crossed when playback of `anim` goes from `from` to `to`
+    /// seconds, in the order they are met.
+    ///
+    /// Times are playback times, which keep growing (or decreasing, in
+    /// reverse) across loops: a looping clip wraps after its last frame,
+    /// back to the first one, every `frames.len() / frame_rate` seconds. An
+    /// event is reported when reached, not when left, so an event at `to` is
+    /// reported and one at `from` is not. Non looping clips are clamped to
+    /// their duration.
+    pub fn crossed(&self, anim: &Md5Anim, from: f32, to: f32, looping: bool) -> Vec<&AnimEvent> {
+        if anim.frame_rate <= 0 || anim.frames.is_empty() || from == to || !from.is_finite() || !to.is_finite() {
+            return Vec::new();
+        }
+
+        let rate = anim.frame_rate as f32;
+        let time = |event: &AnimEvent| event.frame as f32 / rate;
+        let forward = to > from;
+
+        if !looping {
+            let duration = anim_duration(anim);
+            let (from, to) = (from.max(0.0).min(duration), to.max(0.0).min(duration));
+            let mut crossed: Vec<&AnimEvent> = self.events.iter().filter(|e| {
+                let t = time(e);
+                if forward { t > from && t <= to } else { t >= to && t < from }
+            }).collect();
+            if !forward {
+                crossed.reverse();
+            }
+            return crossed;
+        }
+
+        let period = anim.frames.len() as f32 / rate;
+        let (first_loop, last_loop) = ((from / period).floor() as i64, (to / period).floor() as i64);
+        let mut crossed = Vec::new();
+
+        if forward {
+            for k in first_loop..last_loop + 1 {
+                for event in &self.events {
+                    let t = time(event) + k as f32 * period;
+                    if t > from && t <= to {
+                        crossed.push(event);
+                    }
+                }
+            }
+        } else {
+            for k in (last_loop..first_loop + 1).rev() {
+                for event in self.events.iter().rev() {
+                    let t = time(event) + k as f32 * period;
+                    if t >= to && t < from {
+                        crossed.push(event);
+                    }
+                }
+            }
+        }
+
+        crossed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use md5::md5anim::{Md5Anim, Joint as AnimJoint, BaseFrame};
+    use cgmath::Vector3;
+    use animation::pose::{complete_quaternion, encode_anim};
+    use super::{AnimEvent, EventTrack};
+
+    // Ten frames at 10 fps: a loop lasts one second.
+    fn clip() -> Md5Anim {
+        let hierarchy = vec![AnimJoint { name: String::from("root"), index: -1, flag: 0, start_index: 0 }];
+        let poses: Vec<BaseFrame> = (0..10).map(|f| BaseFrame {
+            position: vec![Vector3::new(f as f32, 0.0, 0.0)],
+            orientation: vec![complete_quaternion(0.0, 0.0, 0.0)]
+        }).collect();
+        let anim = encode_anim(&hierarchy, &poses[0], &poses);
+        Md5Anim { frame_rate: 10, .. anim }
+    }
+
+    fn track() -> EventTrack {
+        EventTrack::new(vec![
+            AnimEvent { name: String::from("right"), frame: 7 },
+            AnimEvent { name: String::from("left"), frame: 2 }
+        ])
+    }
+
+    fn names(events: Vec<&AnimEvent>) -> Vec<&str> {
+        events.into_iter().map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn crossed() {
+        let (anim, track) = (clip(), track());
+        assert_eq!(names(track.crossed(&anim, 0.0, 0.2, false)), vec!["left"]);
+        assert_eq!(names(track.crossed(&anim, 0.2, 0.5, false)), Vec::<&str>::new());
+        assert_eq!(names(track.crossed(&anim, 0.1, 5.0, false)), vec!["left", "right"]);
+    }
+
+    #[test]
+    fn crossed_looping() {
+        let (anim, track) = (clip(), track());
+        assert_eq!(names(track.crossed(&anim, 0.8, 1.3, true)), vec!["left"]);
+        assert_eq!(names(track.crossed(&anim, 0.5, 2.75, true)), vec!["right", "left", "right", "left", "right"]);
+    }
+
+    #[test]
+    fn crossed_reverse() {
+        let (anim, track) = (clip(), track());
+        assert_eq!(names(track.crossed(&anim, 0.8, 0.1, false)), vec!["right", "left"]);
+        assert_eq!(names(track.crossed(&anim, 1.3, 0.6, true)), vec!["left", "right"]);
+        assert_eq!(names(track.crossed(&anim, 0.1, -0.4, true)), vec!["right"]);
+    }
+}
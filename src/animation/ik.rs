@@ -0,0 +1,379 @@
+use cgmath::{Vector3, InnerSpace, Rad};
+use md5::md5mesh::Joint;
+use animation::skeleton::{joint_chain, rotate_joint, rotation_between, interpolate_skeletons};
+
+const EPSILON: f32 = 1e-6;
+
+/// Range allowed for the bend of a chain joint: the angle between the bone
+/// coming into the joint and the bone leaving it, zero being straight.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AngleLimit {
+    pub min: Rad<f32>,
+    pub max: Rad<f32>,
+}
+
+/// Skeleton indices of the joints of an IK chain, from its root to its end
+/// effector, along with an optional bend limit for each of them.
+#[derive(Clone, PartialEq, Debug)]
+pub struct IkChain {
+    pub joints: Vec<usize>,
+    pub limits: Vec<Option<AngleLimit>>,
+}
+
+impl IkChain {
+    /// Builds a chain from joint names such as `["thigh.L", "shin.L", "ankle.L"]`.
+    pub fn from_names(skeleton: &[Joint], names: &[&str]) -> Option<IkChain> {
+        joint_chain(skeleton, names).map(|joints| {
+            let count = joints.len();
+            IkChain { joints, limits: vec![None; count] }
+        })
+    }
+
+    /// Limits the bend of the joint at `position` in the chain. The bend of
+    /// the chain root is measured against its parent bone in the skeleton.
+    pub fn with_limit(mut self, position: usize, limit: AngleLimit) -> IkChain {
+        self.limits[position] = Some(limit);
+        self
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct IkSettings {
+    /// Blend between the input pose (0) and the solved pose (1).
+    pub weight: f32,
+    /// Maximum number of passes of the iterative solvers.
+    pub iterations: u32,
+    /// Distance to the target under which a solve is considered reached.
+    pub tolerance: f32,
+}
+
+impl Default for IkSettings {
+    fn default() -> IkSettings {
+        IkSettings { weight: 1.0, iterations: 16, tolerance: 1e-3 }
+    }
+}
+
+/// Analytic solver for a three joint chain (e.g. thigh, shin, ankle). The
+/// middle joint bends in the plane containing the chain root, the target and
+/// the pole vector, towards the pole.
+///
+/// Returns whether the end effector reached the target.
+pub fn solve_two_bone(skeleton: &mut [Joint], chain: &IkChain, target: Vector3<f32>,
+                      pole: Vector3<f32>, settings: &IkSettings) -> bool {
+    if chain.joints.len() != 3 {
+        return false;
+    }
+
+    let original = skeleton.to_vec();
+    let root = skeleton[chain.joints[0]].position;
+    let mid = skeleton[chain.joints[1]].position;
+    let end = skeleton[chain.joints[2]].position;
+
+    let a = (mid - root).magnitude();
+    let b = (end - mid).magnitude();
+    let to_target = target - root;
+    let distance = to_target.magnitude();
+
+    let mut min_distance = (a - b).abs();
+    let mut max_distance = a + b;
+    if let Some(limit) = chain.limits[1] {
+        // Law of cosines on the bend of the middle joint.
+        let reach = |bend: Rad<f32>| (a * a + b * b + 2.0 * a * b * bend.0.cos()).max(0.0).sqrt();
+        min_distance = min_distance.max(reach(limit.max));
+        max_distance = max_distance.min(reach(limit.min));
+    }
+    let reached_distance = distance.max(min_distance).min(max_distance);
+
+    let direction =
+        if distance > EPSILON { to_target / distance } else { safe_normalize(end - root) };
+
+    let mut side = reject(pole - root, direction);
+    if side.magnitude2() < EPSILON {
+        side = reject(mid - root, direction);
+    }
+    let side = if side.magnitude2() < EPSILON { perpendicular(direction) } else { side.normalize() };
+
+    let cos_root =
+        if a > EPSILON && reached_distance > EPSILON {
+            ((a * a + reached_distance * reached_distance - b * b) / (2.0 * a * reached_distance)).clamp(-1.0, 1.0)
+        } else {
+            1.0
+        };
+    let sin_root = (1.0 - cos_root * cos_root).sqrt();
+
+    let positions = [root,
+                     root + (direction * cos_root + side * sin_root) * a,
+                     root + direction * reached_distance];
+    apply_positions(skeleton, &chain.joints, &positions);
+
+    let reached = (skeleton[chain.joints[2]].position - target).magnitude() <= settings.tolerance;
+    blend(skeleton, &original, settings.weight);
+    reached
+}
+
+/// Cyclic coordinate descent: rotates each joint, from the end of the chain
+/// to its root, so that the end effector points towards the target.
+pub fn solve_ccd(skeleton: &mut [Joint], chain: &IkChain, target: Vector3<f32>,
+                 settings: &IkSettings) -> bool {
+    let count = chain.joints.len();
+    if count < 2 {
+        return false;
+    }
+
+    let original = skeleton.to_vec();
+    let end = chain.joints[count - 1];
+
+    for _ in 0..settings.iterations {
+        if (skeleton[end].position - target).magnitude() <= settings.tolerance {
+            break;
+        }
+
+        for i in (0..count - 1).rev() {
+            let joint = chain.joints[i];
+            let pivot = skeleton[joint].position;
+            let rotation = rotation_between(skeleton[end].position - pivot, target - pivot);
+            rotate_joint(skeleton, joint, rotation);
+
+            if let Some(limit) = chain.limits[i] {
+                if let Some(incoming) = incoming_bone(skeleton, chain, i) {
+                    let outgoing = skeleton[chain.joints[i + 1]].position - pivot;
+                    let clamped = clamp_bend(incoming, outgoing, &limit);
+                    rotate_joint(skeleton, joint, rotation_between(outgoing, clamped));
+                }
+            }
+        }
+    }
+
+    let reached = (skeleton[end].position - target).magnitude() <= settings.tolerance;
+    blend(skeleton, &original, settings.weight);
+    reached
+}
+
+/// Forward and backward reaching IK: alternately drags the chain from its
+/// end to the target and back to its root, keeping bone lengths.
+pub fn solve_fabrik(skeleton: &mut [Joint], chain: &IkChain, target: Vector3<f32>,
+                    settings: &IkSettings) -> bool {
+    let count = chain.joints.len();
+    if count < 2 {
+        return false;
+    }
+
+    let original = skeleton.to_vec();
+    let mut positions: Vec<Vector3<f32>> =
+        chain.joints.iter().map(|&j| skeleton[j].position).collect();
+    let lengths: Vec<f32> =
+        positions.windows(2).map(|p| (p[1] - p[0]).magnitude()).collect();
+    let root = positions[0];
+    let root_incoming = incoming_bone(skeleton, chain, 0);
+
+    let total: f32 = lengths.iter().sum();
+    if (target - root).magnitude() >= total {
+        // Out of reach: a single forward pass stretches the chain towards
+        // the target.
+        for p in positions.iter_mut().skip(1) {
+            *p = target;
+        }
+        forward_pass(&mut positions, &lengths, &chain.limits, root_incoming);
+    } else {
+        for _ in 0..settings.iterations {
+            if (positions[count - 1] - target).magnitude() <= settings.tolerance {
+                break;
+            }
+
+            positions[count - 1] = target;
+            for i in (0..count - 1).rev() {
+                let direction = safe_normalize(positions[i] - positions[i + 1]);
+                positions[i] = positions[i + 1] + direction * lengths[i];
+            }
+
+            positions[0] = root;
+            forward_pass(&mut positions, &lengths, &chain.limits, root_incoming);
+        }
+    }
+
+    apply_positions(skeleton, &chain.joints, &positions);
+
+    let reached = (skeleton[chain.joints[count - 1]].position - target).magnitude() <= settings.tolerance;
+    blend(skeleton, &original, settings.weight);
+    reached
+}
+
+fn forward_pass(positions: &mut [Vector3<f32>], lengths: &[f32], limits: &[Option<AngleLimit>],
+                root_incoming: Option<Vector3<f32>>) {
+    for i in 1..positions.len() {
+        let mut direction = positions[i] - positions[i - 1];
+
+        if let Some(limit) = limits[i - 1] {
+            let incoming = if i >= 2 { Some(positions[i - 1] - positions[i - 2]) } else { root_incoming };
+            if let Some(incoming) = incoming {
+                direction = clamp_bend(incoming, direction, &limit);
+            }
+        }
+
+        positions[i] = positions[i - 1] + safe_normalize(direction) * lengths[i - 1];
+    }
+}
+
+/// Rotates the chain joints one after the other so that each of them ends up
+/// at the matching solved position.
+fn apply_positions(skeleton: &mut [Joint], chain: &[usize], positions: &[Vector3<f32>]) {
+    for i in 0..chain.len() - 1 {
+        let pivot = skeleton[chain[i]].position;
+        let current = skeleton[chain[i + 1]].position - pivot;
+        rotate_joint(skeleton, chain[i], rotation_between(current, positions[i + 1] - pivot));
+    }
+}
+
+fn blend(skeleton: &mut [Joint], original: &[Joint], weight: f32) {
+    if weight >= 1.0 {
+        return;
+    }
+    let blended = interpolate_skeletons(original, skeleton, weight.max(0.0));
+    skeleton.clone_from_slice(&blended);
+}
+
+/// Bone ending at the joint at `position` in the chain, taken from the
+/// skeleton parent for the chain root.
+fn incoming_bone(skeleton: &[Joint], chain: &IkChain, position: usize) -> Option<Vector3<f32>> {
+    let joint = &skeleton[chain.joints[position]];
+    let bone =
+        if position > 0 {
+            joint.position - skeleton[chain.joints[position - 1]].position
+        } else if joint.parent_index >= 0 {
+            joint.position - skeleton[joint.parent_index as usize].position
+        } else {
+            return None;
+        };
+
+    if bone.magnitude2() < EPSILON { None } else { Some(bone) }
+}
+
+/// Rotates `outgoing` in its bend plane so that its angle with `incoming`
+/// stays within `limit`, keeping its length.
+fn clamp_bend(incoming: Vector3<f32>, outgoing: Vector3<f32>, limit: &AngleLimit) -> Vector3<f32> {
+    let length = outgoing.magnitude();
+    if incoming.magnitude2() < EPSILON || length < EPSILON {
+        return outgoing;
+    }
+
+    let axis = incoming.normalize();
+    let direction = outgoing / length;
+    let angle = axis.dot(direction).clamp(-1.0, 1.0).acos();
+    let clamped = angle.max(limit.min.0).min(limit.max.0);
+    if (clamped - angle).abs() < EPSILON {
+        return outgoing;
+    }
+
+    let side = reject(direction, axis);
+    let side = if side.magnitude2() < EPSILON { perpendicular(axis) } else { side.normalize() };
+    (axis * clamped.cos() + side * clamped.sin()) * length
+}
+
+/// Component of `v` orthogonal to the unit vector `axis`.
+fn reject(v: Vector3<f32>, axis: Vector3<f32>) -> Vector3<f32> {
+    v - axis * axis.dot(v)
+}
+
+fn perpendicular(v: Vector3<f32>) -> Vector3<f32> {
+    let other = if v.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+    v.cross(other).normalize()
+}
+
+fn safe_normalize(v: Vector3<f32>) -> Vector3<f32> {
+    if v.magnitude2() < EPSILON { Vector3::unit_z() } else { v.normalize() }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Quaternion, InnerSpace, Rad};
+    use md5::md5mesh::Joint;
+    use std::f32::consts::PI;
+    use super::{IkChain, IkSettings, AngleLimit};
+
+    fn straight_chain(count: usize) -> Vec<Joint> {
+        (0..count).map(|i| Joint {
+            name: format!("bone{}", i),
+            parent_index: i as i32 - 1,
+            position: Vector3::new(0.0, 0.0, i as f32),
+            orientation: Quaternion::new(1.0, 0.0, 0.0, 0.0)
+        }).collect()
+    }
+
+    fn lengths(skeleton: &[Joint]) -> Vec<f32> {
+        skeleton.windows(2).map(|j| (j[1].position - j[0].position).magnitude()).collect()
+    }
+
+    #[test]
+    fn from_names() {
+        let skeleton = straight_chain(3);
+        let chain = IkChain::from_names(&skeleton, &["bone0", "bone1", "bone2"]).unwrap();
+        assert_eq!(chain.joints, vec![0, 1, 2]);
+        assert!(IkChain::from_names(&skeleton, &["bone2", "bone1"]).is_none());
+        assert!(IkChain::from_names(&skeleton, &["bone0", "missing"]).is_none());
+    }
+
+    #[test]
+    fn solve_two_bone() {
+        let mut skeleton = straight_chain(3);
+        let chain = IkChain::from_names(&skeleton, &["bone0", "bone1", "bone2"]).unwrap();
+        let target = Vector3::new(1.0, 0.0, 1.0);
+
+        assert!(super::solve_two_bone(&mut skeleton, &chain, target, Vector3::new(0.0, 5.0, 0.0), &IkSettings::default()));
+        assert!((skeleton[2].position - target).magnitude() < 1e-4);
+        assert!(skeleton[1].position.y > 0.5);
+        for l in lengths(&skeleton) {
+            assert!((l - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn solve_two_bone_limit() {
+        let mut skeleton = straight_chain(3);
+        let chain = IkChain::from_names(&skeleton, &["bone0", "bone1", "bone2"]).unwrap()
+            .with_limit(1, AngleLimit { min: Rad(0.0), max: Rad(PI / 4.0) });
+        let target = Vector3::new(0.0, 0.0, 0.5);
+
+        assert!(!super::solve_two_bone(&mut skeleton, &chain, target, Vector3::new(0.0, 1.0, 0.0), &IkSettings::default()));
+        let incoming = (skeleton[1].position - skeleton[0].position).normalize();
+        let outgoing = (skeleton[2].position - skeleton[1].position).normalize();
+        assert!(incoming.dot(outgoing).acos() <= PI / 4.0 + 1e-3);
+    }
+
+    #[test]
+    fn solve_ccd() {
+        let mut skeleton = straight_chain(4);
+        let chain = IkChain::from_names(&skeleton, &["bone0", "bone1", "bone2", "bone3"]).unwrap();
+        let target = Vector3::new(1.0, 0.5, 1.5);
+
+        assert!(super::solve_ccd(&mut skeleton, &chain, target, &IkSettings { iterations: 64, .. IkSettings::default() }));
+        for l in lengths(&skeleton) {
+            assert!((l - 1.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn solve_fabrik() {
+        let mut skeleton = straight_chain(4);
+        let chain = IkChain::from_names(&skeleton, &["bone0", "bone1", "bone2", "bone3"]).unwrap();
+        let target = Vector3::new(1.0, 0.5, 1.5);
+
+        assert!(super::solve_fabrik(&mut skeleton, &chain, target, &IkSettings::default()));
+        assert!((skeleton[3].position - target).magnitude() < 1e-3);
+        for l in lengths(&skeleton) {
+            assert!((l - 1.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn zero_weight() {
+        let mut skeleton = straight_chain(4);
+        let original = skeleton.clone();
+        let chain = IkChain::from_names(&skeleton, &["bone0", "bone1", "bone2", "bone3"]).unwrap();
+        let settings = IkSettings { weight: 0.0, .. IkSettings::default() };
+
+        super::solve_fabrik(&mut skeleton, &chain, Vector3::new(1.0, 0.5, 1.5), &settings);
+        for (a, b) in skeleton.iter().zip(&original) {
+            assert!((a.position - b.position).magnitude() < 1e-5);
+        }
+    }
+}
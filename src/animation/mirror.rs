@@ -0,0 +1,164 @@
+use cgmath::{Vector3, Quaternion, InnerSpace};
+use md5::md5anim::{Md5Anim, Bound};
+use md5::md5mesh::Joint;
+use animation::pose::{frame_skeleton, skeleton_pose, encode_anim};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MirrorAxis {
+    X,
+    Y,
+    Z,
+}
+
+/// Left/right name suffixes used by Blender armatures, such as bob's
+/// "thigh.L" and "thigh.R".
+pub const BLENDER_SIDES: [(&str, &str); 4] = [(".L", ".R"), ("_L", "_R"), (".l", ".r"), ("_l", "_r")];
+
+/// Name of the joint on the other side, if `name` ends with one of the
+/// suffixes of `sides`.
+pub fn mirrored_name(name: &str, sides: &[(&str, &str)]) -> Option<String> {
+    for &(left, right) in sides {
+        if let Some(stem) = name.strip_suffix(left) {
+            return Some(format!("{}{}", stem, right));
+        }
+        if let Some(stem) = name.strip_suffix(right) {
+            return Some(format!("{}{}", stem, left));
+        }
+    }
+    None
+}
+
+/// For every joint, the index of its counterpart on the other side, or its
+/// own index when it has none.
+pub fn mirrored_joints(names: &[&str], sides: &[(&str, &str)]) -> Vec<usize> {
+    names.iter().enumerate().map(|(i, name)| {
+        mirrored_name(name, sides)
+            .and_then(|other| names.iter().position(|n| *n == other))
+            .unwrap_or(i)
+    }).collect()
+}
+
+pub fn mirror_vector(v: Vector3<f32>, axis: MirrorAxis) -> Vector3<f32> {
+    match axis {
+        MirrorAxis::X => Vector3::new(-v.x, v.y, v.z),
+        MirrorAxis::Y => Vector3::new(v.x, -v.y, v.z),
+        MirrorAxis::Z => Vector3::new(v.x, v.y, -v.z),
+    }
+}
+
+/// Conjugates a rotation by the reflection: the rotation axis is reflected
+/// as a pseudo vector and the angle kept.
+pub fn mirror_quaternion(q: Quaternion<f32>, axis: MirrorAxis) -> Quaternion<f32> {
+    Quaternion::from_sv(q.s, -mirror_vector(q.v, axis))
+}
+
+pub fn mirror_bound(bound: &Bound, axis: MirrorAxis) -> Bound {
+    let a = mirror_vector(bound.bound_min, axis);
+    let b = mirror_vector(bound.bound_max, axis);
+    Bound {
+        bound_min: Vector3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+        bound_max: Vector3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+    }
+}
+
+/// Mirrors `anim` across the plane orthogonal to `axis`, swapping the
+/// tracks of joints paired by `sides`.
+///
+/// Joint frames of a rig are rarely symmetric themselves, so the reflection
+/// is applied to the skinning transforms relative to `bind` (the md5mesh
+/// joints, or the skeleton of the anim base frame when no mesh is at hand):
+/// a mesh skinned with the result is the reflection of the original one.
+pub fn mirror_anim(anim: &Md5Anim, bind: &[Joint], axis: MirrorAxis, sides: &[(&str, &str)]) -> Md5Anim {
+    let names: Vec<&str> = anim.hierarchies.iter().map(|j| j.name.as_str()).collect();
+    let counterparts = mirrored_joints(&names, sides);
+
+    let poses: Vec<_> = (0..anim.frames.len()).map(|f| {
+        let skeleton = frame_skeleton(anim, f);
+
+        let mirrored: Vec<Joint> = skeleton.iter().enumerate().map(|(i, joint)| {
+            let source = &skeleton[counterparts[i]];
+            let source_bind = &bind[counterparts[i]];
+
+            // Skinning transform of the source joint, reflected.
+            let rotation = (source.orientation * source_bind.orientation.conjugate()).normalize();
+            let translation = source.position - rotation * source_bind.position;
+            let rotation = mirror_quaternion(rotation, axis);
+            let translation = mirror_vector(translation, axis);
+
+            Joint {
+                name: joint.name.clone(),
+                parent_index: joint.parent_index,
+                position: rotation * bind[i].position + translation,
+                orientation: (rotation * bind[i].orientation).normalize()
+            }
+        }).collect();
+
+        skeleton_pose(&mirrored)
+    }).collect();
+
+    let base_frame = poses.first().cloned().unwrap_or_else(|| anim.base_frame.clone());
+    let encoded = encode_anim(&anim.hierarchies, &base_frame, &poses);
+
+    Md5Anim {
+        version: anim.version,
+        command_line: anim.command_line.clone(),
+        frame_rate: anim.frame_rate,
+        bounds: anim.bounds.iter().map(|b| mirror_bound(b, axis)).collect(),
+        .. encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, InnerSpace};
+    use md5::md5anim::{Md5Anim, Joint as AnimJoint, Bound, BaseFrame};
+    use animation::pose::{complete_quaternion, frame_skeleton, pose_skeleton, encode_anim};
+    use super::{MirrorAxis, BLENDER_SIDES};
+
+    fn arms() -> Md5Anim {
+        let hierarchy = vec![
+            AnimJoint { name: String::from("spine"), index: -1, flag: 0, start_index: 0 },
+            AnimJoint { name: String::from("arm.L"), index: 0, flag: 0, start_index: 0 },
+            AnimJoint { name: String::from("arm.R"), index: 0, flag: 0, start_index: 0 }
+        ];
+        let pose = |angle: f32| BaseFrame {
+            position: vec![Vector3::new(0.0, 0.0, 1.0), Vector3::new(-1.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0)],
+            orientation: vec![complete_quaternion(0.0, 0.0, 0.0),
+                              complete_quaternion(0.0, angle.sin(), 0.0),
+                              complete_quaternion(0.0, 0.0, 0.0)]
+        };
+        let poses = vec![pose(0.0), pose(0.3)];
+        Md5Anim {
+            bounds: vec![Bound { bound_min: Vector3::new(-2.0, -1.0, 0.0), bound_max: Vector3::new(1.0, 1.0, 2.0) }; 2],
+            .. encode_anim(&hierarchy, &poses[0], &poses)
+        }
+    }
+
+    #[test]
+    fn mirrored_name() {
+        assert_eq!(super::mirrored_name("thigh.L", &BLENDER_SIDES), Some(String::from("thigh.R")));
+        assert_eq!(super::mirrored_name("thigh.R", &BLENDER_SIDES), Some(String::from("thigh.L")));
+        assert_eq!(super::mirrored_name("head", &BLENDER_SIDES), None);
+    }
+
+    #[test]
+    fn mirror_anim() {
+        let anim = arms();
+        let bind = pose_skeleton(&anim.hierarchies, &anim.base_frame);
+        let mirrored = super::mirror_anim(&anim, &bind, MirrorAxis::X, &BLENDER_SIDES);
+
+        let original = frame_skeleton(&anim, 1);
+        let result = frame_skeleton(&mirrored, 1);
+        let reflected = super::mirror_quaternion(original[1].orientation, MirrorAxis::X);
+        assert!(result[2].orientation.dot(reflected).abs() > 0.9999);
+        assert!(result[1].orientation.dot(original[2].orientation).abs() > 0.9999);
+        assert_eq!(mirrored.bounds[0].bound_min, Vector3::new(-1.0, -1.0, 0.0));
+        assert_eq!(mirrored.bounds[0].bound_max, Vector3::new(2.0, 1.0, 2.0));
+
+        let twice = super::mirror_anim(&mirrored, &bind, MirrorAxis::X, &BLENDER_SIDES);
+        for (a, b) in frame_skeleton(&twice, 1).iter().zip(&original) {
+            assert!((a.position - b.position).magnitude() < 1e-5);
+            assert!(a.orientation.dot(b.orientation).abs() > 0.9999);
+        }
+    }
+}
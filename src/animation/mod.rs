@@ -0,0 +1,15 @@
+pub mod pose;
+pub mod skeleton;
+
+pub mod ik;
+pub mod aim;
+pub mod mirror;
+pub mod retarget;
+pub mod compress;
+pub mod resample;
+pub mod clip;
+pub mod sidecar_parser;
+pub mod events;
+pub mod sidecar;
+pub mod sampler;
+pub mod bounds;
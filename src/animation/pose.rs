@@ -0,0 +1,290 @@
+use cgmath::{Vector3, Quaternion, InnerSpace};
+use md5::md5anim::{Md5Anim, BaseFrame, Frame, Joint as AnimJoint};
+use md5::md5mesh::Joint;
+
+// Bits of `md5anim::Joint::flag`, one per animated component.
+pub const FLAG_POSITION_X: i32 = 1;
+pub const FLAG_POSITION_Y: i32 = 2;
+pub const FLAG_POSITION_Z: i32 = 4;
+pub const FLAG_ORIENTATION_X: i32 = 8;
+pub const FLAG_ORIENTATION_Y: i32 = 16;
+pub const FLAG_ORIENTATION_Z: i32 = 32;
+
+/// Rebuilds a unit quaternion from its x, y, z components, the way the
+/// parsers do for joints and base frames.
+pub fn complete_quaternion(x: f32, y: f32, z: f32) -> Quaternion<f32> {
+    let mut scal: f32 = 1.0 - x * x - y * y - z * z;
+    if scal < 0.0 { scal = 0.0 };
+    Quaternion::new(-scal.sqrt(), x, y, z)
+}
+
+/// Interpolates two orientations along the shortest arc.
+pub fn interpolate_orientation(a: Quaternion<f32>, b: Quaternion<f32>, t: f32) -> Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    a.slerp(b, t).normalize()
+}
+
+/// Decodes frame `frame_index` of `anim` into parent-relative joint
+/// transforms, starting from the base frame and overriding the components
+/// flagged in the hierarchy.
+pub fn frame_pose(anim: &Md5Anim, frame_index: usize) -> BaseFrame {
+    decode_frame(anim, &anim.frames[frame_index].frame_data)
+}
+
+/// `frame_pose` for frame data kept outside of `anim`, e.g. read one frame
+/// at a time with `Md5AnimReader`.
+pub fn decode_frame(anim: &Md5Anim, data: &[f32]) -> BaseFrame {
+    let mut position = Vec::with_capacity(anim.hierarchies.len());
+    let mut orientation = Vec::with_capacity(anim.hierarchies.len());
+
+    for (i, joint) in anim.hierarchies.iter().enumerate() {
+        let mut p = anim.base_frame.position[i];
+        let base_o = anim.base_frame.orientation[i];
+        let mut o = [base_o.v.x, base_o.v.y, base_o.v.z];
+        let mut k = joint.start_index as usize;
+
+        for (bit, component) in [FLAG_POSITION_X, FLAG_POSITION_Y, FLAG_POSITION_Z].iter().zip(0..3) {
+            if joint.flag & bit != 0 {
+                p[component] = data[k];
+                k += 1;
+            }
+        }
+        for (bit, component) in [FLAG_ORIENTATION_X, FLAG_ORIENTATION_Y, FLAG_ORIENTATION_Z].iter().zip(0..3) {
+            if joint.flag & bit != 0 {
+                o[component] = data[k];
+                k += 1;
+            }
+        }
+
+        position.push(p);
+        orientation.push(complete_quaternion(o[0], o[1], o[2]));
+    }
+
+    BaseFrame { position, orientation }
+}
+
+/// Turns parent-relative joint transforms into an object space skeleton,
+/// the representation `prepare_mesh` skins against.
+pub fn pose_skeleton(hierarchy: &[AnimJoint], pose: &BaseFrame) -> Vec<Joint> {
+    compose_skeleton(hierarchy.iter().map(|j| (&j.name, j.index)), pose)
+}
+
+/// Same as `pose_skeleton`, taking names and parents from an existing
+/// skeleton.
+pub fn rebuild_skeleton(template: &[Joint], pose: &BaseFrame) -> Vec<Joint> {
+    compose_skeleton(template.iter().map(|j| (&j.name, j.parent_index)), pose)
+}
+
+fn compose_skeleton<'a, I>(joints: I, pose: &BaseFrame) -> Vec<Joint>
+    where I: Iterator<Item = (&'a String, i32)>
+{
+    let mut skeleton: Vec<Joint> = Vec::with_capacity(pose.position.len());
+
+    for (i, (name, parent_index)) in joints.enumerate() {
+        let local_position = pose.position[i];
+        let local_orientation = pose.orientation[i];
+
+        let (position, orientation) =
+            if parent_index < 0 {
+                (local_position, local_orientation)
+            } else {
+                let parent = &skeleton[parent_index as usize];
+                (parent.position + parent.orientation * local_position,
+                 (parent.orientation * local_orientation).normalize())
+            };
+
+        skeleton.push(Joint {
+            name: name.clone(),
+            parent_index,
+            position,
+            orientation
+        });
+    }
+
+    skeleton
+}
+
+/// Object space skeleton of frame `frame_index`.
+pub fn frame_skeleton(anim: &Md5Anim, frame_index: usize) -> Vec<Joint> {
+    pose_skeleton(&anim.hierarchies, &frame_pose(anim, frame_index))
+}
+
+pub fn interpolate_pose(a: &BaseFrame, b: &BaseFrame, t: f32) -> BaseFrame {
+    BaseFrame {
+        position: a.position.iter().zip(&b.position)
+            .map(|(pa, pb)| pa + (pb - pa) * t)
+            .collect(),
+        orientation: a.orientation.iter().zip(&b.orientation)
+            .map(|(oa, ob)| interpolate_orientation(*oa, *ob, t))
+            .collect()
+    }
+}
+
+/// Duration of the clip in seconds.
+pub fn anim_duration(anim: &Md5Anim) -> f32 {
+    if anim.frame_rate <= 0 || anim.frames.len() < 2 {
+        0.0
+    } else {
+        (anim.frames.len() - 1) as f32 / anim.frame_rate as f32
+    }
+}
+
+/// Maps a time in seconds to the pair of frames surrounding it and the
+/// blend factor between them. Looping clips wrap from the last frame back
+/// to the first one, others are clamped.
+pub fn frame_at_time(anim: &Md5Anim, time: f32, looping: bool) -> (usize, usize, f32) {
+    let count = anim.frames.len();
+    if count < 2 || anim.frame_rate <= 0 {
+        return (0, 0, 0.0);
+    }
+
+    let position = time * anim.frame_rate as f32;
+
+    if looping {
+        let position = position.rem_euclid(count as f32);
+        let current = (position.floor() as usize).min(count - 1);
+        (current, (current + 1) % count, position - current as f32)
+    } else {
+        let position = position.max(0.0).min((count - 1) as f32);
+        let current = (position.floor() as usize).min(count - 2);
+        (current, current + 1, position - current as f32)
+    }
+}
+
+/// Parent-relative pose of `anim` at `time` seconds.
+pub fn sample_pose(anim: &Md5Anim, time: f32, looping: bool) -> BaseFrame {
+    let (current, next, t) = frame_at_time(anim, time, looping);
+    if current == next {
+        frame_pose(anim, current)
+    } else {
+        interpolate_pose(&frame_pose(anim, current), &frame_pose(anim, next), t)
+    }
+}
+
+/// Object space skeleton of `anim` at `time` seconds.
+pub fn sample_skeleton(anim: &Md5Anim, time: f32, looping: bool) -> Vec<Joint> {
+    pose_skeleton(&anim.hierarchies, &sample_pose(anim, time, looping))
+}
+
+/// Transform of a skeleton joint expressed relative to its parent.
+pub fn local_transform(skeleton: &[Joint], index: usize) -> (Vector3<f32>, Quaternion<f32>) {
+    let joint = &skeleton[index];
+    if joint.parent_index < 0 {
+        (joint.position, joint.orientation)
+    } else {
+        let parent = &skeleton[joint.parent_index as usize];
+        let inverse = parent.orientation.conjugate();
+        (inverse * (joint.position - parent.position),
+         (inverse * joint.orientation).normalize())
+    }
+}
+
+/// Inverse of `pose_skeleton`: parent-relative transforms of every joint.
+pub fn skeleton_pose(skeleton: &[Joint]) -> BaseFrame {
+    let mut position = Vec::with_capacity(skeleton.len());
+    let mut orientation = Vec::with_capacity(skeleton.len());
+
+    for i in 0..skeleton.len() {
+        let (p, o) = local_transform(skeleton, i);
+        position.push(p);
+        orientation.push(o);
+    }
+
+    BaseFrame { position, orientation }
+}
+
+/// Flips a quaternion to the hemisphere MD5 files store, where the omitted
+/// w component is negative.
+pub fn canonical_orientation(q: Quaternion<f32>) -> Quaternion<f32> {
+    if q.s > 0.0 { -q } else { q }
+}
+
+/// Builds a clip out of parent-relative poses, one per frame. Components
+/// are only animated for joints where some frame differs from `base_frame`,
+/// the resulting flags and start indices replace those of `hierarchy`.
+///
+/// The header is filled with defaults and `bounds` is left empty, callers
+/// are expected to override them.
+pub fn encode_anim(hierarchy: &[AnimJoint], base_frame: &BaseFrame, poses: &[BaseFrame]) -> Md5Anim {
+    let base_frame = BaseFrame {
+        position: base_frame.position.clone(),
+        orientation: base_frame.orientation.iter().map(|&q| canonical_orientation(q)).collect()
+    };
+
+    let mut joints: Vec<AnimJoint> = Vec::with_capacity(hierarchy.len());
+    let mut start_index = 0;
+
+    for (i, joint) in hierarchy.iter().enumerate() {
+        let base = joint_components(&base_frame, i);
+        let mut flag = 0;
+
+        for pose in poses {
+            for (c, (value, base_value)) in joint_components(pose, i).iter().zip(&base).enumerate() {
+                if value != base_value {
+                    flag |= 1 << c;
+                }
+            }
+        }
+
+        joints.push(AnimJoint {
+            name: joint.name.clone(),
+            index: joint.index,
+            flag,
+            start_index
+        });
+        start_index += (flag as u32).count_ones() as i32;
+    }
+
+    let frames = poses.iter().enumerate().map(|(n, pose)| {
+        let mut frame_data = Vec::with_capacity(start_index as usize);
+        for (i, joint) in joints.iter().enumerate() {
+            for (c, value) in joint_components(pose, i).iter().enumerate() {
+                if joint.flag & (1 << c) != 0 {
+                    frame_data.push(*value);
+                }
+            }
+        }
+        Frame { frame_number: n as u32, frame_data }
+    }).collect();
+
+    Md5Anim {
+        version: 10,
+        command_line: String::new(),
+        num_frames: poses.len() as i32,
+        num_joints: joints.len() as i32,
+        frame_rate: 24,
+        num_animated_components: start_index,
+        hierarchies: joints,
+        bounds: Vec::new(),
+        base_frame,
+        frames
+    }
+}
+
+/// Position and orientation components of joint `index`, in the order of
+/// the flag bits.
+fn joint_components(pose: &BaseFrame, index: usize) -> [f32; 6] {
+    let p = pose.position[index];
+    let o = canonical_orientation(pose.orientation[index]);
+    [p.x, p.y, p.z, o.v.x, o.v.y, o.v.z]
+}
+
+/// Resamples poses taken `source_rate` times per second at `target_rate`,
+/// interpolating between the surrounding poses. The clip keeps its duration,
+/// rounded down to a whole number of target frames.
+pub fn resample_poses(poses: &[BaseFrame], source_rate: f32, target_rate: f32) -> Vec<BaseFrame> {
+    if poses.len() < 2 || source_rate <= 0.0 || target_rate <= 0.0 {
+        return poses.to_vec();
+    }
+
+    let last = poses.len() - 1;
+    let duration = last as f32 / source_rate;
+    let count = (duration * target_rate + 1e-3).floor() as usize + 1;
+
+    (0..count).map(|i| {
+        let position = (i as f32 * source_rate / target_rate).min(last as f32);
+        let current = (position.floor() as usize).min(last);
+        let next = (current + 1).min(last);
+        interpolate_pose(&poses[current], &poses[next], position - current as f32)
+    }).collect()
+}
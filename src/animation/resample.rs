@@ -0,0 +1,116 @@
+use cgmath::Vector3;
+use md5::md5anim::{Md5Anim, Bound, BaseFrame};
+use md5::md5mesh::Md5Mesh;
+use animation::pose::{frame_pose, resample_poses, encode_anim, rebuild_skeleton};
+use vertex_computation::compute::mesh_bounds;
+
+/// Smallest box containing both `a` and `b`.
+pub fn bound_union(a: &Bound, b: &Bound) -> Bound {
+    Bound {
+        bound_min: Vector3::new(a.bound_min.x.min(b.bound_min.x),
+                                a.bound_min.y.min(b.bound_min.y),
+                                a.bound_min.z.min(b.bound_min.z)),
+        bound_max: Vector3::new(a.bound_max.x.max(b.bound_max.x),
+                                a.bound_max.y.max(b.bound_max.y),
+                                a.bound_max.z.max(b.bound_max.z))
+    }
+}
+
+/// Bounds of `count` frames at `target_rate` taken from bounds at
+/// `source_rate`. A frame falling between two source frames gets the union of
+/// their bounds, which contains the interpolated pose as long as joints move
+/// roughly linearly in between.
+pub fn resample_bounds(bounds: &[Bound], source_rate: f32, target_rate: f32, count: usize) -> Vec<Bound> {
+    if bounds.is_empty() {
+        return Vec::new();
+    }
+
+    let last = bounds.len() - 1;
+    (0..count).map(|i| {
+        let position = (i as f32 * source_rate / target_rate).min(last as f32);
+        let current = (position.floor() as usize).min(last);
+        let next = (current + 1).min(last);
+        if position - current as f32 > 1e-4 {
+            bound_union(&bounds[current], &bounds[next])
+        } else {
+            bounds[current].clone()
+        }
+    }).collect()
+}
+
+/// Resamples `anim` at `frame_rate` frames per second, interpolating between
+/// the surrounding frames. The clip keeps its duration, rounded down to a
+/// whole number of frames.
+///
+/// Bounds are recomputed from the skinned `mesh` when given, otherwise they
+/// are derived from the original ones with `resample_bounds`. The result can
+/// be written back with `md5anim_writer::write_md5anim`. `None` when either
+/// frame rate is not positive.
+pub fn resample_anim(anim: &Md5Anim, frame_rate: i32, mesh: Option<&Md5Mesh>) -> Option<Md5Anim> {
+    if frame_rate <= 0 || anim.frame_rate <= 0 {
+        return None;
+    }
+
+    let poses: Vec<BaseFrame> = (0..anim.frames.len()).map(|f| frame_pose(anim, f)).collect();
+    let poses = resample_poses(&poses, anim.frame_rate as f32, frame_rate as f32);
+
+    let bounds = match mesh {
+        Some(mesh) => poses.iter().map(|p| mesh_bounds(mesh, &rebuild_skeleton(&mesh.joints, p))).collect(),
+        None => resample_bounds(&anim.bounds, anim.frame_rate as f32, frame_rate as f32, poses.len())
+    };
+
+    let encoded = encode_anim(&anim.hierarchies, &anim.base_frame, &poses);
+    Some(Md5Anim {
+        version: anim.version,
+        command_line: anim.command_line.clone(),
+        frame_rate,
+        bounds,
+        .. encoded
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+    use md5::md5anim_writer::md5anim_to_string;
+    use animation::pose::frame_pose;
+    use md5::md5anim_parser::parse_anim;
+    use test_support::bob_anim;
+
+    #[test]
+    fn resample_anim() {
+        let anim = bob_anim();
+        let resampled = super::resample_anim(&anim, 60, None).unwrap();
+
+        // 141 frames at 24 fps span 5.8333 seconds, as do 351 frames at 60 fps.
+        assert_eq!(resampled.frame_rate, 60);
+        assert_eq!(resampled.num_frames, 351);
+        assert_eq!(resampled.frames.len(), 351);
+        assert_eq!(resampled.bounds.len(), 351);
+        assert_eq!(resampled.frames[350].frame_number, 350);
+
+        // Frame 10 at 24 fps is frame 25 at 60 fps.
+        let original = frame_pose(&anim, 10);
+        let sampled = frame_pose(&resampled, 25);
+        for (a, b) in original.position.iter().zip(&sampled.position) {
+            assert!((a - b).magnitude() < 1e-3);
+        }
+
+        let text = md5anim_to_string(&resampled);
+        match parse_anim(text.as_bytes()) {
+            Ok((_, parsed)) => {
+                assert_eq!(parsed.num_frames, 351);
+                assert_eq!(parsed.frame_rate, 60);
+                assert_eq!(parsed.frames.len(), 351);
+            },
+            other => panic!("{:?}", other)
+        }
+    }
+
+    #[test]
+    fn rejects_non_positive_rates() {
+        let anim = bob_anim();
+        assert_eq!(super::resample_anim(&anim, 0, None), None);
+        assert_eq!(super::resample_anim(&anim, -24, None), None);
+    }
+}
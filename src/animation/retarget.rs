@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use cgmath::{Vector3, Quaternion, InnerSpace};
+use md5::md5anim::{Md5Anim, Joint as AnimJoint};
+use md5::md5mesh::{Md5Mesh, Joint};
+use animation::pose::{frame_skeleton, local_transform, skeleton_pose, encode_anim};
+use animation::skeleton::find_joint;
+use vertex_computation::compute::mesh_bounds;
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct RetargetOptions {
+    /// Target joint driven by a source joint, keyed by source joint name,
+    /// for joints whose names differ between the two skeletons. Keyed the
+    /// same way as `BvhImportOptions::joint_map`.
+    pub joint_map: HashMap<String, String>,
+    /// Hip and foot joints of the target, e.g. `("thigh.L", "ankle.L")`,
+    /// mapped to the source through `joint_map`. The ratio of the leg lengths
+    /// of both bind poses scales the translation of the root, which is kept
+    /// as is when this is not set.
+    pub leg: Option<(String, String)>,
+}
+
+/// Sum of the bone lengths from `start` down to its descendant `end`.
+pub fn chain_length(skeleton: &[Joint], start: &str, end: &str) -> Option<f32> {
+    let start = find_joint(skeleton, start)?;
+    let mut current = find_joint(skeleton, end)?;
+    let mut length = 0.0;
+
+    while current != start {
+        let parent = skeleton[current].parent_index;
+        if parent < 0 {
+            return None;
+        }
+        length += (skeleton[current].position - skeleton[parent as usize].position).magnitude();
+        current = parent as usize;
+    }
+
+    Some(length)
+}
+
+/// Transfers `anim`, authored for the `source` skeleton, onto the `target`
+/// skeleton.
+///
+/// Joints are matched by name, or through `options.joint_map`. Each matched
+/// target joint receives the object space rotation its source joint went
+/// through since the bind pose, so bones keep pointing the same way whatever
+/// the bind poses look like, while bone lengths stay those of the target.
+/// Root translations away from the bind pose are scaled by the leg length
+/// ratio; other joints keep their source offsets.
+/// Target joints without a source keep their bind pose relative to their
+/// parent.
+pub fn retarget_anim(anim: &Md5Anim, source: &Md5Mesh, target: &Md5Mesh, options: &RetargetOptions) -> Md5Anim {
+    let target_to_source: HashMap<&String, &String> = options.joint_map.iter().map(|(s, t)| (t, s)).collect();
+    let source_name = |name: &String| (*target_to_source.get(name).unwrap_or(&name)).clone();
+
+    let scale = match options.leg {
+        Some((ref hip, ref foot)) => {
+            match (chain_length(&source.joints, &source_name(hip), &source_name(foot)),
+                   chain_length(&target.joints, hip, foot)) {
+                (Some(s), Some(t)) if s > 1e-6 => t / s,
+                _ => 1.0
+            }
+        },
+        None => 1.0
+    };
+
+    let anim_names: Vec<&str> = anim.hierarchies.iter().map(|j| j.name.as_str()).collect();
+
+    // (index in the anim, index in the source bind pose) of each target joint.
+    let sources: Vec<Option<(usize, usize)>> = target.joints.iter().map(|joint| {
+        let name = source_name(&joint.name);
+        match (anim_names.iter().position(|n| *n == name), find_joint(&source.joints, &name)) {
+            (Some(a), Some(b)) => Some((a, b)),
+            _ => None
+        }
+    }).collect();
+
+    let identity = Quaternion::new(1.0, 0.0, 0.0, 0.0);
+    let poses: Vec<_> = (0..anim.frames.len()).map(|f| {
+        let animated = frame_skeleton(anim, f);
+        let mut skeleton: Vec<Joint> = Vec::with_capacity(target.joints.len());
+
+        for (i, target_bind) in target.joints.iter().enumerate() {
+            let (bind_position, bind_orientation) = local_transform(&target.joints, i);
+            let (parent_position, parent_orientation, parent_bind_orientation) =
+                if target_bind.parent_index < 0 {
+                    (Vector3::new(0.0, 0.0, 0.0), identity, identity)
+                } else {
+                    let parent = target_bind.parent_index as usize;
+                    (skeleton[parent].position, skeleton[parent].orientation, target.joints[parent].orientation)
+                };
+
+            let (local_position, orientation) = match sources[i] {
+                Some((a, b)) => {
+                    let source_bind = &source.joints[b];
+                    let rotation = animated[a].orientation * source_bind.orientation.conjugate();
+
+                    // Translation away from the bind pose, moved from the
+                    // source parent frame to the target parent frame.
+                    let (animated_position, _) = local_transform(&animated, a);
+                    let (source_bind_position, _) = local_transform(&source.joints, b);
+                    let source_parent_orientation = match anim.hierarchies[a].index {
+                        p if p >= 0 => find_joint(&source.joints, &anim.hierarchies[p as usize].name)
+                            .map(|p| source.joints[p].orientation)
+                            .unwrap_or(identity),
+                        _ => identity
+                    };
+                    let offset = parent_bind_orientation.conjugate() * source_parent_orientation
+                        * (animated_position - source_bind_position);
+
+                    let offset = if target_bind.parent_index < 0 { offset * scale } else { offset };
+                    (bind_position + offset, (rotation * target_bind.orientation).normalize())
+                },
+                None => (bind_position, (parent_orientation * bind_orientation).normalize())
+            };
+
+            skeleton.push(Joint {
+                name: target_bind.name.clone(),
+                parent_index: target_bind.parent_index,
+                position: parent_position + parent_orientation * local_position,
+                orientation
+            });
+        }
+
+        skeleton
+    }).collect();
+
+    let hierarchy: Vec<AnimJoint> = target.joints.iter().map(|j| AnimJoint {
+        name: j.name.clone(),
+        index: j.parent_index,
+        flag: 0,
+        start_index: 0
+    }).collect();
+    let local_poses: Vec<_> = poses.iter().map(|s| skeleton_pose(s)).collect();
+    let encoded = encode_anim(&hierarchy, &skeleton_pose(&target.joints), &local_poses);
+
+    Md5Anim {
+        version: anim.version,
+        command_line: anim.command_line.clone(),
+        frame_rate: anim.frame_rate,
+        bounds: poses.iter().map(|s| mesh_bounds(target, s)).collect(),
+        .. encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use cgmath::{Vector3, InnerSpace};
+    use md5::md5anim::{Md5Anim, Joint as AnimJoint, BaseFrame};
+    use md5::md5mesh::Md5Mesh;
+    use animation::pose::{complete_quaternion, frame_skeleton, pose_skeleton, encode_anim};
+    use super::RetargetOptions;
+
+    fn leg(name: &str, length: f32, hip_height: f32) -> Md5Mesh {
+        let hierarchy = leg_hierarchy(name);
+        let pose = BaseFrame {
+            position: vec![Vector3::new(0.0, 0.0, hip_height), Vector3::new(0.0, 0.0, -length)],
+            orientation: vec![complete_quaternion(0.0, 0.0, 0.0), complete_quaternion(0.0, 0.0, 0.0)]
+        };
+        Md5Mesh {
+            version: 10,
+            command_line: String::new(),
+            joints: pose_skeleton(&hierarchy, &pose),
+            meshes: vec![]
+        }
+    }
+
+    fn leg_hierarchy(foot: &str) -> Vec<AnimJoint> {
+        vec![
+            AnimJoint { name: String::from("hip"), index: -1, flag: 0, start_index: 0 },
+            AnimJoint { name: String::from(foot), index: 0, flag: 0, start_index: 0 }
+        ]
+    }
+
+    fn kick() -> Md5Anim {
+        let pose = |height: f32, swing: f32| BaseFrame {
+            position: vec![Vector3::new(0.0, 0.0, height), Vector3::new(0.0, 0.0, -1.0)],
+            orientation: vec![complete_quaternion(0.0, 0.0, 0.0), complete_quaternion(swing, 0.0, 0.0)]
+        };
+        let poses = vec![pose(1.0, 0.0), pose(1.5, 0.5)];
+        encode_anim(&leg_hierarchy("foot"), &poses[0], &poses)
+    }
+
+    #[test]
+    fn chain_length() {
+        let mesh = leg("foot", 2.0, 2.0);
+        assert_eq!(super::chain_length(&mesh.joints, "hip", "foot"), Some(2.0));
+        assert_eq!(super::chain_length(&mesh.joints, "foot", "hip"), None);
+    }
+
+    #[test]
+    fn retarget_anim() {
+        let source = leg("foot", 1.0, 1.0);
+        let target = leg("ankle", 2.0, 2.0);
+        let mut joint_map = HashMap::new();
+        joint_map.insert(String::from("foot"), String::from("ankle"));
+        let options = RetargetOptions { joint_map, leg: Some((String::from("hip"), String::from("ankle"))) };
+
+        let anim = kick();
+        let result = super::retarget_anim(&anim, &source, &target, &options);
+        assert_eq!(result.frames.len(), 2);
+        assert_eq!(result.hierarchies[1].name, "ankle");
+
+        let original = frame_skeleton(&anim, 1);
+        let retargeted = frame_skeleton(&result, 1);
+        // Hip raised by 0.5 in the source, twice as much on a leg twice as long.
+        assert!((retargeted[0].position - Vector3::new(0.0, 0.0, 3.0)).magnitude() < 1e-5);
+        // Same leg direction, target bone length.
+        let source_leg = original[1].position - original[0].position;
+        let target_leg = retargeted[1].position - retargeted[0].position;
+        assert!((target_leg.magnitude() - 2.0).abs() < 1e-5);
+        assert!(target_leg.normalize().dot(source_leg.normalize()) > 0.9999);
+    }
+
+    #[test]
+    fn retarget_scales_root_only() {
+        let source = leg("foot", 1.0, 1.0);
+        let target = leg("foot", 2.0, 2.0);
+        let options = RetargetOptions { joint_map: HashMap::new(), leg: Some((String::from("hip"), String::from("foot"))) };
+
+        // The foot slides 0.2 forward of the hip while the hip rises by 0.5.
+        let pose = |height: f32, slide: f32| BaseFrame {
+            position: vec![Vector3::new(0.0, 0.0, height), Vector3::new(0.0, slide, -1.0)],
+            orientation: vec![complete_quaternion(0.0, 0.0, 0.0), complete_quaternion(0.0, 0.0, 0.0)]
+        };
+        let poses = vec![pose(1.0, 0.0), pose(1.5, 0.2)];
+        let anim = encode_anim(&leg_hierarchy("foot"), &poses[0], &poses);
+
+        let retargeted = frame_skeleton(&super::retarget_anim(&anim, &source, &target, &options), 1);
+        assert!((retargeted[0].position - Vector3::new(0.0, 0.0, 3.0)).magnitude() < 1e-5);
+        assert!((retargeted[1].position - retargeted[0].position - Vector3::new(0.0, 0.2, -2.0)).magnitude() < 1e-5);
+    }
+}
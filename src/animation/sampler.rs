@@ -0,0 +1,83 @@
+use md5::md5anim::{Md5Anim, BaseFrame};
+use md5::md5mesh::Joint;
+use animation::pose::{sample_pose, pose_skeleton, anim_duration};
+use animation::events::{AnimEvent, EventTrack};
+
+/// Playback state of one clip: where it is, how fast it goes and whether it
+/// wraps around.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AnimSampler {
+    /// Playback time in seconds.
+    pub time: f32,
+    /// Playback speed, negative to play in reverse.
+    pub speed: f32,
+    pub looping: bool,
+}
+
+impl AnimSampler {
+    pub fn new(looping: bool) -> AnimSampler {
+        AnimSampler { time: 0.0, speed: 1.0, looping }
+    }
+
+    /// Moves playback forward by `delta` seconds of wall time and returns the
+    /// events of `track` crossed on the way, in the order they were met.
+    pub fn advance<'a>(&mut self, anim: &Md5Anim, track: &'a EventTrack, delta: f32) -> Vec<&'a AnimEvent> {
+        let from = self.time;
+        let mut to = from + delta * self.speed;
+
+        if !self.looping {
+            to = to.max(0.0).min(anim_duration(anim));
+        }
+
+        let crossed = track.crossed(anim, from, to, self.looping);
+
+        // Looping time is kept within one period so that it does not lose
+        // precision over long sessions.
+        if self.looping && anim.frame_rate > 0 && !anim.frames.is_empty() {
+            to = to.rem_euclid(anim.frames.len() as f32 / anim.frame_rate as f32);
+        }
+        self.time = to;
+
+        crossed
+    }
+
+    pub fn pose(&self, anim: &Md5Anim) -> BaseFrame {
+        sample_pose(anim, self.time, self.looping)
+    }
+
+    pub fn skeleton(&self, anim: &Md5Anim) -> Vec<Joint> {
+        pose_skeleton(&anim.hierarchies, &self.pose(anim))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+    use md5::md5anim::{Md5Anim, Joint as AnimJoint, BaseFrame};
+    use animation::pose::{complete_quaternion, encode_anim};
+    use animation::events::{AnimEvent, EventTrack};
+    use super::AnimSampler;
+
+    #[test]
+    fn advance() {
+        let hierarchy = vec![AnimJoint { name: String::from("root"), index: -1, flag: 0, start_index: 0 }];
+        let poses: Vec<BaseFrame> = (0..10).map(|f| BaseFrame {
+            position: vec![Vector3::new(f as f32, 0.0, 0.0)],
+            orientation: vec![complete_quaternion(0.0, 0.0, 0.0)]
+        }).collect();
+        let anim = Md5Anim { frame_rate: 10, .. encode_anim(&hierarchy, &poses[0], &poses) };
+        let track = EventTrack::new(vec![AnimEvent { name: String::from("step"), frame: 2 }]);
+
+        let mut sampler = AnimSampler::new(true);
+        assert_eq!(sampler.advance(&anim, &track, 0.25).len(), 1);
+        assert_eq!(sampler.advance(&anim, &track, 0.5).len(), 0);
+        // Wraps around and steps again.
+        assert_eq!(sampler.advance(&anim, &track, 0.5).len(), 1);
+        assert!((sampler.time - 0.25).abs() < 1e-5);
+
+        sampler.speed = -1.0;
+        assert_eq!(sampler.advance(&anim, &track, 0.1).len(), 1);
+        assert_eq!(sampler.advance(&anim, &track, 0.1).len(), 0);
+        assert!((sampler.pose(&anim).position[0].x - 0.5).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,9 @@
+use animation::clip::ClipRange;
+use animation::events::EventTrack;
+
+/// Everything a sidecar file says about the md5anim it sits next to.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Sidecar {
+    pub clips: Vec<ClipRange>,
+    pub events: EventTrack,
+}
@@ -0,0 +1,127 @@
+use nom::branch::alt;
+use nom::combinator::{map, cut};
+use nom::error::context;
+use nom::multi::many0;
+use nom::sequence::{preceded, tuple};
+use md5::md5common_parser::*;
+use animation::clip::ClipRange;
+use animation::events::{AnimEvent, EventTrack};
+use animation::sidecar::Sidecar;
+
+// Sidecar files sit next to an md5anim and describe it, one entry per line:
+//
+//     // bob_lamp_update_export.md5anim
+//     clip "idle" 0 40
+//     clip "swing" 41 140
+//     event "lamp_hit" 75
+
+pub fn parse_clip_range(input: &[u8]) -> ParseResult<'_, ClipRange> {
+    context("clip",
+        map(
+            preceded(
+                ws(keyword("clip")),
+                cut(tuple((ws(escaped_string), ws(parse_u32), ws(parse_u32))))
+            ),
+            |(name, start, end)| ClipRange {
+                name,
+                start,
+                end
+            }
+        )
+    )(input)
+}
+
+pub fn parse_event(input: &[u8]) -> ParseResult<'_, AnimEvent> {
+    context("event",
+        map(
+            preceded(
+                ws(keyword("event")),
+                cut(tuple((ws(escaped_string), ws(parse_u32))))
+            ),
+            |(name, frame)| AnimEvent {
+                name,
+                frame
+            }
+        )
+    )(input)
+}
+
+enum Entry {
+    Clip(ClipRange),
+    Event(AnimEvent),
+}
+
+// Comments are skipped along with the blanks around entries.
+pub fn parse_sidecar(input: &[u8]) -> ParseResult<'_, Sidecar> {
+    map(
+        preceded(
+            blank,
+            many0(
+                ws(
+                    alt((
+                        map(parse_clip_range, Entry::Clip),
+                        map(parse_event, Entry::Event)
+                    ))
+                )
+            )
+        ),
+        |entries: Vec<Entry>| {
+            let mut clips = Vec::new();
+            let mut events = Vec::new();
+            for entry in entries {
+                match entry {
+                    Entry::Clip(clip) => clips.push(clip),
+                    Entry::Event(event) => events.push(event),
+                }
+            }
+            Sidecar { clips, events: EventTrack::new(events) }
+        }
+    )(input)
+}
+
+pub fn parse_clip_ranges(input: &[u8]) -> ParseResult<'_, Vec<ClipRange>> {
+    map(parse_sidecar, |sidecar: Sidecar| sidecar.clips)(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use animation::clip::ClipRange;
+    use animation::events::AnimEvent;
+
+    #[test]
+    fn parse_clip_range() {
+        let string = b"clip \"idle\" 0 40\n";
+        let range = ClipRange { name: String::from("idle"), start: 0, end: 40 };
+        assert_eq!(super::parse_clip_range(string), Ok((&b""[..], range)));
+    }
+
+    #[test]
+    fn parse_clip_ranges() {
+        let string = b"// bob_lamp_update_export.md5anim
+        clip \"idle\" 0 40
+        clip \"swing\" 41 140
+        ";
+        let ranges = vec![
+            ClipRange { name: String::from("idle"), start: 0, end: 40 },
+            ClipRange { name: String::from("swing"), start: 41, end: 140 }
+        ];
+        assert_eq!(super::parse_clip_ranges(string), Ok((&b""[..], ranges)));
+    }
+
+    #[test]
+    fn parse_sidecar() {
+        let string = b"clip \"swing\" 41 140
+        event \"lamp_hit\" 75 // comment
+        event \"step\" 50
+        ";
+        let sidecar = match super::parse_sidecar(string) {
+            Ok((_, sidecar)) => sidecar,
+            other => panic!("{:?}", other)
+        };
+        assert_eq!(sidecar.clips.len(), 1);
+        assert_eq!(sidecar.events.events, vec![
+            AnimEvent { name: String::from("step"), frame: 50 },
+            AnimEvent { name: String::from("lamp_hit"), frame: 75 }
+        ]);
+    }
+}
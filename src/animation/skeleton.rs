@@ -0,0 +1,69 @@
+use cgmath::{Vector3, Quaternion, InnerSpace};
+use md5::md5mesh::Joint;
+use animation::pose::{skeleton_pose, interpolate_pose, rebuild_skeleton};
+
+pub fn find_joint(skeleton: &[Joint], name: &str) -> Option<usize> {
+    skeleton.iter().position(|j| j.name == name)
+}
+
+/// Resolves a chain of joint names, from the root of the chain to its end,
+/// into skeleton indices. Every joint must be a descendant of the previous
+/// one.
+pub fn joint_chain(skeleton: &[Joint], names: &[&str]) -> Option<Vec<usize>> {
+    let mut chain: Vec<usize> = Vec::with_capacity(names.len());
+
+    for name in names {
+        let index = find_joint(skeleton, name)?;
+        if let Some(&previous) = chain.last() {
+            if !is_descendant(skeleton, index, previous) {
+                return None;
+            }
+        }
+        chain.push(index);
+    }
+
+    Some(chain)
+}
+
+pub fn is_descendant(skeleton: &[Joint], index: usize, ancestor: usize) -> bool {
+    let mut current = skeleton[index].parent_index;
+    let mut depth = 0;
+
+    while current >= 0 && depth < skeleton.len() {
+        if current as usize == ancestor {
+            return true;
+        }
+        current = skeleton[current as usize].parent_index;
+        depth += 1;
+    }
+
+    false
+}
+
+/// Rotates joint `index` in object space around its own position, carrying
+/// all of its descendants along.
+pub fn rotate_joint(skeleton: &mut [Joint], index: usize, rotation: Quaternion<f32>) {
+    let pivot = skeleton[index].position;
+
+    for i in index..skeleton.len() {
+        if i == index || is_descendant(skeleton, i, index) {
+            let joint = &mut skeleton[i];
+            joint.position = pivot + rotation * (joint.position - pivot);
+            joint.orientation = (rotation * joint.orientation).normalize();
+        }
+    }
+}
+
+/// Rotation turning direction `from` into direction `to`.
+pub fn rotation_between(from: Vector3<f32>, to: Vector3<f32>) -> Quaternion<f32> {
+    if from.magnitude2() < 1e-12 || to.magnitude2() < 1e-12 {
+        return Quaternion::new(1.0, 0.0, 0.0, 0.0);
+    }
+    Quaternion::from_arc(from.normalize(), to.normalize(), None)
+}
+
+/// Blends two skeletons sharing the same hierarchy, interpolating each
+/// joint relative to its parent.
+pub fn interpolate_skeletons(a: &[Joint], b: &[Joint], t: f32) -> Vec<Joint> {
+    rebuild_skeleton(a, &interpolate_pose(&skeleton_pose(a), &skeleton_pose(b), t))
+}
@@ -0,0 +1,59 @@
+//! Bakes md5mesh and md5anim files to the binary format of
+//! `amalia::loader::baked`, next to each source file:
+//!
+//!     md5bake bob.md5mesh bob.md5anim
+//!
+//! writes `bob.md5mesh.baked` and `bob.md5anim.baked`. With `--check`,
+//! reports the files whose baked file is missing, stale or corrupt instead,
+//! and exits with an error if there are any.
+
+extern crate amalia;
+
+use std::env;
+use std::path::Path;
+use std::process;
+use amalia::loader::baked::{bake_file, baked_path, load_baked};
+use amalia::loader::error::Md5Kind;
+
+fn main() {
+    let mut check = false;
+    let mut sources = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ => sources.push(arg)
+        }
+    }
+    if sources.is_empty() {
+        eprintln!("usage: md5bake [--check] FILE...");
+        process::exit(2);
+    }
+
+    let mut failed = false;
+    for source in &sources {
+        let source = Path::new(source);
+        let baked = baked_path(source);
+        if check {
+            let checked = load_baked(source, &baked).and_then(|file| match file.kind() {
+                Md5Kind::Mesh => file.model().map(|_| ()),
+                Md5Kind::Anim => file.anim().map(|_| ())
+            });
+            if let Err(e) = checked {
+                println!("{}: {}", baked.display(), e);
+                failed = true;
+            }
+            continue;
+        }
+
+        match bake_file(source, &baked) {
+            Ok(kind) => println!("{} -> {} ({})", source.display(), baked.display(), kind),
+            Err(e) => {
+                eprintln!("{}: {}", source.display(), e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
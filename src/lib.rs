@@ -0,0 +1,78 @@
+//! MD5 (Doom 3) models and animations: parsing, pose evaluation, skinning
+//! and export, plus the Vulkan viewer behind the opt-in `viewer` feature.
+//!
+//! * `md5`: `.md5mesh` and `.md5anim` types, their nom parsers and the
+//!   `.md5anim` writer. The types implement serde's traits with the `serde`
+//!   feature.
+//! * `animation`: posing skeletons from animations, sampling, blending
+//!   helpers, compression, resampling, clips and events.
+//! * `vertex_computation`: skinning (linear blend, dual quaternion, matrix
+//!   palettes, batched in parallel), normals, bounds and picking.
+//! * `mocap`: BVH motion capture import.
+//! * `loader`: loading either kind of MD5 file from a path or reader, with
+//!   its textures, behind a single error type; a hot reloading asset cache;
+//!   reading long md5anims one frame at a time; a binary format, written
+//!   by the `md5bake` command, that loads without parsing.
+//!
+//! Skinning `bob` at one second into his animation:
+//!
+//! ```no_run
+//! extern crate amalia;
+//!
+//! use std::fs::File;
+//! use std::io::Read;
+//! use amalia::md5::md5mesh_parser::parse_md5mesh;
+//! use amalia::md5::md5anim_parser::parse_anim;
+//! use amalia::animation::pose::sample_skeleton;
+//! use amalia::vertex_computation::compute::prepare_mesh;
+//!
+//! fn read(path: &str) -> Vec<u8> {
+//!     let mut data = Vec::new();
+//!     File::open(path).unwrap().read_to_end(&mut data).unwrap();
+//!     data
+//! }
+//!
+//! fn main() {
+//!     let mesh = parse_md5mesh(&read("bob.md5mesh")).unwrap().1;
+//!     let anim = parse_anim(&read("bob.md5anim")).unwrap().1;
+//!
+//!     let skeleton = sample_skeleton(&anim, 1.0, true);
+//!     for m in &mesh.meshes {
+//!         let positions = prepare_mesh(m, &skeleton);
+//!         println!("{}: {} vertices", m.shader, positions.len());
+//!     }
+//! }
+//! ```
+
+extern crate nom;
+extern crate cgmath;
+extern crate rayon;
+extern crate memmap2;
+extern crate bytemuck;
+extern crate crc32fast;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+
+#[cfg(feature = "viewer")]
+extern crate winit;
+#[cfg(feature = "viewer")]
+#[macro_use]
+extern crate vulkano;
+#[cfg(feature = "viewer")]
+#[macro_use]
+extern crate vulkano_shader_derive;
+#[cfg(feature = "viewer")]
+extern crate vulkano_win;
+
+pub mod md5;
+pub mod animation;
+pub mod vertex_computation;
+pub mod mocap;
+pub mod loader;
+
+#[cfg(test)]
+pub mod test_support;
+
+#[cfg(feature = "viewer")]
+pub mod renderer;
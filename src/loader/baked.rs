@@ -0,0 +1,755 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
+use std::process;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+use bytemuck::{self, Pod};
+use cgmath::{Vector2, Vector3, Quaternion};
+use memmap2::Mmap;
+use md5::md5mesh::{Md5Mesh, Joint, Vertex, Mesh, Triangle, Weight};
+use md5::md5anim::{self, Md5Anim, Bound, BaseFrame, Frame};
+use animation::pose::frame_pose;
+use loader::error::{LoadError, Md5Kind};
+use loader::load::{Model, Md5File, load};
+use loader::material::{Material, resolve_material};
+use vertex_computation::skinning::SkinningMode;
+
+// A baked file is a header followed by a payload, all little-endian:
+//
+//     magic           8 bytes, MAGIC
+//     version         u32, VERSION
+//     kind            u32, 0 for a model and 1 for an animation
+//     source length   u64  \
+//     source seconds  u64   } SourceStamp of the file baked
+//     source nanos    u32  /
+//     checksum        u32, CRC-32 of the payload
+//     payload length  u64
+//
+// Every value in the payload is 4 bytes wide and strings are padded to 4
+// bytes, so the arrays in it stay aligned and are used in place.
+
+/// First bytes of every baked file.
+pub const MAGIC: [u8; 8] = *b"AMD5BAKE";
+
+/// Version of the layout. Files of any other version are stale and have
+/// to be baked again.
+pub const VERSION: u32 = 1;
+
+pub const HEADER_LEN: usize = 48;
+
+/// Extension added to the name of a source file for its baked file.
+pub const BAKED_EXTENSION: &str = "baked";
+
+/// Identifies the version of a source file a baked file was made from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct SourceStamp {
+    pub len: u64,
+    /// Modification time since the Unix epoch, zero where unsupported.
+    pub modified_secs: u64,
+    pub modified_nanos: u32,
+}
+
+impl SourceStamp {
+    pub fn of(path: &Path) -> io::Result<SourceStamp> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).unwrap_or_default();
+        Ok(SourceStamp { len: metadata.len(), modified_secs: modified.as_secs(), modified_nanos: modified.subsec_nanos() })
+    }
+}
+
+/// `source` with `BAKED_EXTENSION` appended: `bob.md5mesh.baked`.
+pub fn baked_path(source: &Path) -> PathBuf {
+    let mut name = source.as_os_str().to_owned();
+    name.push(".");
+    name.push(BAKED_EXTENSION);
+    PathBuf::from(name)
+}
+
+fn corrupt(message: &str) -> LoadError {
+    LoadError::Corrupt { message: message.to_string() }
+}
+
+struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn u32(&mut self, v: u32) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn i32(&mut self, v: i32) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn f32(&mut self, v: f32) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.len(s.len());
+        self.data.extend_from_slice(s.as_bytes());
+        let padded = self.data.len().div_ceil(4) * 4;
+        self.data.resize(padded, 0);
+    }
+
+    fn vector3(&mut self, v: Vector3<f32>) {
+        self.f32(v.x);
+        self.f32(v.y);
+        self.f32(v.z);
+    }
+
+    fn quaternion(&mut self, q: Quaternion<f32>) {
+        self.f32(q.s);
+        self.vector3(q.v);
+    }
+
+    /// Header and payload.
+    fn finish(self, kind: Md5Kind, source: SourceStamp) -> Vec<u8> {
+        let mut file = Vec::with_capacity(HEADER_LEN + self.data.len());
+        file.extend_from_slice(&MAGIC);
+        file.extend_from_slice(&VERSION.to_le_bytes());
+        file.extend_from_slice(&(kind as u32).to_le_bytes());
+        file.extend_from_slice(&source.len.to_le_bytes());
+        file.extend_from_slice(&source.modified_secs.to_le_bytes());
+        file.extend_from_slice(&source.modified_nanos.to_le_bytes());
+        file.extend_from_slice(&crc32fast::hash(&self.data).to_le_bytes());
+        file.extend_from_slice(&(self.data.len() as u64).to_le_bytes());
+        file.extend_from_slice(&self.data);
+        file
+    }
+}
+
+/// The baked file of `model`, made from a source file stamped `source`.
+pub fn bake_model(model: &Model, source: SourceStamp) -> Vec<u8> {
+    let mesh = &model.mesh;
+    let mut w = Writer { data: Vec::new() };
+    w.u32(u32::from(mesh.version));
+    w.string(&mesh.command_line);
+
+    w.len(mesh.joints.len());
+    for joint in &mesh.joints {
+        w.string(&joint.name);
+        w.i32(joint.parent_index);
+        w.vector3(joint.position);
+        w.quaternion(joint.orientation);
+    }
+
+    w.len(mesh.meshes.len());
+    for (i, m) in mesh.meshes.iter().enumerate() {
+        w.string(&m.shader);
+        w.len(m.vertices.len());
+        w.len(m.triangles.len());
+        w.len(m.weights.len());
+        for v in &m.vertices {
+            w.u32(v.index);
+            w.f32(v.tex_coords.x);
+            w.f32(v.tex_coords.y);
+            w.u32(v.start_weight);
+            w.u32(v.weight_count);
+        }
+        for t in &m.triangles {
+            w.u32(t.index);
+            w.u32(t.vertex_indices.0);
+            w.u32(t.vertex_indices.1);
+            w.u32(t.vertex_indices.2);
+        }
+        for weight in &m.weights {
+            w.u32(weight.index);
+            w.u32(weight.joint_index);
+            w.f32(weight.bias);
+            w.vector3(weight.position);
+        }
+
+        // Vertex and index buffers, ready to upload.
+        for p in &model.positions[i] {
+            w.vector3(*p);
+        }
+        for n in &model.normals[i] {
+            w.vector3(*n);
+        }
+        for v in &m.vertices {
+            w.f32(v.tex_coords.x);
+            w.f32(v.tex_coords.y);
+        }
+        for t in &m.triangles {
+            w.u32(t.vertex_indices.0);
+            w.u32(t.vertex_indices.1);
+            w.u32(t.vertex_indices.2);
+        }
+    }
+    w.finish(Md5Kind::Mesh, source)
+}
+
+/// The baked file of `anim`, with the pose of every frame decoded. `anim`
+/// must pass `validate_anim`, as those `load` returns do.
+pub fn bake_anim(anim: &Md5Anim, source: SourceStamp) -> Vec<u8> {
+    let mut w = Writer { data: Vec::new() };
+    w.i32(anim.version);
+    w.string(&anim.command_line);
+    w.i32(anim.num_frames);
+    w.i32(anim.num_joints);
+    w.i32(anim.frame_rate);
+    w.i32(anim.num_animated_components);
+
+    w.len(anim.hierarchies.len());
+    for joint in &anim.hierarchies {
+        w.string(&joint.name);
+        w.i32(joint.index);
+        w.i32(joint.flag);
+        w.i32(joint.start_index);
+    }
+
+    w.len(anim.bounds.len());
+    for bound in &anim.bounds {
+        w.vector3(bound.bound_min);
+        w.vector3(bound.bound_max);
+    }
+
+    w.len(anim.base_frame.position.len());
+    for (p, o) in anim.base_frame.position.iter().zip(&anim.base_frame.orientation) {
+        w.vector3(*p);
+        w.quaternion(*o);
+    }
+
+    w.len(anim.frames.len());
+    for frame in &anim.frames {
+        w.u32(frame.frame_number);
+        w.len(frame.frame_data.len());
+        for v in &frame.frame_data {
+            w.f32(*v);
+        }
+    }
+
+    // Decoded tracks: position then orientation of every joint, frame
+    // after frame.
+    let joint_count = anim.base_frame.position.len();
+    w.len(joint_count);
+    for i in 0..anim.frames.len() {
+        let pose = frame_pose(anim, i);
+        for (p, o) in pose.position.iter().zip(&pose.orientation) {
+            w.vector3(*p);
+            w.quaternion(*o);
+        }
+    }
+    w.finish(Md5Kind::Anim, source)
+}
+
+/// Reads the payload of a baked file in place.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len()).ok_or_else(|| corrupt("truncated"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn word(&mut self) -> Result<[u8; 4], LoadError> {
+        let bytes = self.bytes(4)?;
+        Ok([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        self.word().map(u32::from_le_bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, LoadError> {
+        self.word().map(i32::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> Result<f32, LoadError> {
+        self.word().map(f32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, LoadError> {
+        let bytes = self.bytes(8)?;
+        let mut word = [0; 8];
+        word.copy_from_slice(bytes);
+        Ok(u64::from_le_bytes(word))
+    }
+
+    fn len(&mut self) -> Result<usize, LoadError> {
+        self.u32().map(|v| v as usize)
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.len()?;
+        let bytes = self.bytes(len)?;
+        self.bytes((4 - len % 4) % 4)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| corrupt("invalid string"))
+    }
+
+    fn vector3(&mut self) -> Result<Vector3<f32>, LoadError> {
+        Ok(Vector3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn quaternion(&mut self) -> Result<Quaternion<f32>, LoadError> {
+        Ok(Quaternion::from_sv(self.f32()?, self.vector3()?))
+    }
+
+    /// `count` values used where they lie in the file.
+    fn slice<T: Pod>(&mut self, count: usize) -> Result<&'a [T], LoadError> {
+        let len = count.checked_mul(mem::size_of::<T>()).ok_or_else(|| corrupt("truncated"))?;
+        bytemuck::try_cast_slice(self.bytes(len)?).map_err(|_| corrupt("misaligned data"))
+    }
+}
+
+/// Where the bytes of a baked file live. Read files are kept in words so
+/// that the arrays in them are aligned like mapped ones.
+enum Storage {
+    Mapped(Mmap),
+    Owned { words: Vec<u32>, len: usize },
+}
+
+impl Storage {
+    fn bytes(&self) -> &[u8] {
+        match *self {
+            Storage::Mapped(ref map) => &map[..],
+            Storage::Owned { ref words, len } => &bytemuck::cast_slice(words)[..len],
+        }
+    }
+}
+
+/// A checked baked file. `model` and `anim` decode it; the vertex and
+/// index buffers and the decoded tracks are borrowed from it rather than
+/// copied.
+pub struct Baked {
+    storage: Storage,
+    kind: Md5Kind,
+    source: SourceStamp,
+}
+
+impl Baked {
+    /// Maps the baked file at `path` into memory.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Baked, LoadError> {
+        let file = File::open(path)?;
+        // Safety: the file must not be modified while mapped, as for every
+        // asset file read by the viewer; `bake_file` replaces baked files
+        // rather than writing into them. It is checked against its checksum
+        // once here.
+        let map = unsafe { Mmap::map(&file)? };
+        Baked::new(Storage::Mapped(map))
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> Result<Baked, LoadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Baked::from_bytes(&data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Baked, LoadError> {
+        let mut words = vec![0u32; data.len().div_ceil(4)];
+        bytemuck::cast_slice_mut(&mut words)[..data.len()].copy_from_slice(data);
+        Baked::new(Storage::Owned { words, len: data.len() })
+    }
+
+    fn new(storage: Storage) -> Result<Baked, LoadError> {
+        if cfg!(target_endian = "big") {
+            return Err(corrupt("baked files are only read on little-endian machines"));
+        }
+
+        let (kind, source) = {
+            let data = storage.bytes();
+            let mut header = Reader { data, pos: 0 };
+            if data.len() < HEADER_LEN || header.bytes(MAGIC.len())? != MAGIC {
+                return Err(corrupt("not a baked MD5 file"));
+            }
+            if header.u32()? != VERSION {
+                return Err(LoadError::Stale);
+            }
+            let kind = match header.u32()? {
+                0 => Md5Kind::Mesh,
+                1 => Md5Kind::Anim,
+                _ => return Err(corrupt("unknown kind"))
+            };
+            let source = SourceStamp { len: header.u64()?, modified_secs: header.u64()?, modified_nanos: header.u32()? };
+            let checksum = header.u32()?;
+            let payload_len = header.u64()?;
+
+            let payload = &data[HEADER_LEN..];
+            if payload.len() as u64 != payload_len {
+                return Err(corrupt("truncated"));
+            }
+            if crc32fast::hash(payload) != checksum {
+                return Err(corrupt("checksum mismatch"));
+            }
+            (kind, source)
+        };
+        Ok(Baked { storage, kind, source })
+    }
+
+    pub fn kind(&self) -> Md5Kind {
+        self.kind
+    }
+
+    /// Stamp of the source file this was baked from.
+    pub fn source(&self) -> SourceStamp {
+        self.source
+    }
+
+    fn payload(&self) -> Reader<'_> {
+        Reader { data: &self.storage.bytes()[HEADER_LEN..], pos: 0 }
+    }
+
+    pub fn model(&self) -> Result<BakedModel<'_>, LoadError> {
+        if self.kind != Md5Kind::Mesh {
+            return Err(LoadError::WrongKind { expected: Md5Kind::Mesh, found: self.kind });
+        }
+
+        let mut r = self.payload();
+        let version = r.u32()? as u8;
+        let command_line = r.string()?;
+
+        let joint_count = r.len()?;
+        let mut joints = Vec::new();
+        for _ in 0..joint_count {
+            joints.push(Joint { name: r.string()?, parent_index: r.i32()?, position: r.vector3()?, orientation: r.quaternion()? });
+        }
+
+        let mesh_count = r.len()?;
+        let mut meshes = Vec::new();
+        let mut buffers = Vec::new();
+        for _ in 0..mesh_count {
+            let shader = r.string()?;
+            let (vertex_count, triangle_count, weight_count) = (r.len()?, r.len()?, r.len()?);
+
+            let mut vertices = Vec::new();
+            for _ in 0..vertex_count {
+                vertices.push(Vertex {
+                    index: r.u32()?,
+                    tex_coords: Vector2::new(r.f32()?, r.f32()?),
+                    start_weight: r.u32()?,
+                    weight_count: r.u32()?
+                });
+            }
+            let mut triangles = Vec::new();
+            for _ in 0..triangle_count {
+                triangles.push(Triangle { index: r.u32()?, vertex_indices: (r.u32()?, r.u32()?, r.u32()?) });
+            }
+            let mut weights = Vec::new();
+            for _ in 0..weight_count {
+                weights.push(Weight { index: r.u32()?, joint_index: r.u32()?, bias: r.f32()?, position: r.vector3()? });
+            }
+
+            meshes.push(Mesh { shader, vertices, triangles, weights });
+            buffers.push(MeshBuffers {
+                positions: r.slice(vertex_count)?,
+                normals: r.slice(vertex_count)?,
+                tex_coords: r.slice(vertex_count)?,
+                indices: r.slice(triangle_count)?
+            });
+        }
+
+        Ok(BakedModel { mesh: Md5Mesh { version, command_line, joints, meshes }, buffers })
+    }
+
+    pub fn anim(&self) -> Result<BakedAnim<'_>, LoadError> {
+        if self.kind != Md5Kind::Anim {
+            return Err(LoadError::WrongKind { expected: Md5Kind::Anim, found: self.kind });
+        }
+
+        let mut r = self.payload();
+        let version = r.i32()?;
+        let command_line = r.string()?;
+        let (num_frames, num_joints, frame_rate, num_animated_components) = (r.i32()?, r.i32()?, r.i32()?, r.i32()?);
+
+        let joint_count = r.len()?;
+        let mut hierarchies = Vec::new();
+        for _ in 0..joint_count {
+            hierarchies.push(md5anim::Joint { name: r.string()?, index: r.i32()?, flag: r.i32()?, start_index: r.i32()? });
+        }
+
+        let bound_count = r.len()?;
+        let mut bounds = Vec::new();
+        for _ in 0..bound_count {
+            bounds.push(Bound { bound_min: r.vector3()?, bound_max: r.vector3()? });
+        }
+
+        let base_count = r.len()?;
+        let mut base_frame = BaseFrame { position: Vec::new(), orientation: Vec::new() };
+        for _ in 0..base_count {
+            base_frame.position.push(r.vector3()?);
+            base_frame.orientation.push(r.quaternion()?);
+        }
+
+        let frame_count = r.len()?;
+        let mut frames = Vec::new();
+        for _ in 0..frame_count {
+            let frame_number = r.u32()?;
+            let len = r.len()?;
+            frames.push(Frame { frame_number, frame_data: r.slice(len)?.to_vec() });
+        }
+
+        let track_joints = r.len()?;
+        let tracks = r.slice(frame_count.checked_mul(track_joints).ok_or_else(|| corrupt("truncated"))?)?;
+
+        let anim = Md5Anim {
+            version,
+            command_line,
+            num_frames,
+            num_joints,
+            frame_rate,
+            num_animated_components,
+            hierarchies,
+            bounds,
+            base_frame,
+            frames
+        };
+        Ok(BakedAnim { anim, joint_count: track_joints, tracks })
+    }
+}
+
+/// Buffers of one mesh, in the baked file.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MeshBuffers<'a> {
+    /// Bind pose vertex positions.
+    pub positions: &'a [[f32; 3]],
+    pub normals: &'a [[f32; 3]],
+    pub tex_coords: &'a [[f32; 2]],
+    pub indices: &'a [[u32; 3]],
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct BakedModel<'a> {
+    pub mesh: Md5Mesh,
+    /// In the order of `mesh.meshes`.
+    pub buffers: Vec<MeshBuffers<'a>>,
+}
+
+impl<'a> BakedModel<'a> {
+    /// The model, with its textures looked for in `texture_dir` when given.
+    pub fn to_model(&self, texture_dir: Option<&Path>) -> Model {
+        let vectors = |v: &[[f32; 3]]| v.iter().map(|&p| Vector3::from(p)).collect();
+        Model {
+            mesh: self.mesh.clone(),
+            positions: self.buffers.iter().map(|b| vectors(b.positions)).collect(),
+            normals: self.buffers.iter().map(|b| vectors(b.normals)).collect(),
+            materials: self.mesh.meshes.iter().map(|m| match texture_dir {
+                Some(dir) => resolve_material(dir, &m.shader),
+                None => Material { shader: m.shader.clone(), ..Material::default() }
+            }).collect(),
+            skinning: SkinningMode::default()
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct BakedAnim<'a> {
+    pub anim: Md5Anim,
+    joint_count: usize,
+    /// Position and orientation (w, x, y, z) of every joint in every frame.
+    tracks: &'a [[f32; 7]],
+}
+
+impl<'a> BakedAnim<'a> {
+    /// The decoded pose of frame `frame`, in the file.
+    pub fn pose_data(&self, frame: usize) -> &'a [[f32; 7]] {
+        &self.tracks[frame * self.joint_count..(frame + 1) * self.joint_count]
+    }
+
+    /// What `frame_pose` computes for `frame`, without decoding it.
+    pub fn pose(&self, frame: usize) -> BaseFrame {
+        let data = self.pose_data(frame);
+        BaseFrame {
+            position: data.iter().map(|j| Vector3::new(j[0], j[1], j[2])).collect(),
+            orientation: data.iter().map(|j| Quaternion::new(j[3], j[4], j[5], j[6])).collect()
+        }
+    }
+}
+
+/// Numbers the temporary files of `bake_file`, which threads of one process
+/// may call at the same time.
+static NEXT_TEMPORARY: AtomicUsize = AtomicUsize::new(0);
+
+/// Parses the MD5 file at `source` and writes its baked file to `target`.
+///
+/// The file is written next to `target` and renamed over it, so that a
+/// `Baked` still mapping the previous version keeps reading it unchanged.
+pub fn bake_file(source: &Path, target: &Path) -> Result<Md5Kind, LoadError> {
+    let stamp = SourceStamp::of(source)?;
+    let (kind, data) = match load(source)? {
+        Md5File::Mesh(model) => (Md5Kind::Mesh, bake_model(&model, stamp)),
+        Md5File::Anim(anim) => (Md5Kind::Anim, bake_anim(&anim, stamp))
+    };
+
+    let mut temporary = target.as_os_str().to_owned();
+    temporary.push(format!(".{}.{}.tmp", process::id(), NEXT_TEMPORARY.fetch_add(1, Ordering::Relaxed)));
+    let temporary = PathBuf::from(temporary);
+    let written = fs::write(&temporary, data).and_then(|_| fs::rename(&temporary, target));
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    written?;
+    Ok(kind)
+}
+
+/// Opens the baked file at `baked`, which must have been made from the
+/// current version of `source`. Its `model` or `anim` borrow their buffers
+/// from the mapped file; textures of a model are in the directory of
+/// `source`.
+pub fn load_baked(source: &Path, baked: &Path) -> Result<Baked, LoadError> {
+    let file = Baked::open(baked)?;
+    if file.source() != SourceStamp::of(source)? {
+        return Err(LoadError::Stale);
+    }
+    Ok(file)
+}
+
+/// Opens the baked file next to `source`, baking it first when it is
+/// missing, stale or corrupt.
+pub fn load_or_bake<P: AsRef<Path>>(source: P) -> Result<Baked, LoadError> {
+    let source = source.as_ref();
+    let baked = baked_path(source);
+    match load_baked(source, &baked) {
+        Err(LoadError::Io(_)) | Err(LoadError::Stale) | Err(LoadError::Corrupt { .. }) if source.exists() => {
+            bake_file(source, &baked)?;
+            load_baked(source, &baked)
+        },
+        result => result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use animation::pose::frame_pose;
+    use loader::error::{LoadError, Md5Kind};
+    use loader::load::{load_model, load_anim};
+    use std::thread;
+    use super::{Baked, SourceStamp, bake_model, bake_anim, bake_file, baked_path, load_baked, load_or_bake, HEADER_LEN};
+
+    const MESH: &str = "./Resources/bob_lamp_update/bob_lamp_update_export.md5mesh";
+    const ANIM: &str = "./Resources/bob_lamp_update/bob_lamp_update_export.md5anim";
+
+    #[test]
+    fn model_round_trip() {
+        let model = load_model(MESH).unwrap();
+        let data = bake_model(&model, SourceStamp::default());
+        let baked = Baked::from_bytes(&data).unwrap();
+        assert_eq!(baked.kind(), Md5Kind::Mesh);
+
+        let read = baked.model().unwrap();
+        assert_eq!(read.mesh, model.mesh);
+        assert_eq!(read.to_model(None).positions, model.positions);
+        assert_eq!(read.to_model(None).normals, model.normals);
+        let buffers = &read.buffers[0];
+        assert_eq!(buffers.indices.len(), model.mesh.meshes[0].triangles.len());
+        assert_eq!(buffers.tex_coords[3], [model.mesh.meshes[0].vertices[3].tex_coords.x, model.mesh.meshes[0].vertices[3].tex_coords.y]);
+    }
+
+    #[test]
+    fn anim_round_trip() {
+        let anim = load_anim(ANIM).unwrap();
+        let data = bake_anim(&anim, SourceStamp::default());
+        let baked = Baked::from_bytes(&data).unwrap();
+        match baked.model() {
+            Err(LoadError::WrongKind { expected: Md5Kind::Mesh, found: Md5Kind::Anim }) => (),
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+
+        let read = baked.anim().unwrap();
+        assert_eq!(read.anim, anim);
+        for i in &[0, 70, 140] {
+            assert_eq!(read.pose(*i), frame_pose(&anim, *i));
+        }
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let anim = load_anim(ANIM).unwrap();
+        let data = bake_anim(&anim, SourceStamp::default());
+        let check = |data: &[u8]| match Baked::from_bytes(data) {
+            Err(e @ LoadError::Corrupt { .. }) | Err(e @ LoadError::Stale) => e,
+            other => panic!("{:?}", other.map(|_| ()))
+        };
+
+        let mut flipped = data.clone();
+        flipped[HEADER_LEN + 1000] ^= 1;
+        assert!(check(&flipped).to_string().contains("checksum"));
+        assert!(check(&data[..data.len() - 4]).to_string().contains("truncated"));
+        assert!(check(b"MD5Version 10").to_string().contains("not a baked"));
+
+        let mut old = data.clone();
+        old[8] = 0;
+        match check(&old) {
+            LoadError::Stale => (),
+            other => panic!("{:?}", other)
+        }
+    }
+
+    #[test]
+    fn bakes_next_to_source() {
+        let dir = env::temp_dir().join(format!("amalia_baked_{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("bob.md5mesh");
+        fs::copy(MESH, &source).unwrap();
+        let baked = baked_path(&source);
+        assert_eq!(baked, dir.join("bob.md5mesh.baked"));
+
+        match load_baked(&source, &baked) {
+            Err(LoadError::Io(_)) => (),
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+        let file = load_or_bake(&source).unwrap();
+        assert_eq!(file.kind(), Md5Kind::Mesh);
+        assert_eq!(file.model().unwrap().mesh, load_model(MESH).unwrap().mesh);
+        assert!(load_baked(&source, &baked).is_ok());
+
+        // Editing the source makes the baked file stale. Baking it again
+        // replaces the file, leaving the one still mapped untouched.
+        let edited = fs::read_to_string(MESH).unwrap().replace("by Paul Zirkle", "by Paul Zirkle, edited");
+        fs::write(&source, edited).unwrap();
+        match load_baked(&source, &baked) {
+            Err(LoadError::Stale) => (),
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+        let rebaked = load_or_bake(&source).unwrap();
+        assert!(rebaked.model().unwrap().mesh.command_line.ends_with("edited"));
+        assert!(!file.model().unwrap().mesh.command_line.ends_with("edited"));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bakes_concurrently() {
+        let dir = env::temp_dir().join(format!("amalia_baked_threads_{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("bob.md5anim");
+        fs::copy(ANIM, &source).unwrap();
+        let baked = baked_path(&source);
+
+        // Threads baking the same file each write their own temporary file.
+        let bakes: Vec<_> = (0..4).map(|_| {
+            let (source, baked) = (source.clone(), baked.clone());
+            thread::spawn(move || bake_file(&source, &baked))
+        }).collect();
+        for bake in bakes {
+            assert_eq!(bake.join().unwrap().unwrap(), Md5Kind::Anim);
+        }
+        assert!(load_baked(&source, &baked).is_ok());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        // What `load` rejects is not baked.
+        let edited = fs::read_to_string(ANIM).unwrap().replacen("numAnimatedComponents 198", "numAnimatedComponents 200", 1);
+        fs::write(&source, edited).unwrap();
+        match bake_file(&source, &baked) {
+            Err(LoadError::Invalid { kind: Md5Kind::Anim, .. }) => (),
+            other => panic!("{:?}", other)
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,354 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use md5::md5anim::Md5Anim;
+use loader::error::LoadError;
+use loader::load::{Model, load_model, load_anim};
+
+/// Raw contents of an image file. Decoding is left to the renderer, which
+/// knows which formats it supports.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Texture {
+    pub data: Vec<u8>,
+}
+
+/// What is compared to tell whether a file changed on disk: exporters do
+/// not always move the modification time forward by a visible amount.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    fs::metadata(path).ok().map(|m| Stamp { modified: m.modified().ok(), len: m.len() })
+}
+
+struct Cached<T> {
+    value: Arc<T>,
+    version: u64,
+    stamp: Option<Stamp>,
+    reloading: bool,
+}
+
+/// Cached assets of one type, and the channel bringing their reloads back
+/// from the background threads.
+pub struct Store<T> {
+    entries: HashMap<PathBuf, Cached<T>>,
+    sender: Sender<(PathBuf, Option<Stamp>, Result<T, LoadError>)>,
+    receiver: Receiver<(PathBuf, Option<Stamp>, Result<T, LoadError>)>,
+}
+
+impl<T> Default for Store<T> {
+    fn default() -> Store<T> {
+        let (sender, receiver) = channel();
+        Store { entries: HashMap::new(), sender, receiver }
+    }
+}
+
+/// Something the cache can hold.
+pub trait Asset: Sized + Send + 'static {
+    fn load(path: &Path) -> Result<Self, LoadError>;
+    fn store(cache: &mut AssetCache) -> &mut Store<Self>;
+}
+
+impl Asset for Model {
+    fn load(path: &Path) -> Result<Model, LoadError> {
+        load_model(path)
+    }
+
+    fn store(cache: &mut AssetCache) -> &mut Store<Model> {
+        &mut cache.models
+    }
+}
+
+impl Asset for Md5Anim {
+    fn load(path: &Path) -> Result<Md5Anim, LoadError> {
+        load_anim(path)
+    }
+
+    fn store(cache: &mut AssetCache) -> &mut Store<Md5Anim> {
+        &mut cache.anims
+    }
+}
+
+impl Asset for Texture {
+    fn load(path: &Path) -> Result<Texture, LoadError> {
+        Ok(Texture { data: fs::read(path)? })
+    }
+
+    fn store(cache: &mut AssetCache) -> &mut Store<Texture> {
+        &mut cache.textures
+    }
+}
+
+/// Outcome of reloading a changed file. On failure the cache keeps serving
+/// the last version that loaded.
+#[derive(Debug)]
+pub struct ReloadEvent {
+    pub path: PathBuf,
+    /// The new version number, or why the file was not swapped in.
+    pub result: Result<u64, LoadError>,
+}
+
+/// Models, animations and textures by path, reloaded when their file
+/// changes.
+///
+/// Call `update` once per frame: it swaps in the assets reparsed since the
+/// last call and starts reparsing, on background threads, the files
+/// modified since. Holders of an `Arc` from a previous version keep it
+/// alive until they fetch the new one with `get`.
+pub struct AssetCache {
+    models: Store<Model>,
+    anims: Store<Md5Anim>,
+    textures: Store<Texture>,
+    /// Shortest time between two checks of the files on disk.
+    pub poll_interval: Duration,
+    last_poll: Option<Instant>,
+}
+
+impl Default for AssetCache {
+    fn default() -> AssetCache {
+        AssetCache {
+            models: Store::default(),
+            anims: Store::default(),
+            textures: Store::default(),
+            poll_interval: Duration::from_millis(250),
+            last_poll: None
+        }
+    }
+}
+
+impl AssetCache {
+    pub fn new() -> AssetCache {
+        AssetCache::default()
+    }
+
+    /// The asset at `path`, loaded now if it is not cached yet.
+    pub fn get<T: Asset>(&mut self, path: &Path) -> Result<Arc<T>, LoadError> {
+        let store = T::store(self);
+        if let Some(cached) = store.entries.get(path) {
+            return Ok(cached.value.clone());
+        }
+
+        let stamp = stamp(path);
+        let value = Arc::new(T::load(path)?);
+        store.entries.insert(path.to_path_buf(), Cached { value: value.clone(), version: 0, stamp, reloading: false });
+        Ok(value)
+    }
+
+    pub fn model(&mut self, path: &Path) -> Result<Arc<Model>, LoadError> {
+        self.get(path)
+    }
+
+    pub fn anim(&mut self, path: &Path) -> Result<Arc<Md5Anim>, LoadError> {
+        self.get(path)
+    }
+
+    pub fn texture(&mut self, path: &Path) -> Result<Arc<Texture>, LoadError> {
+        self.get(path)
+    }
+
+    /// Number of reloads of the asset at `path` swapped in so far.
+    pub fn version<T: Asset>(&mut self, path: &Path) -> Option<u64> {
+        T::store(self).entries.get(path).map(|cached| cached.version)
+    }
+
+    /// Drops the asset at `path`, which stops watching its file.
+    pub fn remove<T: Asset>(&mut self, path: &Path) -> Option<Arc<T>> {
+        T::store(self).entries.remove(path).map(|cached| cached.value)
+    }
+
+    /// Swaps in finished reloads and starts new ones. Returns what was
+    /// reloaded since the last call, successfully or not.
+    pub fn update(&mut self) -> Vec<ReloadEvent> {
+        let mut events = Vec::new();
+        receive(&mut self.models, &mut events);
+        receive(&mut self.anims, &mut events);
+        receive(&mut self.textures, &mut events);
+
+        let now = Instant::now();
+        if self.last_poll.is_none_or(|last| now.duration_since(last) >= self.poll_interval) {
+            self.last_poll = Some(now);
+            poll(&mut self.models);
+            poll(&mut self.anims);
+            poll(&mut self.textures);
+        }
+        events
+    }
+}
+
+fn receive<T>(store: &mut Store<T>, events: &mut Vec<ReloadEvent>) {
+    loop {
+        let (path, stamp, result) = match store.receiver.try_recv() {
+            Ok(received) => received,
+            Err(TryRecvError::Empty) => return,
+            // No reload can come back any more: let `poll` start new ones.
+            Err(TryRecvError::Disconnected) => {
+                for cached in store.entries.values_mut() {
+                    cached.reloading = false;
+                }
+                return;
+            }
+        };
+        let cached = match store.entries.get_mut(&path) {
+            Some(cached) => cached,
+            // Removed while reloading.
+            None => continue
+        };
+        cached.reloading = false;
+        // A failed load stays recorded as seen, so it is not retried until
+        // the file changes again.
+        cached.stamp = stamp;
+
+        let result = result.map(|value| {
+            cached.value = Arc::new(value);
+            cached.version += 1;
+            cached.version
+        });
+        events.push(ReloadEvent { path, result });
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "unknown panic".to_string()
+    }
+}
+
+fn poll<T: Asset>(store: &mut Store<T>) {
+    for (path, cached) in store.entries.iter_mut() {
+        if cached.reloading {
+            continue;
+        }
+        let current = stamp(path);
+        if current == cached.stamp {
+            continue;
+        }
+
+        cached.reloading = true;
+        let sender = store.sender.clone();
+        let path = path.clone();
+        thread::spawn(move || {
+            // A panicking load must still report back, or the file would
+            // stay marked as reloading and never be looked at again.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| T::load(&path)))
+                .unwrap_or_else(|payload| Err(LoadError::Panicked { message: panic_message(&*payload) }));
+            // The cache may be gone; nothing to do then.
+            let _ = sender.send((path, current, result));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use loader::error::LoadError;
+    use super::{AssetCache, Asset, ReloadEvent, Store, Cached};
+
+    const MESH: &str = "./Resources/bob_lamp_update/bob_lamp_update_export.md5mesh";
+
+    fn wait_for_reload(cache: &mut AssetCache) -> ReloadEvent {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(10) {
+            if let Some(event) = cache.update().pop() {
+                return event;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("no reload");
+    }
+
+    #[test]
+    fn hot_reload() {
+        let dir = env::temp_dir().join(format!("amalia_cache_{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bob.md5mesh");
+        let original = fs::read_to_string(MESH).unwrap();
+        fs::write(&path, &original).unwrap();
+
+        let mut cache = AssetCache::new();
+        cache.poll_interval = Duration::from_millis(0);
+        let first = cache.model(&path).unwrap();
+        assert!(cache.update().is_empty());
+        assert!(::std::sync::Arc::ptr_eq(&first, &cache.model(&path).unwrap()));
+
+        // A broken export keeps the last good version.
+        fs::write(&path, &original[..original.len() / 2]).unwrap();
+        match wait_for_reload(&mut cache).result {
+            Err(LoadError::Parse { .. }) => (),
+            other => panic!("{:?}", other)
+        }
+        assert!(::std::sync::Arc::ptr_eq(&first, &cache.model(&path).unwrap()));
+        assert_eq!(cache.version::<super::Model>(&path), Some(0));
+
+        let edited = original.replace("by Paul Zirkle", "by Paul Zirkle, edited");
+        fs::write(&path, &edited).unwrap();
+        assert_eq!(wait_for_reload(&mut cache).result.unwrap(), 1);
+        let second = cache.model(&path).unwrap();
+        assert!(second.mesh.command_line.ends_with("edited"));
+        assert_eq!(second.mesh.meshes, first.mesh.meshes);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_file() {
+        let mut cache = AssetCache::new();
+        match cache.texture(Path::new("./Resources/missing.tga")) {
+            Err(LoadError::Io(_)) => (),
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+    }
+
+    struct Panicky;
+
+    impl Asset for Panicky {
+        fn load(_: &Path) -> Result<Panicky, LoadError> {
+            panic!("bad asset");
+        }
+
+        fn store(_: &mut AssetCache) -> &mut Store<Panicky> {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn panicking_reload() {
+        let mut store = Store::default();
+        store.entries.insert(Path::new(MESH).to_path_buf(), Cached { value: ::std::sync::Arc::new(Panicky), version: 0, stamp: None, reloading: false });
+
+        super::poll(&mut store);
+        let mut events = Vec::new();
+        let start = Instant::now();
+        while events.is_empty() && start.elapsed() < Duration::from_secs(10) {
+            thread::sleep(Duration::from_millis(10));
+            super::receive(&mut store, &mut events);
+        }
+        match events.pop().map(|event| event.result) {
+            Some(Err(LoadError::Panicked { ref message })) if message == "bad asset" => (),
+            other => panic!("{:?}", other)
+        }
+        assert!(!store.entries[Path::new(MESH)].reloading);
+
+        // Reloads that can no longer come back do not block the next ones.
+        store.entries.get_mut(Path::new(MESH)).unwrap().reloading = true;
+        let (sender, receiver) = ::std::sync::mpsc::channel();
+        drop(sender);
+        store.receiver = receiver;
+        super::receive(&mut store, &mut events);
+        assert!(!store.entries[Path::new(MESH)].reloading);
+    }
+}
@@ -0,0 +1,75 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// The two kinds of MD5 files.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Md5Kind {
+    Mesh,
+    Anim,
+}
+
+impl fmt::Display for Md5Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Md5Kind::Mesh => write!(f, "md5mesh"),
+            Md5Kind::Anim => write!(f, "md5anim"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The data does not start with an MD5 header, or the header names
+    /// neither meshes nor frames.
+    UnknownFormat,
+    /// A file of one kind was loaded where the other was expected.
+    WrongKind { expected: Md5Kind, found: Md5Kind },
+    /// The header was recognized but the rest did not parse.
+    Parse { kind: Md5Kind, message: String },
+    /// The file parsed but refers to joints, weights, vertices or animated
+    /// components that do not exist.
+    Invalid { kind: Md5Kind, message: String },
+    /// A baked file was written by another version of the format, or from
+    /// a source file that changed since.
+    Stale,
+    /// A baked file is damaged: wrong magic, bad checksum or truncated.
+    Corrupt { message: String },
+    /// Loading panicked, e.g. on a background reload; the message is the
+    /// panic's.
+    Panicked { message: String },
+    /// The clip sidecar of an md5anim did not parse.
+    Sidecar { message: String },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref e) => write!(f, "could not read MD5 file: {}", e),
+            LoadError::UnknownFormat => write!(f, "not an md5mesh or md5anim file"),
+            LoadError::WrongKind { expected, found } => write!(f, "expected an {} file, found an {} file", expected, found),
+            LoadError::Parse { kind, ref message } => write!(f, "invalid {} file: {}", kind, message),
+            LoadError::Invalid { kind, ref message } => write!(f, "inconsistent {} file: {}", kind, message),
+            LoadError::Stale => write!(f, "baked file is out of date"),
+            LoadError::Corrupt { ref message } => write!(f, "corrupt baked file: {}", message),
+            LoadError::Panicked { ref message } => write!(f, "loading panicked: {}", message),
+            LoadError::Sidecar { ref message } => write!(f, "invalid sidecar file: {}", message),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            LoadError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
@@ -0,0 +1,299 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use cgmath::Vector3;
+use nom::branch::alt;
+use nom::bytes::complete::{tag, is_not};
+use nom::character::complete::alphanumeric1;
+use nom::combinator::{opt, value, all_consuming};
+use nom::sequence::tuple;
+use md5::md5mesh::{Md5Mesh, Joint};
+use md5::md5anim::Md5Anim;
+use md5::md5mesh_parser::parse_md5mesh;
+use md5::md5anim_parser::parse_anim;
+use md5::md5common_parser::{ParseResult, error_message, blank, ws, keyword, byte_order_mark, escaped_string};
+use vertex_computation::compute::{prepare_mesh, prepare_normals};
+use vertex_computation::skinning::{SkinningMode, FullMesh, skin_mesh, skin_full_mesh};
+use animation::sidecar::Sidecar;
+use animation::sidecar_parser::parse_sidecar;
+use loader::error::{LoadError, Md5Kind};
+use loader::material::{Material, resolve_material};
+use loader::validate::{validate_mesh, validate_anim};
+
+/// A parsed md5mesh ready to draw: vertices and normals, in the bind pose
+/// until `skin` poses them, and the textures of every mesh, in the order of
+/// `mesh.meshes`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Model {
+    pub mesh: Md5Mesh,
+    pub positions: Vec<Vec<Vector3<f32>>>,
+    pub normals: Vec<Vec<Vector3<f32>>>,
+    pub materials: Vec<Material>,
+    /// How `skin` and `skin_full` pose the model.
+    pub skinning: SkinningMode,
+}
+
+impl Model {
+    /// Prepares `mesh`, looking for its textures in `texture_dir` when
+    /// given.
+    pub fn new(mesh: Md5Mesh, texture_dir: Option<&Path>) -> Model {
+        let positions: Vec<Vec<Vector3<f32>>> = mesh.meshes.iter().map(|m| prepare_mesh(m, &mesh.joints)).collect();
+        let normals = mesh.meshes.iter().zip(&positions).map(|(m, p)| prepare_normals(m, p)).collect();
+        let materials = mesh.meshes.iter().map(|m| match texture_dir {
+            Some(dir) => resolve_material(dir, &m.shader),
+            None => Material { shader: m.shader.clone(), ..Material::default() }
+        }).collect();
+
+        Model { mesh, positions, normals, materials, skinning: SkinningMode::default() }
+    }
+
+    pub fn with_skinning(mut self, mode: SkinningMode) -> Model {
+        self.skinning = mode;
+        self
+    }
+
+    /// Poses the model: `positions` and `normals` are replaced by those of
+    /// every mesh skinned by `posed` with `self.skinning`.
+    pub fn skin(&mut self, posed: &Vec<Joint>) {
+        self.positions = self.mesh.meshes.iter()
+            .map(|m| skin_mesh(m, &self.mesh.joints, posed, self.skinning))
+            .collect();
+        self.normals = self.mesh.meshes.iter().zip(&self.positions).map(|(m, p)| prepare_normals(m, p)).collect();
+    }
+
+    /// `skin_full_mesh` with `self.skinning`.
+    pub fn skin_full(&self, posed: &Vec<Joint>) -> FullMesh {
+        skin_full_mesh(&self.mesh, posed, self.skinning)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Md5File {
+    Mesh(Model),
+    Anim(Md5Anim),
+}
+
+/// Kind of MD5 file `data` holds, from the counts in its header.
+pub fn detect_kind(data: &[u8]) -> Option<Md5Kind> {
+    let (mut input, _) = tuple((opt(byte_order_mark), blank, keyword("MD5Version")))(data).ok()?;
+
+    // The header is made of a name and a value per field, the value quoted
+    // for commandline, and ends where the first block opens.
+    loop {
+        let (rest, _) = ws(alt((value((), escaped_string), value((), is_not(" \t\r\n")))))(input).ok()?;
+        let (rest, name) = ws(alphanumeric1)(rest).ok()?;
+        match name {
+            b"numMeshes" => return Some(Md5Kind::Mesh),
+            b"numFrames" => return Some(Md5Kind::Anim),
+            _ if tag::<_, _, ()>("{")(rest).is_ok() => return None,
+            _ => input = rest
+        }
+    }
+}
+
+fn parse_error<T>(kind: Md5Kind, data: &[u8], result: ParseResult<'_, T>) -> Result<T, LoadError> {
+    result.map(|(_, value)| value).map_err(|e| LoadError::Parse { kind, message: error_message(data, &e) })
+}
+
+fn invalid(kind: Md5Kind, result: Result<(), String>) -> Result<(), LoadError> {
+    result.map_err(|message| LoadError::Invalid { kind, message })
+}
+
+/// Parses md5mesh or md5anim data, whichever it is. Textures of a model are
+/// looked for in `texture_dir`.
+///
+/// Indices are checked with `validate_mesh` and `validate_anim`, so what
+/// this returns can be skinned and posed without panicking.
+pub fn parse_md5(data: &[u8], texture_dir: Option<&Path>) -> Result<Md5File, LoadError> {
+    match detect_kind(data) {
+        Some(Md5Kind::Mesh) => {
+            let mesh = parse_error(Md5Kind::Mesh, data, parse_md5mesh(data))?;
+            invalid(Md5Kind::Mesh, validate_mesh(&mesh))?;
+            Ok(Md5File::Mesh(Model::new(mesh, texture_dir)))
+        },
+        Some(Md5Kind::Anim) => {
+            let anim = parse_error(Md5Kind::Anim, data, parse_anim(data))?;
+            invalid(Md5Kind::Anim, validate_anim(&anim))?;
+            Ok(Md5File::Anim(anim))
+        },
+        None => Err(LoadError::UnknownFormat)
+    }
+}
+
+pub fn read_md5<R: Read>(mut reader: R, texture_dir: Option<&Path>) -> Result<Md5File, LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    parse_md5(&data, texture_dir)
+}
+
+/// Loads the MD5 file at `path`, resolving the textures of a model in its
+/// directory.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Md5File, LoadError> {
+    let path = path.as_ref();
+    read_md5(File::open(path)?, Some(path.parent().unwrap_or_else(|| Path::new("."))))
+}
+
+pub fn load_model<P: AsRef<Path>>(path: P) -> Result<Model, LoadError> {
+    match load(path)? {
+        Md5File::Mesh(model) => Ok(model),
+        Md5File::Anim(_) => Err(LoadError::WrongKind { expected: Md5Kind::Mesh, found: Md5Kind::Anim })
+    }
+}
+
+pub fn load_anim<P: AsRef<Path>>(path: P) -> Result<Md5Anim, LoadError> {
+    match load(path)? {
+        Md5File::Anim(anim) => Ok(anim),
+        Md5File::Mesh(_) => Err(LoadError::WrongKind { expected: Md5Kind::Anim, found: Md5Kind::Mesh })
+    }
+}
+
+/// Extension of the clip sidecar of an md5anim.
+pub const SIDECAR_EXTENSION: &str = "clips";
+
+/// Where the sidecar of the md5anim at `anim_path` sits: `bob.md5anim` is
+/// described by `bob.clips`.
+pub fn sidecar_path<P: AsRef<Path>>(anim_path: P) -> PathBuf {
+    anim_path.as_ref().with_extension(SIDECAR_EXTENSION)
+}
+
+/// Loads the sidecar next to the md5anim at `anim_path`. An md5anim without
+/// one has neither clips nor events.
+pub fn load_sidecar<P: AsRef<Path>>(anim_path: P) -> Result<Sidecar, LoadError> {
+    let data = match fs::read(sidecar_path(anim_path)) {
+        Ok(data) => data,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Sidecar::default()),
+        Err(e) => return Err(LoadError::Io(e))
+    };
+    let parsed = all_consuming(parse_sidecar)(&data).map(|(_, sidecar)| sidecar);
+    parsed.map_err(|e| LoadError::Sidecar { message: error_message(&data, &e) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use animation::sidecar::Sidecar;
+    use loader::error::{LoadError, Md5Kind};
+    use vertex_computation::skinning::{SkinningMode, prepare_mesh_dual_quaternion};
+    use test_support::bob_skeleton;
+    use super::{Md5File, load, load_model, load_anim, read_md5, load_sidecar};
+
+    const MESH: &str = "./Resources/bob_lamp_update/bob_lamp_update_export.md5mesh";
+    const ANIM: &str = "./Resources/bob_lamp_update/bob_lamp_update_export.md5anim";
+
+    #[test]
+    fn detect_kind() {
+        assert_eq!(super::detect_kind(b"MD5Version 10\ncommandline \"\"\n\nnumJoints 2\nnumMeshes 1\n\njoints {"), Some(Md5Kind::Mesh));
+        assert_eq!(super::detect_kind(b"\n MD5Version 10\ncommandline \"\"\nnumFrames 3\nnumJoints 2\n"), Some(Md5Kind::Anim));
+        assert_eq!(super::detect_kind(b"MD5Version 10\njoints { numMeshes"), None);
+        assert_eq!(super::detect_kind(b"\xef\xbb\xbfMD5Version 10\ncommandline \"\"\nnumFrames 3\n"), Some(Md5Kind::Anim));
+        assert_eq!(super::detect_kind(b"// exported by hand\r\nMD5Version 10 // version\nnumJoints 2\nnumMeshes 1\n"), Some(Md5Kind::Mesh));
+        // Counts named inside the command line are not header fields.
+        assert_eq!(super::detect_kind(b"MD5Version 10\ncommandline \"-numMeshes 2 { numMeshes\"\nnumFrames 3\n"), Some(Md5Kind::Anim));
+        assert_eq!(super::detect_kind(b"HIERARCHY\nROOT Hips"), None);
+    }
+
+    #[test]
+    fn load_model_with_materials() {
+        let model = load_model(MESH).unwrap();
+        assert_eq!(model.positions.len(), model.mesh.meshes.len());
+        assert_eq!(model.normals[0].len(), model.mesh.meshes[0].vertices.len());
+
+        let body = &model.materials[0];
+        assert_eq!(body.shader, "bob_body");
+        assert_eq!(body.diffuse.as_deref(), Some(Path::new("./Resources/bob_lamp_update/bob_body.tga")));
+        assert_eq!(body.normal.as_deref(), Some(Path::new("./Resources/bob_lamp_update/bob_body_local.tga")));
+        assert!(body.specular.is_some() && body.height.is_some());
+
+        // lantern has no specular map.
+        assert!(model.materials[3].diffuse.is_some() && model.materials[3].specular.is_none());
+    }
+
+    #[test]
+    fn skinning_mode() {
+        let mut model = load_model(MESH).unwrap();
+        assert_eq!(model.skinning, SkinningMode::LinearBlend);
+        let skeleton = bob_skeleton(60);
+        model.skin(&skeleton);
+        let linear = model.positions[0].clone();
+
+        let mut model = model.with_skinning(SkinningMode::DualQuaternion);
+        model.skin(&skeleton);
+        assert_eq!(model.positions[0], prepare_mesh_dual_quaternion(&model.mesh.meshes[0], &model.mesh.joints, &skeleton));
+        assert_ne!(model.positions[0], linear);
+        assert_eq!(model.normals[0].len(), model.positions[0].len());
+        assert_eq!(model.skin_full(&skeleton).0.len(), model.positions.iter().map(|p| p.len()).sum::<usize>());
+    }
+
+    #[test]
+    fn load_either_kind() {
+        match load(ANIM).unwrap() {
+            Md5File::Anim(anim) => assert_eq!(anim.frames.len(), 141),
+            Md5File::Mesh(_) => panic!("loaded the animation as a model")
+        }
+        match load_anim(MESH) {
+            Err(LoadError::WrongKind { expected: Md5Kind::Anim, found: Md5Kind::Mesh }) => (),
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn errors() {
+        match load("./Resources/missing.md5mesh") {
+            Err(LoadError::Io(_)) => (),
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+        match read_md5(&b"not md5"[..], None) {
+            Err(LoadError::UnknownFormat) => (),
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+        match read_md5(&b"MD5Version 10\ncommandline \"\"\nnumJoints 1\nnumMeshes 1\njoints {\n\t\"origin\" -1 ( 0 0"[..], None) {
+            Err(LoadError::Parse { kind: Md5Kind::Mesh, .. }) => (),
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn dangling_indices() {
+        let mesh = fs::read_to_string(MESH).unwrap();
+        for (original, edited) in [("weight 0 16 0.333333", "weight 0 40 0.333333"),
+                                   ("tri 0 0 2 1", "tri 0 0 2 489")] {
+            match read_md5(mesh.replacen(original, edited, 1).as_bytes(), None) {
+                Err(LoadError::Invalid { kind: Md5Kind::Mesh, .. }) => (),
+                other => panic!("{}: {:?}", edited, other.map(|_| ()))
+            }
+        }
+
+        let anim = fs::read_to_string(ANIM).unwrap();
+        for (original, edited) in [("\"pelvis\"\t3 63 24", "\"pelvis\"\t3 63 195"),
+                                   ("numAnimatedComponents 198", "numAnimatedComponents 200")] {
+            match read_md5(anim.replacen(original, edited, 1).as_bytes(), None) {
+                Err(LoadError::Invalid { kind: Md5Kind::Anim, .. }) => (),
+                other => panic!("{}: {:?}", edited, other.map(|_| ()))
+            }
+        }
+    }
+
+    #[test]
+    fn sidecar() {
+        let dir = env::temp_dir().join(format!("amalia_sidecar_{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let anim = dir.join("bob.md5anim");
+        assert_eq!(load_sidecar(&anim).unwrap(), Sidecar::default());
+
+        fs::write(dir.join("bob.clips"), "// bob\nclip \"idle\" 0 40\nclip \"swing\" 41 140\nevent \"lamp_hit\" 75\n").unwrap();
+        let sidecar = load_sidecar(&anim).unwrap();
+        assert_eq!(sidecar.clips.len(), 2);
+        assert_eq!(sidecar.clips[1].name, "swing");
+        assert_eq!(sidecar.events.events[0].frame, 75);
+
+        fs::write(dir.join("bob.clips"), "clip \"idle\" 0 40\nloop \"idle\"\n").unwrap();
+        match load_sidecar(&anim) {
+            Err(LoadError::Sidecar { .. }) => (),
+            other => panic!("{:?}", other)
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
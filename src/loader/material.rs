@@ -0,0 +1,47 @@
+use std::path::{Path, PathBuf};
+
+/// Image formats looked for, in order of preference.
+pub const TEXTURE_EXTENSIONS: [&str; 5] = ["tga", "png", "jpg", "jpeg", "dds"];
+
+/// Textures found for the shader of a mesh. Doom 3 names them after the
+/// shader: `name` for the diffuse map, `name_local` for the normal map,
+/// `name_s` for the specular map and `name_h` for the height map.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Material {
+    pub shader: String,
+    pub diffuse: Option<PathBuf>,
+    pub normal: Option<PathBuf>,
+    pub specular: Option<PathBuf>,
+    pub height: Option<PathBuf>,
+}
+
+/// First existing image named `name` in `dir`. Shaders often carry the
+/// path of the texture in the game tree, so the last component alone is
+/// tried too.
+fn find_texture(dir: &Path, name: &str) -> Option<PathBuf> {
+    let mut candidates = vec![dir.join(name)];
+    if let Some(file_name) = Path::new(name).file_name() {
+        candidates.push(dir.join(file_name));
+    }
+
+    for candidate in &candidates {
+        for extension in &TEXTURE_EXTENSIONS {
+            let path = candidate.with_extension(extension);
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+/// Textures of `shader` found next to the model, in `dir`.
+pub fn resolve_material(dir: &Path, shader: &str) -> Material {
+    Material {
+        shader: shader.to_string(),
+        diffuse: find_texture(dir, shader),
+        normal: find_texture(dir, &format!("{}_local", shader)),
+        specular: find_texture(dir, &format!("{}_s", shader)),
+        height: find_texture(dir, &format!("{}_h", shader))
+    }
+}
@@ -0,0 +1,7 @@
+pub mod error;
+pub mod material;
+pub mod load;
+pub mod validate;
+pub mod cache;
+pub mod stream;
+pub mod baked;
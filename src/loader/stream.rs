@@ -0,0 +1,375 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use md5::md5anim::{Md5Anim, Frame, BaseFrame};
+use md5::md5anim_parser::{parse_header, parse_hierarchy, parse_bounds, parse_baseframe, parse_frame};
+use md5::md5common_parser::{ParseResult, blank, byte_order_mark, error_message};
+use loader::error::{LoadError, Md5Kind};
+use loader::validate::{validate_hierarchy, validate_frame};
+
+/// Bytes read from the underlying reader at a time.
+pub const READ_CHUNK: usize = 64 * 1024;
+
+/// Length of `data` up to and including the `count`-th closing brace, not
+/// counting braces in strings and comments.
+fn block_end(data: &[u8], count: usize) -> Option<usize> {
+    let mut closed = 0;
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'"' => match data[i + 1..].iter().position(|&c| c == b'"') {
+                Some(end) => i += end + 1,
+                None => return None
+            },
+            b'/' if data.get(i + 1) == Some(&b'/') => match data[i..].iter().position(|&c| c == b'\n') {
+                Some(end) => i += end,
+                None => return None
+            },
+            b'}' => {
+                closed += 1;
+                if closed == count {
+                    return Some(i + 1);
+                }
+            },
+            _ => ()
+        }
+        i += 1;
+    }
+    None
+}
+
+fn parse_error(message: String) -> LoadError {
+    LoadError::Parse { kind: Md5Kind::Anim, message }
+}
+
+fn invalid(message: String) -> LoadError {
+    LoadError::Invalid { kind: Md5Kind::Anim, message }
+}
+
+/// Length left after `result`, a parse of `data`, and what it parsed. Error
+/// positions are relative to `data`, a block rather than the whole file.
+fn parsed<T>(data: &[u8], result: ParseResult<'_, T>) -> Result<(usize, T), LoadError> {
+    result.map(|(rest, value)| (rest.len(), value)).map_err(|e| parse_error(error_message(data, &e)))
+}
+
+/// Reads an md5anim one frame at a time, holding a single frame and one
+/// chunk of input in memory whatever the length of the animation.
+///
+/// Everything before the frames (header, hierarchy, bounds and base frame)
+/// is read up front and available from `anim`, with no frames. Frames are
+/// decoded with `decode_frame`.
+pub struct Md5AnimReader<R> {
+    reader: R,
+    anim: Md5Anim,
+    buffer: Vec<u8>,
+    /// Bytes of `buffer` already consumed.
+    start: usize,
+    /// Position of `buffer[0]` in the stream.
+    buffer_offset: u64,
+    eof: bool,
+    next_frame: usize,
+    /// Stream position of every frame seen so far, to seek back to them.
+    frame_offsets: Vec<u64>,
+}
+
+impl<R: Read> Md5AnimReader<R> {
+    pub fn new(reader: R) -> Result<Md5AnimReader<R>, LoadError> {
+        let mut stream = Md5AnimReader {
+            reader,
+            anim: Md5Anim {
+                version: 0,
+                command_line: String::new(),
+                num_frames: 0,
+                num_joints: 0,
+                frame_rate: 0,
+                num_animated_components: 0,
+                hierarchies: Vec::new(),
+                bounds: Vec::new(),
+                base_frame: BaseFrame { position: Vec::new(), orientation: Vec::new() },
+                frames: Vec::new()
+            },
+            buffer: Vec::with_capacity(READ_CHUNK),
+            start: 0,
+            buffer_offset: 0,
+            eof: false,
+            next_frame: 0,
+            frame_offsets: Vec::new()
+        };
+        stream.read_preamble()?;
+        Ok(stream)
+    }
+
+    /// Header, hierarchy, bounds and base frame; `frames` is empty.
+    pub fn anim(&self) -> &Md5Anim {
+        &self.anim
+    }
+
+    /// Index of the frame `next_frame` returns.
+    pub fn position(&self) -> usize {
+        self.next_frame
+    }
+
+    /// Reads up to `READ_CHUNK` more bytes, first dropping consumed ones.
+    /// Returns false at the end of the stream.
+    fn fill(&mut self) -> Result<bool, LoadError> {
+        if self.eof {
+            return Ok(false);
+        }
+        self.buffer.drain(..self.start);
+        self.buffer_offset += self.start as u64;
+        self.start = 0;
+
+        let read = (&mut self.reader).take(READ_CHUNK as u64).read_to_end(&mut self.buffer)?;
+        self.eof = read == 0;
+        Ok(!self.eof)
+    }
+
+    /// End in `buffer` of the next `count` blocks, or None if the stream
+    /// ends first with nothing but blanks.
+    fn read_blocks(&mut self, count: usize) -> Result<Option<usize>, LoadError> {
+        loop {
+            if let Some(end) = block_end(&self.buffer[self.start..], count) {
+                return Ok(Some(self.start + end));
+            }
+            if !self.fill()? {
+                let rest = &self.buffer[self.start..];
+                return match blank(rest) {
+                    Ok((&[], _)) => Ok(None),
+                    _ => Err(parse_error("unexpected end of file".to_string()))
+                };
+            }
+        }
+    }
+
+    fn read_preamble(&mut self) -> Result<(), LoadError> {
+        // Hierarchy, bounds and base frame are the first three blocks.
+        let end = match self.read_blocks(3)? {
+            Some(end) => end,
+            None => return Err(parse_error("unexpected end of file".to_string()))
+        };
+
+        let mut data = &self.buffer[self.start..end];
+        if let Ok((rest, _)) = byte_order_mark(data) {
+            data = rest;
+        }
+        let (rest, header) = parsed(data, parse_header(data))?;
+        let data = &data[data.len() - rest..];
+        let (rest, hierarchies) = parsed(data, parse_hierarchy(data))?;
+        let data = &data[data.len() - rest..];
+        let (rest, bounds) = parsed(data, parse_bounds(data))?;
+        let data = &data[data.len() - rest..];
+        let (_, base_frame) = parsed(data, parse_baseframe(data))?;
+
+        self.anim = Md5Anim {
+            version: header.0,
+            command_line: header.1,
+            num_frames: header.2,
+            num_joints: header.3,
+            frame_rate: header.4,
+            num_animated_components: header.5,
+            hierarchies,
+            bounds,
+            base_frame,
+            frames: Vec::new()
+        };
+        validate_hierarchy(&self.anim).map_err(invalid)?;
+        self.start = end;
+        Ok(())
+    }
+
+    fn record_offset(&mut self) {
+        if self.next_frame == self.frame_offsets.len() {
+            let offset = self.buffer_offset + self.start as u64;
+            self.frame_offsets.push(offset);
+        }
+    }
+
+    /// The next frame, or None after the last one.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, LoadError> {
+        let end = match self.read_blocks(1)? {
+            Some(end) => end,
+            None => return Ok(None)
+        };
+        let block = &self.buffer[self.start..end];
+        let (_, frame) = parsed(block, parse_frame(block))?;
+        validate_frame(&self.anim, self.next_frame, &frame).map_err(invalid)?;
+
+        self.record_offset();
+        self.start = end;
+        self.next_frame += 1;
+        Ok(Some(frame))
+    }
+
+    /// Moves past `count` frames without decoding their values. Returns
+    /// how many there were, fewer at the end of the animation.
+    pub fn skip_frames(&mut self, count: usize) -> Result<usize, LoadError> {
+        for skipped in 0..count {
+            match self.read_blocks(1)? {
+                Some(end) => {
+                    self.record_offset();
+                    self.start = end;
+                    self.next_frame += 1;
+                },
+                None => return Ok(skipped)
+            }
+        }
+        Ok(count)
+    }
+
+    /// Reads the remaining frames into a complete animation.
+    pub fn read_all(mut self) -> Result<Md5Anim, LoadError> {
+        let mut frames = Vec::new();
+        while let Some(frame) = self.next_frame()? {
+            frames.push(frame);
+        }
+        Ok(Md5Anim { frames, ..self.anim })
+    }
+}
+
+impl<R: Read + Seek> Md5AnimReader<R> {
+    /// Makes frame `frame` the next one read. Frames already passed are
+    /// returned to directly; frames ahead are reached by skipping, which
+    /// only looks for the end of each frame.
+    pub fn seek_frame(&mut self, frame: usize) -> Result<(), LoadError> {
+        if frame == self.next_frame {
+            return Ok(());
+        }
+
+        let known = self.frame_offsets.len();
+        if known > 0 && (frame < self.next_frame || known - 1 > self.next_frame) {
+            let target = frame.min(known - 1);
+            let offset = self.frame_offsets[target];
+            self.reader.seek(SeekFrom::Start(offset))?;
+            self.buffer.clear();
+            self.start = 0;
+            self.buffer_offset = offset;
+            self.eof = false;
+            self.next_frame = target;
+        }
+
+        let wanted = frame - self.next_frame;
+        if self.skip_frames(wanted)? < wanted {
+            return Err(parse_error(format!("no frame {}, the animation has {}", frame, self.next_frame)));
+        }
+        Ok(())
+    }
+
+    /// Frames `range`, seeking to the first one.
+    pub fn read_frames(&mut self, range: Range<usize>) -> Result<Vec<Frame>, LoadError> {
+        self.seek_frame(range.start)?;
+        let mut frames = Vec::with_capacity(range.len());
+        for _ in range {
+            match self.next_frame()? {
+                Some(frame) => frames.push(frame),
+                None => break
+            }
+        }
+        Ok(frames)
+    }
+}
+
+impl<R: Read> Iterator for Md5AnimReader<R> {
+    type Item = Result<Frame, LoadError>;
+
+    fn next(&mut self) -> Option<Result<Frame, LoadError>> {
+        match self.next_frame() {
+            Ok(frame) => frame.map(Ok),
+            Err(e) => Some(Err(e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Cursor, Read};
+    use md5::md5anim_parser::parse_anim;
+    use animation::pose::{decode_frame, frame_pose};
+    use loader::error::LoadError;
+    use super::Md5AnimReader;
+
+    const ANIM: &str = "./Resources/bob_lamp_update/bob_lamp_update_export.md5anim";
+
+    fn bob_data() -> Vec<u8> {
+        let mut data = Vec::new();
+        File::open(ANIM).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    /// Hands out a few bytes per call, so blocks straddle reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(7);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn read_all() {
+        let data = bob_data();
+        let expected = parse_anim(&data).unwrap().1;
+        assert_eq!(Md5AnimReader::new(Cursor::new(&data)).unwrap().read_all().unwrap(), expected);
+        assert_eq!(Md5AnimReader::new(Trickle(&data)).unwrap().read_all().unwrap(), expected);
+    }
+
+    #[test]
+    fn frames_one_at_a_time() {
+        let data = bob_data();
+        let expected = parse_anim(&data).unwrap().1;
+        let reader = Md5AnimReader::new(Cursor::new(&data)).unwrap();
+        let anim = reader.anim().clone();
+        assert!(anim.frames.is_empty());
+
+        for (i, frame) in reader.enumerate() {
+            let frame = frame.unwrap();
+            assert_eq!(frame, expected.frames[i]);
+            assert_eq!(decode_frame(&anim, &frame.frame_data), frame_pose(&expected, i));
+        }
+    }
+
+    #[test]
+    fn seek_frame() {
+        let data = bob_data();
+        let expected = parse_anim(&data).unwrap().1;
+        let mut reader = Md5AnimReader::new(Cursor::new(&data)).unwrap();
+
+        assert_eq!(reader.read_frames(100..103).unwrap(), &expected.frames[100..103]);
+        assert_eq!(reader.read_frames(10..12).unwrap(), &expected.frames[10..12]);
+        assert_eq!(reader.read_frames(139..145).unwrap(), &expected.frames[139..141]);
+        assert_eq!(reader.read_frames(0..1).unwrap(), &expected.frames[0..1]);
+        assert!(reader.seek_frame(141).is_ok());
+        assert!(reader.seek_frame(142).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let data = bob_data();
+        let mut reader = Md5AnimReader::new(Cursor::new(&data[..data.len() - 40])).unwrap();
+        assert!(reader.skip_frames(140).is_ok());
+        assert!(reader.next_frame().is_err());
+    }
+
+    #[test]
+    fn dangling_indices() {
+        let data = String::from_utf8(bob_data()).unwrap();
+        let edited = data.replacen("\"pelvis\"\t3 63 24", "\"pelvis\"\t3 63 195", 1);
+        match Md5AnimReader::new(Cursor::new(edited.as_bytes())) {
+            Err(LoadError::Invalid { .. }) => (),
+            other => panic!("{:?}", other.map(|_| ()))
+        }
+
+        // A frame missing a component is reported when it is read.
+        let frame = data.find("frame 2 {").unwrap();
+        let first = frame + data[frame..].find("-0.000000 ").unwrap();
+        let edited = format!("{}{}", &data[..first], &data[first + "-0.000000 ".len()..]);
+        let mut reader = Md5AnimReader::new(Cursor::new(edited.as_bytes())).unwrap();
+        assert!(reader.skip_frames(2).is_ok());
+        match reader.next_frame() {
+            Err(LoadError::Invalid { .. }) => (),
+            other => panic!("{:?}", other)
+        }
+    }
+}
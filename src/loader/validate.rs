@@ -0,0 +1,136 @@
+use md5::md5mesh::Md5Mesh;
+use md5::md5anim::{Md5Anim, Frame};
+use animation::pose::{FLAG_POSITION_X, FLAG_POSITION_Y, FLAG_POSITION_Z,
+                      FLAG_ORIENTATION_X, FLAG_ORIENTATION_Y, FLAG_ORIENTATION_Z};
+
+// The parsers check the syntax of a file, not that its indices point
+// somewhere: skinning and posing index with them unchecked, so files are
+// checked here before they reach them.
+
+/// Checks that joints come after their parent and that weights, vertices
+/// and triangles only refer to joints, weights and vertices that exist.
+pub fn validate_mesh(mesh: &Md5Mesh) -> Result<(), String> {
+    for (i, joint) in mesh.joints.iter().enumerate() {
+        if joint.parent_index >= i as i32 {
+            return Err(format!("joint {} has parent {}, which does not come before it", i, joint.parent_index));
+        }
+    }
+
+    for (m, mesh_data) in mesh.meshes.iter().enumerate() {
+        for (i, weight) in mesh_data.weights.iter().enumerate() {
+            if weight.joint_index as usize >= mesh.joints.len() {
+                return Err(format!("weight {} of mesh {} uses joint {}, there are {} joints",
+                                   i, m, weight.joint_index, mesh.joints.len()));
+            }
+        }
+        for (i, vertex) in mesh_data.vertices.iter().enumerate() {
+            if vertex.start_weight as u64 + vertex.weight_count as u64 > mesh_data.weights.len() as u64 {
+                return Err(format!("vertex {} of mesh {} uses weights {} to {}, there are {} weights",
+                                   i, m, vertex.start_weight, vertex.start_weight as u64 + vertex.weight_count as u64,
+                                   mesh_data.weights.len()));
+            }
+        }
+        for (i, triangle) in mesh_data.triangles.iter().enumerate() {
+            let (a, b, c) = triangle.vertex_indices;
+            if [a, b, c].iter().any(|&v| v as usize >= mesh_data.vertices.len()) {
+                return Err(format!("triangle {} of mesh {} uses vertices {} {} {}, there are {} vertices",
+                                   i, m, a, b, c, mesh_data.vertices.len()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Everything `validate_anim` checks but the frames: joints come after
+/// their parent, the base frame has a transform per joint and the
+/// components every joint animates are within `num_animated_components`.
+pub fn validate_hierarchy(anim: &Md5Anim) -> Result<(), String> {
+    let joints = anim.hierarchies.len();
+    if anim.base_frame.position.len() != joints || anim.base_frame.orientation.len() != joints {
+        return Err(format!("the base frame has {} positions and {} orientations for {} joints",
+                           anim.base_frame.position.len(), anim.base_frame.orientation.len(), joints));
+    }
+
+    let flags = [FLAG_POSITION_X, FLAG_POSITION_Y, FLAG_POSITION_Z,
+                 FLAG_ORIENTATION_X, FLAG_ORIENTATION_Y, FLAG_ORIENTATION_Z];
+    for (i, joint) in anim.hierarchies.iter().enumerate() {
+        if joint.index >= i as i32 {
+            return Err(format!("joint {} has parent {}, which does not come before it", i, joint.index));
+        }
+        let components = flags.iter().filter(|&&bit| joint.flag & bit != 0).count() as i64;
+        if joint.start_index < 0 || joint.start_index as i64 + components > anim.num_animated_components as i64 {
+            return Err(format!("joint {} animates components {} to {}, there are {}",
+                               i, joint.start_index, joint.start_index as i64 + components, anim.num_animated_components));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that frame `index` has `num_animated_components` values.
+pub fn validate_frame(anim: &Md5Anim, index: usize, frame: &Frame) -> Result<(), String> {
+    if frame.frame_data.len() as i64 != anim.num_animated_components as i64 {
+        return Err(format!("frame {} has {} components, numAnimatedComponents is {}",
+                           index, frame.frame_data.len(), anim.num_animated_components));
+    }
+    Ok(())
+}
+
+/// Checks that every frame of `anim` can be decoded with `frame_pose`.
+pub fn validate_anim(anim: &Md5Anim) -> Result<(), String> {
+    validate_hierarchy(anim)?;
+    anim.frames.iter().enumerate().try_for_each(|(i, frame)| validate_frame(anim, i, frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use test_support::{bob_mesh, bob_anim};
+    use super::{validate_mesh, validate_anim};
+
+    #[test]
+    fn bob_is_valid() {
+        assert_eq!(validate_mesh(&bob_mesh()), Ok(()));
+        assert_eq!(validate_anim(&bob_anim()), Ok(()));
+    }
+
+    #[test]
+    fn dangling_mesh_indices() {
+        let mut mesh = bob_mesh();
+        mesh.meshes[1].weights[3].joint_index = mesh.joints.len() as u32;
+        assert!(validate_mesh(&mesh).unwrap_err().starts_with("weight 3 of mesh 1"));
+
+        let mut mesh = bob_mesh();
+        let weights = mesh.meshes[0].weights.len() as u32;
+        mesh.meshes[0].vertices[5].start_weight = weights - 1;
+        mesh.meshes[0].vertices[5].weight_count = 2;
+        assert!(validate_mesh(&mesh).unwrap_err().starts_with("vertex 5 of mesh 0"));
+
+        let mut mesh = bob_mesh();
+        mesh.meshes[0].triangles[7].vertex_indices.2 = mesh.meshes[0].vertices.len() as u32;
+        assert!(validate_mesh(&mesh).unwrap_err().starts_with("triangle 7 of mesh 0"));
+
+        let mut mesh = bob_mesh();
+        mesh.joints[2].parent_index = 2;
+        assert!(validate_mesh(&mesh).unwrap_err().starts_with("joint 2"));
+    }
+
+    #[test]
+    fn dangling_anim_indices() {
+        let mut anim = bob_anim();
+        anim.hierarchies[4].start_index = anim.num_animated_components - 1;
+        assert!(validate_anim(&anim).unwrap_err().starts_with("joint 4 animates"));
+
+        let mut anim = bob_anim();
+        anim.frames[10].frame_data.pop();
+        assert!(validate_anim(&anim).unwrap_err().starts_with("frame 10"));
+
+        let mut anim = bob_anim();
+        anim.base_frame.position.pop();
+        assert!(validate_anim(&anim).unwrap_err().starts_with("the base frame"));
+
+        let mut anim = bob_anim();
+        anim.hierarchies[3].index = 5;
+        assert!(validate_anim(&anim).unwrap_err().starts_with("joint 3 has parent 5"));
+    }
+}
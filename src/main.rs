@@ -1,53 +1,50 @@
-#[macro_use]
-extern crate nom;
-extern crate cgmath;
-
-extern crate winit;
-extern crate time;
-
-#[macro_use]
-extern crate vulkano;
-#[macro_use]
-extern crate vulkano_shader_derive;
-extern crate vulkano_win;
-
-mod md5;
-use md5::md5mesh_parser::parse_md5mesh;
-use nom::FileProducer;
-use std::fs::File;
-use std::io::Read;
-
-mod renderer;
-use renderer::render::render_model;
-
-mod vertex_computation;
-use vertex_computation::compute::{prepare_mesh, prepare_normals, prepare_full_mesh};
-use vertex_computation::convert::posvec3_to_posvulkano;
-use vertex_computation::convert::normvec3_to_normvulkano;
-use vertex_computation::convert::generate_indices;
+extern crate amalia;
 
-fn main() {
+use std::path::Path;
 
-    let path = "./Resources/bob_lamp_update/bob_lamp_update_export.md5mesh";
-    let mut f = File::open(path).unwrap();
-    let mut buff = vec![];
+use amalia::loader::cache::AssetCache;
+use amalia::loader::load::Model;
 
-    f.read_to_end(&mut buff).unwrap();
-    let (_, res) = parse_md5mesh(&buff).unwrap();
+use amalia::renderer::render::{render_model, MeshData};
 
-    // let model_idx: usize = 0;
+use amalia::vertex_computation::compute::prepare_full_mesh;
+use amalia::vertex_computation::convert::posvec3_to_posvulkano;
+use amalia::vertex_computation::convert::normvec3_to_normvulkano;
 
-    // let s = prepare_mesh(&res.meshes[model_idx], &res.joints);
-    // let n = prepare_normals(&res.meshes[model_idx], &s);
-    // let idx = generate_indices(&res.meshes[model_idx]);
+fn mesh_data(model: &Model) -> MeshData {
+    let (s, n, idx) = prepare_full_mesh(&model.mesh);
 
-    let (s, n, idx) = prepare_full_mesh(&res);
+    (posvec3_to_posvulkano(&s), normvec3_to_normvulkano(&n), idx)
+}
 
-    let vertices = posvec3_to_posvulkano(&s);
-    let normales = normvec3_to_normvulkano(&n);
+fn main() {
 
-    //println!("{:?}", vertices);
-    //println!("{:?}", normales);
-    //println!("{:?}", idx);
-    render_model(vertices.as_slice(), normales.as_slice(), idx.as_slice());
-}
\ No newline at end of file
+    let path = Path::new("./Resources/bob_lamp_update/bob_lamp_update_export.md5mesh");
+    let mut cache = AssetCache::new();
+    let model = match cache.model(path) {
+        Ok(model) => model,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let (vertices, normales, idx) = mesh_data(&model);
+
+    // Draws the new version of the model whenever its file changes. A file
+    // that fails to load is reported and the last version stays on screen.
+    render_model(vertices.as_slice(), normales.as_slice(), idx.as_slice(), || {
+        let mut changed = false;
+        for event in cache.update() {
+            match event.result {
+                Ok(_) => changed |= event.path == path,
+                Err(e) => eprintln!("{}: {}", event.path.display(), e)
+            }
+        }
+        if !changed {
+            return None;
+        }
+
+        cache.model(path).ok().map(|model| mesh_data(&model))
+    });
+}
@@ -4,6 +4,7 @@ extern crate cgmath;
 use cgmath::{Vector3, Quaternion};
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Joint {
     pub name: String,
     pub index: i32,
@@ -12,18 +13,21 @@ pub struct Joint {
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Bound {
     pub bound_min: Vector3<f32>,
     pub bound_max: Vector3<f32>
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BaseFrame {
     pub position: Vec<Vector3<f32>>,
     pub orientation: Vec<Quaternion<f32>>
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Frame {
     pub frame_number: u32,
     pub frame_data: Vec<f32>
@@ -31,6 +35,7 @@ pub struct Frame {
 
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Md5Anim {
     pub version: i32,
     pub command_line: String,
@@ -43,3 +48,26 @@ pub struct Md5Anim {
     pub base_frame: BaseFrame,
     pub frames: Vec<Frame>
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    extern crate serde_json;
+    extern crate ron;
+
+    use test_support::bob_anim;
+    use super::Md5Anim;
+
+    #[test]
+    fn json_round_trip() {
+        let anim = bob_anim();
+        let json = serde_json::to_string(&anim).unwrap();
+        assert_eq!(serde_json::from_str::<Md5Anim>(&json).unwrap(), anim);
+    }
+
+    #[test]
+    fn ron_round_trip() {
+        let anim = bob_anim();
+        let text = ron::ser::to_string(&anim).unwrap();
+        assert_eq!(ron::from_str::<Md5Anim>(&text).unwrap(), anim);
+    }
+}
@@ -1,136 +1,173 @@
 #![allow(dead_code)]
-extern crate cgmath;
-
+use nom::combinator::{opt, map, cut};
+use nom::error::context;
+use nom::multi::{many0, many1, fold_many1};
+use nom::sequence::{delimited, preceded, pair, tuple};
 use md5::md5common_parser::*;
 use md5::md5anim;
 use cgmath::{Vector3, Quaternion};
 
-named!(pub parse_header<&[u8], (i32, String, i32, i32, i32, i32)>,
-    do_parse!(
-        tag!("MD5Version ") >>
-        version: parse_i32 >>
-        take_until_and_consume!("commandline ") >>
-        command_line: escaped_string >>
-        take_until_and_consume!("numFrames ") >>
-        num_frame: parse_i32 >> 
-        take_until_and_consume!("numJoints ") >>
-        num_joints: parse_i32 >> 
-        take_until_and_consume!("frameRate ") >>
-        frame_rate: parse_i32 >> 
-        take_until_and_consume!("numAnimatedComponents ") >>
-        num_animated_components: parse_i32 >> 
-    (version, command_line, num_frame, num_joints, frame_rate, num_animated_components))
-);
-
-named!(pub parse_joint<&[u8], md5anim::Joint>,
-    do_parse!(
-        joint_name: escaped_string >>
-        parent_index: ws!(parse_i32) >>
-        flags: ws!(parse_i32) >>
-        start_index: ws!(parse_i32) >>
-        opt!(ws!(comments)) >>
-        (
-            md5anim::Joint {
-                name: joint_name,
-                index: parent_index,
-                flag: flags,
-                start_index: start_index
-            }
+pub fn parse_header(input: &[u8]) -> ParseResult<'_, (i32, String, i32, i32, i32, i32)> {
+    context("header",
+        ws(
+            map(
+                tuple((
+                    ws(keyword("MD5Version")),
+                    ws(parse_i32),
+                    ws(keyword("commandline")),
+                    ws(escaped_string),
+                    ws(keyword("numFrames")),
+                    ws(parse_i32),
+                    ws(keyword("numJoints")),
+                    ws(parse_i32),
+                    ws(keyword("frameRate")),
+                    ws(parse_i32),
+                    ws(keyword("numAnimatedComponents")),
+                    ws(parse_i32)
+                )),
+                |(_, version, _, command_line, _, num_frame, _, num_joints, _, frame_rate, _, num_animated_components)|
+                    (version, command_line, num_frame, num_joints, frame_rate, num_animated_components)
+            )
         )
-    )
-);
-
-named!(pub parse_hierarchy<&[u8], Vec<md5anim::Joint> >,
-    do_parse!(
-        ws!(tag!("hierarchy")) >>
-        ws!(tag!("{")) >>
-        joints: many1!(parse_joint) >>
-        ws!(tag!("}")) >>
-        (joints)
-    )
-);
-
-named!(pub parse_bound<&[u8], md5anim::Bound>, 
-    do_parse!(
-        b1: parse_vector3f32 >>
-        b2: parse_vector3f32 >>
-        (
-            md5anim::Bound {
-                bound_min: b1,
-                bound_max: b2
-            }
+    )(input)
+}
+
+pub fn parse_joint(input: &[u8]) -> ParseResult<'_, md5anim::Joint> {
+    context("joint",
+        map(
+            tuple((
+                ws(escaped_string),
+                ws(parse_i32),
+                ws(parse_i32),
+                ws(parse_i32)
+            )),
+            |(joint_name, parent_index, flags, start_index)|
+                md5anim::Joint {
+                    name: joint_name,
+                    index: parent_index,
+                    flag: flags,
+                    start_index
+                }
         )
-    )
-);
-
-named!(pub parse_bounds<&[u8], Vec<md5anim::Bound> >,
-    do_parse!(
-        ws!(tag!("bounds")) >>
-        ws!(tag!("{")) >>
-        bounds: many1!(parse_bound) >>
-        ws!(tag!("}")) >>
-        (bounds)
-    )
-);
-
-named!(pub pos_and_orientation<&[u8], (Vector3<f32>, Quaternion<f32>) >,
-    do_parse!(
-        p: parse_vector3f32 >>
-        o: parse_quaternionf32 >>
-        (p, o)
-    )
- );
-
- named!(pub parse_baseframe<&[u8], md5anim::BaseFrame>,
-    do_parse!(
-        ws!(tag!("baseframe")) >>
-        ws!(tag!("{")) >>
-        r: fold_many1!(pos_and_orientation, (Vec::new(), Vec::new()), | mut acc: (Vec<Vector3<f32> >, Vec<Quaternion<f32> >), item: (Vector3<f32>, Quaternion<f32>) | {
-            acc.0.push(item.0);
-            acc.1.push(item.1);
-            acc
-        }) >>
-        ws!(tag!("}")) >>
-        (
-            md5anim::BaseFrame {
-                position: r.0,
-                orientation: r.1
-            }
+    )(input)
+}
+
+pub fn parse_hierarchy(input: &[u8]) -> ParseResult<'_, Vec<md5anim::Joint>> {
+    context("hierarchy",
+        preceded(
+            ws(keyword("hierarchy")),
+            cut(
+                delimited(
+                    ws(keyword("{")),
+                    many1(parse_joint),
+                    ws(keyword("}"))
+                )
+            )
         )
-    )
- );
-
-named!(pub parse_frame<&[u8], md5anim::Frame>, 
-    do_parse!(
-        ws!(tag!("frame")) >>
-        frame_number: ws!(parse_u32) >>
-        ws!(tag!("{")) >>
-        frame_data: ws!(many0!(parse_f32)) >> 
-        ws!(tag!("}")) >>
-        (
-            md5anim::Frame {
-                frame_number: frame_number,
-                frame_data: frame_data
-            }
+    )(input)
+}
+
+pub fn parse_bound(input: &[u8]) -> ParseResult<'_, md5anim::Bound> {
+    context("bound",
+        map(
+            pair(parse_vector3f32, parse_vector3f32),
+            |(b1, b2)|
+                md5anim::Bound {
+                    bound_min: b1,
+                    bound_max: b2
+                }
+        )
+    )(input)
+}
+
+pub fn parse_bounds(input: &[u8]) -> ParseResult<'_, Vec<md5anim::Bound>> {
+    context("bounds",
+        preceded(
+            ws(keyword("bounds")),
+            cut(
+                delimited(
+                    ws(keyword("{")),
+                    many1(parse_bound),
+                    ws(keyword("}"))
+                )
+            )
+        )
+    )(input)
+}
+
+pub fn pos_and_orientation(input: &[u8]) -> ParseResult<'_, (Vector3<f32>, Quaternion<f32>)> {
+    context("joint pose",
+        pair(parse_vector3f32, parse_quaternionf32)
+    )(input)
+}
+
+pub fn parse_baseframe(input: &[u8]) -> ParseResult<'_, md5anim::BaseFrame> {
+    context("baseframe",
+        preceded(
+            ws(keyword("baseframe")),
+            cut(
+                map(
+                    delimited(
+                        ws(keyword("{")),
+                        fold_many1(
+                            pos_and_orientation,
+                            || (Vec::new(), Vec::new()),
+                            | mut acc: (Vec<Vector3<f32> >, Vec<Quaternion<f32> >), item: (Vector3<f32>, Quaternion<f32>) | {
+                                acc.0.push(item.0);
+                                acc.1.push(item.1);
+                                acc
+                            }
+                        ),
+                        ws(keyword("}"))
+                    ),
+                    |r| md5anim::BaseFrame {
+                        position: r.0,
+                        orientation: r.1
+                    }
+                )
+            )
         )
-    )
-);
-
-named!(pub parse_frames<&[u8], Vec<md5anim::Frame> >, 
-    do_parse!(
-        frames: many1!(parse_frame) >>
-        (frames)
-    )
-);
-
-named!(pub parse_anim<&[u8], md5anim::Md5Anim>, 
-    do_parse!(
-        header: parse_header >>
-        hierarchy: parse_hierarchy >>
-        bounds: parse_bounds >>
-        baseframe: parse_baseframe >>
-        frames: parse_frames >>
-        (
+    )(input)
+}
+
+pub fn parse_frame(input: &[u8]) -> ParseResult<'_, md5anim::Frame> {
+    context("frame",
+        map(
+            preceded(
+                ws(keyword("frame")),
+                cut(
+                    tuple((
+                        ws(parse_u32),
+                        ws(keyword("{")),
+                        ws(many0(parse_f32)),
+                        ws(keyword("}"))
+                    ))
+                )
+            ),
+            |(frame_number, _, frame_data, _)|
+                md5anim::Frame {
+                    frame_number,
+                    frame_data
+                }
+        )
+    )(input)
+}
+
+pub fn parse_frames(input: &[u8]) -> ParseResult<'_, Vec<md5anim::Frame>> {
+    many1(parse_frame)(input)
+}
+
+pub fn parse_anim(input: &[u8]) -> ParseResult<'_, md5anim::Md5Anim> {
+    map(
+        tuple((
+            opt(byte_order_mark),
+            parse_header,
+            parse_hierarchy,
+            parse_bounds,
+            parse_baseframe,
+            parse_frames
+        )),
+        |(_, header, hierarchy, bounds, baseframe, frames)|
             md5anim::Md5Anim {
                 version: header.0,
                 command_line: header.1,
@@ -139,21 +176,20 @@ named!(pub parse_anim<&[u8], md5anim::Md5Anim>,
                 frame_rate: header.4,
                 num_animated_components: header.5,
                 hierarchies: hierarchy,
-                bounds: bounds,
+                bounds,
                 base_frame: baseframe,
-                frames: frames
+                frames
             }
-        )
-
-    )
-);
+    )(input)
+}
 
 #[cfg(test)]
 mod test {
     extern crate cgmath;
-    use nom::IResult::Done; 
+    use std::fs;
     use md5::md5anim;
     use cgmath::{Vector3, Quaternion};
+    use test_support::{BOB_ANIM, with_tabs, parses_like};
 
     #[test]
     fn parse_header() {
@@ -165,14 +201,14 @@ mod test {
         frameRate 24
         numAnimatedComponents 198";
         let header = (10, String::from("Exported from Blender by io_export_md5.py by Paul Zirkle"), 141, 33, 24, 198);
-        assert_eq!(super::parse_header(string), Done(&b""[..], header));
+        assert_eq!(super::parse_header(string), Ok((&b""[..], header)));
     }
 
     #[test]
     fn parse_joint() {
         let string = b"\"origin\"	-1 63 0	//\n";
         let header = md5anim::Joint { name: String::from("origin"), index: -1, flag: 63, start_index: 0 };
-        assert_eq!(super::parse_joint(string), Done(&b""[..], header));
+        assert_eq!(super::parse_joint(string), Ok((&b""[..], header)));
     }
 
     #[test]
@@ -186,14 +222,14 @@ mod test {
         res.push(md5anim::Joint { name: String::from("origin"), index: -1, flag: 63, start_index: 0 });
         res.push(md5anim::Joint { name: String::from("sheath"), index: 0, flag: 63, start_index: 6 });
         res.push(md5anim::Joint { name: String::from("sword"), index: 1, flag: 63, start_index: 12 });
-        assert_eq!(super::parse_hierarchy(string), Done(&b""[..], res));
+        assert_eq!(super::parse_hierarchy(string), Ok((&b""[..], res)));
     }
 
     #[test]
     fn parse_bound() {
         let string = b"	( -1.634066 -1.634066 -1.634066 ) ( -1.634066 6.444685 5.410537 )";
         let b = md5anim::Bound { bound_min: Vector3::new(-1.634066, -1.634066, -1.634066), bound_max: Vector3::new(-1.634066, 6.444685, 5.410537) };
-        assert_eq!(super::parse_bound(string), Done(&b""[..], b));
+        assert_eq!(super::parse_bound(string), Ok((&b""[..], b)));
     }
 
     #[test]
@@ -207,7 +243,7 @@ mod test {
         bounds.push(md5anim::Bound { bound_min: Vector3::new(-1.634066, -1.634066, -1.634066 ), bound_max: Vector3::new(-1.634066, 6.444685, 5.410537) });
         bounds.push(md5anim::Bound { bound_min: Vector3::new(-1.634381, -1.634381, -1.634381 ), bound_max: Vector3::new(-1.634381, 6.444589, 5.410597) });
         bounds.push(md5anim::Bound { bound_min: Vector3::new(-1.63419, -1.63419, -1.63419 ), bound_max: Vector3::new(-1.63419, 6.444603, 5.410734) });
-        assert_eq!(super::parse_bounds(string), Done(&b""[..], bounds));
+        assert_eq!(super::parse_bounds(string), Ok((&b""[..], bounds)));
     }
 
     #[test]
@@ -215,8 +251,8 @@ mod test {
         let string = b"
 	    ( 3.122890 0.625194 0.923663 ) ( -0.022398 0.133633 0.852234 )";
 
-        let l = (Vector3::new(3.12289, 0.625194, 0.923663), Quaternion::new(0.25533772, -0.022398, 0.133633, 0.852234 ));
-        assert_eq!(super::pos_and_orientation(string), Done(&b""[..], l));
+        let l = (Vector3::new(3.12289, 0.625194, 0.923663), Quaternion::new(-0.5053095, -0.022398, 0.133633, 0.852234 ));
+        assert_eq!(super::pos_and_orientation(string), Ok((&b""[..], l)));
     }
 
     #[test]
@@ -231,12 +267,12 @@ mod test {
         position_vector.push(Vector3::new(3.12289, 0.625194, 0.923663));
         position_vector.push(Vector3::new(0.000386, -1.102681, 0.01009));
 
-        orientation_vector.push(Quaternion::new(0.25533772, -0.022398, 0.133633, 0.852234 ));
-        orientation_vector.push(Quaternion::new(0.9999951, 0.001203, -0.000819, 0.001678));
+        orientation_vector.push(Quaternion::new(-0.5053095, -0.022398, 0.133633, 0.852234 ));
+        orientation_vector.push(Quaternion::new(-0.99999756, 0.001203, -0.000819, 0.001678));
 
         let r = md5anim::BaseFrame { position: position_vector, orientation: orientation_vector};
 
-        assert_eq!(super::parse_baseframe(string), Done(&b""[..], r));
+        assert_eq!(super::parse_baseframe(string), Ok((&b""[..], r)));
     }
 
     #[test]
@@ -250,7 +286,7 @@ mod test {
         let frame_data = vec![0.001643, -0.000604, -0.707107, -0.000242, -0.707107, 3.12289, 0.625194, 0.923663, 0.022398, -0.133633, -0.852234, 0.000386, -1.102681, 0.01009, -0.001203, 0.000819, -0.001678, 2.600285, -0.20302, 0.001408, 0.75074];
         let frame = md5anim::Frame {frame_number: 0, frame_data: frame_data};
 
-        assert_eq!(super::parse_frame(string), Done(&b""[..], frame));
+        assert_eq!(super::parse_frame(string), Ok((&b""[..], frame)));
     }
 
     #[test]
@@ -277,7 +313,7 @@ mod test {
         }";
         let r = vec![md5anim::Frame { frame_number: 0, frame_data: vec![0.001643, -0.000604, -0.707107, -0.000242, -0.707107, 3.12289, 0.625194, 0.923663, 0.022398, -0.133633, -0.852234, 0.000386, -1.102681, 0.01009, -0.001203, 0.000819, -0.001678, 2.600285, -0.20302, 0.001408, 0.75074] }, md5anim::Frame { frame_number: 1, frame_data: vec![0.001643, -0.000604, -0.707107, -0.000242, -0.707107, 3.12289, 0.625194, 0.923663, 0.022398, -0.133633, -0.852234, 0.000386, -1.102681, 0.01009, -0.001203, 0.000819, -0.001678, 2.600285, -0.20302, 0.001408, 0.75074] }, md5anim::Frame { frame_number: 2, frame_data: vec![0.001643, -0.000604, -0.707107, -0.000242, -0.707107, 3.12289, 0.625194, 0.923663, 0.022398, -0.133633, -0.852234, 0.000386, -1.102681, 0.01009, -0.001203, 0.000819, -0.001678, 2.600285, -0.20302, 0.001408, 0.75074] }];
 
-        assert_eq!(super::parse_frames(string), Done(&b""[..], r));
+        assert_eq!(super::parse_frames(string), Ok((&b""[..], r)));
     }
 
     #[test]
@@ -346,8 +382,8 @@ mod test {
         position_vector.push(Vector3::new(3.12289, 0.625194, 0.923663));
         position_vector.push(Vector3::new(0.000386, -1.102681, 0.01009));
 
-        orientation_vector.push(Quaternion::new(0.25533772, -0.022398, 0.133633, 0.852234 ));
-        orientation_vector.push(Quaternion::new(0.9999951, 0.001203, -0.000819, 0.001678 ));
+        orientation_vector.push(Quaternion::new(-0.5053095, -0.022398, 0.133633, 0.852234 ));
+        orientation_vector.push(Quaternion::new(-0.99999756, 0.001203, -0.000819, 0.001678 ));
         let baseframe = md5anim::BaseFrame { position: position_vector, orientation: orientation_vector};
 
         let frames = vec![md5anim::Frame { frame_number: 0, frame_data: vec![0.001643, -0.000604, -0.707107, -0.000242, -0.707107, 3.12289, 0.625194, 0.923663, 0.022398, -0.133633, -0.852234, 0.000386, -1.102681, 0.01009, -0.001203, 0.000819, -0.001678, 2.600285, -0.20302, 0.001408, 0.75074] }, md5anim::Frame { frame_number: 1, frame_data: vec![0.001643, -0.000604, -0.707107, -0.000242, -0.707107, 3.12289, 0.625194, 0.923663, 0.022398, -0.133633, -0.852234, 0.000386, -1.102681, 0.01009, -0.001203, 0.000819, -0.001678, 2.600285, -0.20302, 0.001408, 0.75074] }, md5anim::Frame { frame_number: 2, frame_data: vec![0.001643, -0.000604, -0.707107, -0.000242, -0.707107, 3.12289, 0.625194, 0.923663, 0.022398, -0.133633, -0.852234, 0.000386, -1.102681, 0.01009, -0.001203, 0.000819, -0.001678, 2.600285, -0.20302, 0.001408, 0.75074] }];
@@ -365,7 +401,20 @@ mod test {
             frames: frames
         };
 
-        assert_eq!(super::parse_anim(string), Done(&b""[..], res));
+        assert_eq!(super::parse_anim(string), Ok((&b""[..], res)));
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn tolerant_layout() {
+        let original = fs::read_to_string(BOB_ANIM).unwrap();
+
+        parses_like(super::parse_anim, &original, &original.replace("\n", "\r\n"));
+        parses_like(super::parse_anim, &original, &format!("\u{feff}{}", original));
+        parses_like(super::parse_anim, &original, &with_tabs(&original));
+        parses_like(super::parse_anim, &original, &original.replace("\n", "\n\n \t\n"));
+        parses_like(super::parse_anim, &original, &format!("// exported by hand\n{}\n// end", original.replace("bounds {", "// per frame\nbounds { // min max\n")
+                                                                                                      .replace("frame 1 {", "// second frame\nframe 1 {")
+                                                                                                      .replace("numJoints", "// joints\nnumJoints")));
+    }
+}
@@ -0,0 +1,98 @@
+use std::io;
+use std::io::Write;
+use cgmath::{Vector3, Quaternion};
+use md5::md5anim::Md5Anim;
+
+/// Writes `anim` in the md5anim text format read by `parse_anim`.
+pub fn write_md5anim<W: Write>(w: &mut W, anim: &Md5Anim) -> io::Result<()> {
+    writeln!(w, "MD5Version {}", anim.version)?;
+    writeln!(w, "commandline \"{}\"", anim.command_line)?;
+    writeln!(w)?;
+    writeln!(w, "numFrames {}", anim.frames.len())?;
+    writeln!(w, "numJoints {}", anim.hierarchies.len())?;
+    writeln!(w, "frameRate {}", anim.frame_rate)?;
+    writeln!(w, "numAnimatedComponents {}", anim.num_animated_components)?;
+    writeln!(w)?;
+
+    writeln!(w, "hierarchy {{")?;
+    for joint in &anim.hierarchies {
+        let parent_name =
+            if joint.index >= 0 { anim.hierarchies[joint.index as usize].name.as_str() } else { "" };
+        writeln!(w, "\t\"{}\"\t{} {} {}\t// {}", joint.name, joint.index, joint.flag, joint.start_index, parent_name)?;
+    }
+    writeln!(w, "}}")?;
+    writeln!(w)?;
+
+    writeln!(w, "bounds {{")?;
+    for bound in &anim.bounds {
+        writeln!(w, "\t{} {}", vector(&bound.bound_min), vector(&bound.bound_max))?;
+    }
+    writeln!(w, "}}")?;
+    writeln!(w)?;
+
+    writeln!(w, "baseframe {{")?;
+    for (position, orientation) in anim.base_frame.position.iter().zip(&anim.base_frame.orientation) {
+        writeln!(w, "\t{} {}", vector(position), quaternion(orientation))?;
+    }
+    writeln!(w, "}}")?;
+
+    for frame in &anim.frames {
+        writeln!(w)?;
+        writeln!(w, "frame {} {{", frame.frame_number)?;
+        for joint in &anim.hierarchies {
+            let count = (joint.flag as u32 & 63).count_ones() as usize;
+            if count == 0 {
+                continue;
+            }
+            let start = joint.start_index as usize;
+            let values: Vec<String> = frame.frame_data[start..start + count].iter().map(|v| float(*v)).collect();
+            writeln!(w, "\t{}", values.join(" "))?;
+        }
+        writeln!(w, "}}")?;
+    }
+
+    Ok(())
+}
+
+pub fn md5anim_to_string(anim: &Md5Anim) -> String {
+    let mut buff: Vec<u8> = Vec::new();
+    write_md5anim(&mut buff, anim).expect("writing to memory cannot fail");
+    String::from_utf8(buff).expect("md5anim output is valid utf-8")
+}
+
+fn float(v: f32) -> String {
+    // Keeps "-0.000000" out of the output.
+    if v == 0.0 { String::from("0.000000") } else { format!("{:.6}", v) }
+}
+
+fn vector(v: &Vector3<f32>) -> String {
+    format!("( {} {} {} )", float(v.x), float(v.y), float(v.z))
+}
+
+/// Only x, y and z are written, flipped so that the implied w is negative
+/// like the parser expects.
+fn quaternion(q: &Quaternion<f32>) -> String {
+    let v = if q.s > 0.0 { -q.v } else { q.v };
+    vector(&v)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+    use md5::md5anim_parser::parse_anim;
+
+    #[test]
+    fn write_md5anim() {
+        let mut buff = vec![];
+        File::open("./Resources/bob_lamp_update/bob_lamp_update_export.md5anim").unwrap()
+            .read_to_end(&mut buff).unwrap();
+        let anim = match parse_anim(&buff) {
+            Ok((_, anim)) => anim,
+            _ => panic!("cannot parse bob_lamp_update_export.md5anim")
+        };
+
+        let written = super::md5anim_to_string(&anim);
+        assert_eq!(parse_anim(written.as_bytes()), Ok((&b""[..], anim)));
+    }
+}
@@ -1,154 +1,398 @@
 #![allow(dead_code)]
-extern crate nom;
-extern crate cgmath;
-
 use cgmath::{Vector2, Vector3, Quaternion};
 use std::str;
 use std::str::FromStr;
-use nom::digit;
 use std::f32;
+use nom::IResult;
+use nom::branch::alt;
+use nom::bytes::complete::{tag, tag_no_case, is_not, take_until};
+use nom::character::complete::{digit1, one_of};
+use nom::combinator::{opt, map, map_res, map_opt, recognize};
+use nom::error::{VerboseError, VerboseErrorKind, context};
+use nom::multi::fold_many0;
+use nom::sequence::{delimited, preceded, terminated, pair, tuple};
+
+/// Result of the MD5 parsers. On failure the error lists the `context`s
+/// the parser was in, from the innermost out; `error_message` turns it
+/// into something to show.
+pub type ParseResult<'a, T> = IResult<&'a [u8], T, VerboseError<&'a [u8]>>;
 
-named!(pub escaped_string<&[u8], String>,
-    map_res!(
-        delimited!(
-            tag!("\""),
-            fold_many0!(
-                is_not!("\""),
-                Vec::new(),
-                |mut acc: Vec<u8>, bytes: &[u8]| {
-                    acc.extend(bytes);
-                    acc
-                }
+pub fn escaped_string(input: &[u8]) -> ParseResult<'_, String> {
+    context("string",
+        map_res(
+            delimited(
+                tag("\""),
+                fold_many0(
+                    is_not("\""),
+                    Vec::new,
+                    |mut acc: Vec<u8>, bytes: &[u8]| {
+                        acc.extend(bytes);
+                        acc
+                    }
+                ),
+                tag("\"")
             ),
-            tag!("\"")
-        ),
-        String::from_utf8
-    )
-);
-
-named!(pub comments<&[u8]>,
-    preceded!(
-        tag!("//"),
-        take_until_and_consume!("\n")
-    )
-);
-
-named!(pub parse_u32<&[u8], u32>,
-    map_opt!(
-        map_res!(
-            digit,
-            str::from_utf8
-        ),
-        |str| u32::from_str(str).ok()
-    )
-);
-
-named!(pub parse_i<&[u8], (bool, &str)>,
-    ws!(
-        do_parse!(
-            neg: opt!(ws!(tag!("-"))) >>
-            int: map_res!(digit, str::from_utf8) >>
-            (neg.is_some(), int)
+            String::from_utf8
         )
-    )
-);
-
-named!(pub parse_i32<&[u8], i32>,
-    map_opt!(
-        parse_i,
-        | (neg, int) : (bool, &str) |
-            i32::from_str(int)
-            .ok()
-            .map(|v| if neg {-v} else {v})
-    )
-);
-
-named!(pub parse_f<&[u8], (bool, &str, Option<&str>)>,
-    ws!(
-        do_parse!(
-            neg: opt!(ws!(tag!("-"))) >>
-            int: map_res!(digit, str::from_utf8) >>
-            dec_opt:
-                opt!(
-                    do_parse!(
-                        tag!(".") >>
-                        dec: map_res!(digit, str::from_utf8) >>
-                        (dec)
-                    )
-                ) >> (neg.is_some(), int, dec_opt)
+    )(input)
+}
+
+/// Whitespace, tabs and CRLF line endings included, and `//` comments
+/// running to the end of their line. Parsers skip it through `ws`, so
+/// comments are accepted wherever spaces are.
+pub fn blank(input: &[u8]) -> ParseResult<'_, &[u8]> {
+    let mut i = 0;
+    loop {
+        match input.get(i) {
+            Some(&b' ') | Some(&b'\t') | Some(&b'\r') | Some(&b'\n') => i += 1,
+            Some(&b'/') if input.get(i + 1) == Some(&b'/') => {
+                i += input[i..].iter().position(|&c| c == b'\n').unwrap_or(input.len() - i);
+            },
+            _ => return Ok((&input[i..], &input[..i]))
+        }
+    }
+}
+
+/// `inner`, with the blanks before and after it skipped.
+pub fn ws<'a, O, F>(inner: F) -> impl FnMut(&'a [u8]) -> ParseResult<'a, O>
+    where F: FnMut(&'a [u8]) -> ParseResult<'a, O>
+{
+    delimited(blank, inner, blank)
+}
+
+/// The fixed word or symbol `word`, which errors name when it is missing.
+pub fn keyword<'a>(word: &'static str) -> impl FnMut(&'a [u8]) -> ParseResult<'a, &'a [u8]> {
+    context(word, tag(word))
+}
+
+pub fn byte_order_mark(input: &[u8]) -> ParseResult<'_, &[u8]> {
+    tag("\u{feff}")(input)
+}
+
+pub fn comments(input: &[u8]) -> ParseResult<'_, &[u8]> {
+    preceded(
+        tag("//"),
+        terminated(take_until("\n"), tag("\n"))
+    )(input)
+}
+
+pub fn parse_u32(input: &[u8]) -> ParseResult<'_, u32> {
+    context("unsigned integer",
+        map_opt(
+            map_res(digit1, str::from_utf8),
+            |str| u32::from_str(str).ok()
         )
-    )
-);
-
-named!(pub parse_f32<&[u8], f32>,
-    map_opt!(
-        parse_f,
-        | (neg, int, dec_opt) : (bool, &str, Option<&str>) |
-            match dec_opt {
-                Some(dec) => f32::from_str(&(String::from(int) + "." +  dec)),
-                None => f32::from_str(int)
-            }
-            .ok()
-            .map(|v| if neg {-v} else {v})
-    )
-);
-
-named!(pub parse_vector2f32<&[u8], Vector2<f32>>,
-    ws!(
-        do_parse!(
-            opt!(tag!("(")) >>
-            x: ws!(parse_f32) >>
-            y: ws!(parse_f32) >>
-            opt!(tag!(")")) >>
-            (Vector2::new(x, y))
+    )(input)
+}
+
+pub fn parse_i(input: &[u8]) -> ParseResult<'_, (bool, &str)> {
+    ws(
+        map(
+            pair(
+                opt(ws(tag("-"))),
+                map_res(digit1, str::from_utf8)
+            ),
+            |(neg, int)| (neg.is_some(), int)
         )
-    )
-);
-
-named!(pub parse_tuple3u32<&[u8], (u32, u32, u32)>,
-    ws!(
-        do_parse!(
-            a: ws!(parse_u32) >>
-            b: ws!(parse_u32) >>
-            c: ws!(parse_u32) >>
-            (a, b, c)
+    )(input)
+}
+
+pub fn parse_i32(input: &[u8]) -> ParseResult<'_, i32> {
+    context("integer",
+        map_opt(
+            parse_i,
+            | (neg, int) : (bool, &str) |
+                i32::from_str(int)
+                .ok()
+                .map(|v| if neg {-v} else {v})
         )
-    )
-);
-
-named!(pub parse_tuple3f32<&[u8], (f32, f32, f32)>,
-    ws!(
-        do_parse!(
-            opt!(tag!("(")) >>
-            a: ws!(parse_f32) >>
-            b: ws!(parse_f32) >>
-            c: ws!(parse_f32) >>
-            opt!(tag!(")")) >>
-            (a, b, c)
+    )(input)
+}
+
+fn float_mantissa(input: &[u8]) -> ParseResult<'_, &[u8]> {
+    alt((
+        recognize(pair(digit1, opt(preceded(tag("."), opt(digit1))))),
+        recognize(preceded(tag("."), digit1))
+    ))(input)
+}
+
+// MSVC prints special values as `1.#QNAN`, `-1.#IND`, `1.#INF`, sometimes
+// followed by digits.
+fn float_msvc_special(input: &[u8]) -> ParseResult<'_, &[u8]> {
+    recognize(
+        tuple((
+            tag("#"),
+            alt((tag_no_case("QNAN"), tag_no_case("SNAN"), tag_no_case("IND"), tag_no_case("INF"))),
+            opt(digit1)
+        ))
+    )(input)
+}
+
+fn float_exponent(input: &[u8]) -> ParseResult<'_, &[u8]> {
+    recognize(
+        tuple((
+            one_of("eE"),
+            opt(one_of("+-")),
+            digit1
+        ))
+    )(input)
+}
+
+pub fn float_literal(input: &[u8]) -> ParseResult<'_, &[u8]> {
+    alt((
+        recognize(pair(float_mantissa, opt(alt((float_msvc_special, float_exponent))))),
+        tag_no_case("nan"),
+        tag_no_case("infinity"),
+        tag_no_case("inf")
+    ))(input)
+}
+
+pub fn parse_f(input: &[u8]) -> ParseResult<'_, (bool, &str)> {
+    ws(
+        map(
+            pair(
+                opt(ws(alt((tag("-"), tag("+"))))),
+                map_res(float_literal, str::from_utf8)
+            ),
+            |(sign, literal)| (sign == Some(&b"-"[..]), literal)
         )
-    )
-);
-
-named!(pub parse_vector3f32<&[u8], Vector3<f32>>,
-    ws!(
-        map!(
-            parse_tuple3f32,
-            |(a, b, c)| {
-                Vector3::new(a, b, c)
-            }
+    )(input)
+}
+
+/// Value of a literal recognized by `float_literal`, without its sign.
+pub fn float_value(literal: &str) -> Option<f32> {
+    let lower = literal.to_ascii_lowercase();
+    if lower.starts_with("nan") {
+        Some(f32::NAN)
+    } else if lower.starts_with("inf") || lower.contains("#inf") {
+        Some(f32::INFINITY)
+    } else if lower.contains('#') {
+        Some(f32::NAN)
+    } else {
+        f32::from_str(literal).ok()
+    }
+}
+
+/// What NaN and infinite values in a file are read as. No MD5 field has a
+/// use for them; they come from exporters normalizing degenerate vectors,
+/// and would spread through every pose computed from them. Both become 0:
+/// the largest finite value would overflow back to infinity in the first
+/// sum or product it enters.
+pub fn sanitize_f32(v: f32) -> f32 {
+    if v.is_finite() { v } else { 0.0 }
+}
+
+/// A float as written, NaN and infinities included.
+pub fn parse_f32_raw(input: &[u8]) -> ParseResult<'_, f32> {
+    context("number",
+        map_opt(
+            parse_f,
+            | (neg, literal) : (bool, &str) |
+                float_value(literal).map(|v| if neg {-v} else {v})
         )
-    )
-);
-
-named!(pub parse_quaternionf32<&[u8], Quaternion<f32>>,
-    ws!(
-        map!(
-            parse_tuple3f32,
-            |(x, y, z)| {
-                let mut scal : f32= 1.0 - x * x - y * y - z * z;
-                if scal < 0.0 { scal = 0.0 };
-                Quaternion::new(-scal.sqrt(), x, y, z) 
-            }
+    )(input)
+}
+
+/// A float, with NaN and infinities read as 0 by `sanitize_f32`.
+pub fn parse_f32(input: &[u8]) -> ParseResult<'_, f32> {
+    map(parse_f32_raw, sanitize_f32)(input)
+}
+
+pub fn parse_vector2f32(input: &[u8]) -> ParseResult<'_, Vector2<f32>> {
+    context("vector",
+        ws(
+            map(
+                tuple((
+                    opt(tag("(")),
+                    ws(parse_f32),
+                    ws(parse_f32),
+                    opt(tag(")"))
+                )),
+                |(_, x, y, _)| Vector2::new(x, y)
+            )
         )
-    )
-);
+    )(input)
+}
+
+pub fn parse_tuple3u32(input: &[u8]) -> ParseResult<'_, (u32, u32, u32)> {
+    ws(
+        tuple((
+            ws(parse_u32),
+            ws(parse_u32),
+            ws(parse_u32)
+        ))
+    )(input)
+}
+
+pub fn parse_tuple3f32(input: &[u8]) -> ParseResult<'_, (f32, f32, f32)> {
+    context("vector",
+        ws(
+            map(
+                tuple((
+                    opt(tag("(")),
+                    ws(parse_f32),
+                    ws(parse_f32),
+                    ws(parse_f32),
+                    opt(tag(")"))
+                )),
+                |(_, a, b, c, _)| (a, b, c)
+            )
+        )
+    )(input)
+}
+
+pub fn parse_vector3f32(input: &[u8]) -> ParseResult<'_, Vector3<f32>> {
+    map(
+        parse_tuple3f32,
+        |(a, b, c)| {
+            Vector3::new(a, b, c)
+        }
+    )(input)
+}
+
+pub fn parse_quaternionf32(input: &[u8]) -> ParseResult<'_, Quaternion<f32>> {
+    map(
+        parse_tuple3f32,
+        |(x, y, z)| {
+            let mut scal : f32= 1.0 - x * x - y * y - z * z;
+            if scal < 0.0 { scal = 0.0 };
+            Quaternion::new(-scal.sqrt(), x, y, z)
+        }
+    )(input)
+}
+
+/// Where a parse of `data` failed and what was expected there, as
+/// `line 12, column 5: expected "}" in joint, in joints`. `data` is the
+/// whole input given to the parser that returned `error`.
+pub fn error_message(data: &[u8], error: &::nom::Err<VerboseError<&[u8]>>) -> String {
+    let error = match *error {
+        ::nom::Err::Incomplete(_) => return "unexpected end of file".to_string(),
+        ::nom::Err::Error(ref e) | ::nom::Err::Failure(ref e) => e
+    };
+    let (at, kind) = match error.errors.first() {
+        Some(&(at, ref kind)) => (at, kind),
+        None => return "parse error".to_string()
+    };
+    let mut contexts: Vec<(&[u8], &str)> = error.errors.iter().filter_map(|&(input, ref kind)| match *kind {
+        VerboseErrorKind::Context(name) => Some((input, name)),
+        _ => None
+    }).collect();
+
+    // A context that failed where the error is, blanks aside, failed to
+    // start: it names what was expected better than the parser inside it.
+    let starts_at_error = |input: &[u8]| blank(input).map(|(rest, _)| rest.len() == at.len()).unwrap_or(false);
+    let expected = match *kind {
+        VerboseErrorKind::Char(c) => format!("expected {:?}", c),
+        _ => match contexts.iter().rposition(|&(input, _)| starts_at_error(input)) {
+            Some(i) => {
+                let name = contexts[i].1;
+                contexts.drain(..i + 1);
+                format!("expected {}", name)
+            },
+            None => "unexpected input".to_string()
+        }
+    };
+
+    let offset = data.len() - at.len();
+    let line_start = data[..offset].iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1);
+    let line = data[..offset].iter().filter(|&&c| c == b'\n').count() + 1;
+    let column = offset - line_start + 1;
+
+    let mut message = format!("line {}, column {}: {}", line, column, expected);
+    if !contexts.is_empty() {
+        message.push_str(" in ");
+        message.push_str(&contexts.iter().map(|&(_, name)| name).collect::<Vec<_>>().join(", in "));
+    }
+    if at.is_empty() {
+        message.push_str(", found the end of the file");
+    } else {
+        let found = &at[..at.iter().position(|&c| c == b'\n').unwrap_or(at.len())];
+        message.push_str(&format!(", found {:?}", String::from_utf8_lossy(&found[..found.len().min(32)]).trim_end()));
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+    use super::{parse_f32, parse_f32_raw, error_message};
+
+    fn parsed(input: &str) -> f32 {
+        match parse_f32(input.as_bytes()) {
+            Ok((rest, v)) => {
+                assert!(rest.is_empty(), "{:?} left {:?}", input, rest);
+                v
+            },
+            other => panic!("{:?}: {:?}", input, other)
+        }
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!(parsed("12"), 12.0);
+        assert_eq!(parsed("-0.25"), -0.25);
+        assert_eq!(parsed(" 3.5 "), 3.5);
+        assert_eq!(parsed("- 1.5"), -1.5);
+    }
+
+    #[test]
+    fn leading_plus() {
+        assert_eq!(parsed("+0.2"), 0.2);
+        assert_eq!(parsed("+7"), 7.0);
+    }
+
+    #[test]
+    fn missing_digits() {
+        assert_eq!(parsed(".5"), 0.5);
+        assert_eq!(parsed("-.5"), -0.5);
+        assert_eq!(parsed("2."), 2.0);
+    }
+
+    #[test]
+    fn exponent() {
+        assert_eq!(parsed("1e-05"), 1e-05);
+        assert_eq!(parsed("-2.5E+3"), -2500.0);
+        assert_eq!(parsed("3e2"), 300.0);
+        assert_eq!(parsed(".5e1"), 5.0);
+    }
+
+    #[test]
+    fn exponent_without_digits() {
+        assert_eq!(parse_f32(b"1e )"), Ok((&b"e )"[..], 1.0)));
+    }
+
+    #[test]
+    fn not_a_number() {
+        assert!(parse_f32(b"abc").is_err());
+        assert!(parse_f32(b"-)").is_err());
+    }
+
+    #[test]
+    fn special_values() {
+        for input in &["nan", "-nan", "NaN", "1.#QNAN", "-1.#QNAN0", "-1.#IND", "1.#IND00", "1.#SNAN"] {
+            assert!(match parse_f32_raw(input.as_bytes()) { Ok((_, v)) => v.is_nan(), _ => false }, "{}", input);
+            assert_eq!(parsed(input), 0.0);
+        }
+        for &(input, sign) in &[("inf", 1.0), ("-inf", -1.0), ("Infinity", 1.0), ("1.#INF", 1.0), ("-1.#INF00", -1.0)] {
+            assert_eq!(parse_f32_raw(input.as_bytes()), Ok((&b""[..], sign * f32::INFINITY)), "{}", input);
+            assert_eq!(parsed(input), 0.0);
+        }
+    }
+
+    #[test]
+    fn in_vector() {
+        assert_eq!(super::parse_tuple3f32(b"( 1e-05 -.5 +2 )"), Ok((&b""[..], (1e-05, -0.5, 2.0))));
+        assert_eq!(super::parse_tuple3f32(b"( -nan 0 1.#INF )"), Ok((&b""[..], (0.0, 0.0, 0.0))));
+    }
+
+    #[test]
+    fn error_position() {
+        let input = b"( 1\n  x 2 )";
+        let error = super::parse_tuple3f32(input).unwrap_err();
+        assert_eq!(error_message(input, &error), "line 2, column 3: expected number in vector, found \"x 2 )\"");
+
+        let input = b"( 1 2";
+        let error = super::parse_tuple3f32(input).unwrap_err();
+        assert_eq!(error_message(input, &error), "line 1, column 6: expected number in vector, found the end of the file");
+    }
+}
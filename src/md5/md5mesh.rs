@@ -2,6 +2,7 @@
 use cgmath::{Vector3, Vector2, Quaternion};
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Joint {
     pub name: String,
     pub parent_index: i32,
@@ -10,6 +11,7 @@ pub struct Joint {
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Vertex {
     pub index: u32,
     pub tex_coords: Vector2<f32>,
@@ -18,12 +20,14 @@ pub struct Vertex {
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Triangle {
     pub index: u32,
     pub vertex_indices: (u32, u32, u32),
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Weight {
     pub index: u32,
     pub joint_index: u32,
@@ -32,6 +36,7 @@ pub struct Weight {
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Mesh {
     pub shader: String,
     pub vertices: Vec<Vertex>,
@@ -40,9 +45,38 @@ pub struct Mesh {
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Md5Mesh {
     pub version: u8,
     pub command_line: String,
     pub joints: Vec<Joint>,
     pub meshes: Vec<Mesh>,
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    extern crate serde_json;
+    extern crate ron;
+
+    use test_support::bob_mesh;
+    use super::Md5Mesh;
+
+    #[test]
+    fn json_round_trip() {
+        let mesh = bob_mesh();
+        let json = serde_json::to_string(&mesh).unwrap();
+        assert_eq!(serde_json::from_str::<Md5Mesh>(&json).unwrap(), mesh);
+
+        let joint = serde_json::to_value(&mesh.joints[1]).unwrap();
+        assert_eq!(joint["name"], "sheath");
+        assert_eq!(joint["position"]["x"].as_f64().unwrap() as f32, mesh.joints[1].position.x);
+        assert_eq!(joint["orientation"]["s"].as_f64().unwrap() as f32, mesh.joints[1].orientation.s);
+    }
+
+    #[test]
+    fn ron_round_trip() {
+        let mesh = bob_mesh();
+        let text = ron::ser::to_string_pretty(&mesh, ron::ser::PrettyConfig::default()).unwrap();
+        assert_eq!(ron::from_str::<Md5Mesh>(&text).unwrap(), mesh);
+    }
+}
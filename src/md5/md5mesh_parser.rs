@@ -1,210 +1,245 @@
 #![allow(dead_code)]
 use std::str;
 use std::str::FromStr;
-use nom::{digit, multispace};
+use nom::character::complete::digit1;
+use nom::combinator::{opt, map, map_res, cut};
+use nom::error::context;
+use nom::multi::many0;
+use nom::sequence::{delimited, preceded, tuple};
 use md5::md5mesh::{Md5Mesh, Joint, Vertex, Mesh, Triangle, Weight};
 use md5::md5common_parser::*;
 
 
-named!(pub parse_header<&[u8], (u8, String)>,
-    do_parse!(
-        version:
-            map_res!(
-                map_res!(
-                    terminated!(
-                        preceded!(tag!("MD5Version "), digit),
-                        opt!(multispace)
+pub fn parse_header(input: &[u8]) -> ParseResult<'_, (u8, String)> {
+    context("header",
+        ws(
+            map(
+                tuple((
+                    ws(keyword("MD5Version")),
+                    ws(
+                        map_res(
+                            map_res(digit1, str::from_utf8),
+                            FromStr::from_str
+                        )
                     ),
-                    str::from_utf8
-                ),
-                FromStr::from_str ) >>
-
-    command_line:
-        preceded!(
-            tag!("commandline "),
-            escaped_string
-        ) >>
-        (version, command_line)));
-
-
-
-named!(pub parse_joints<&[u8], Vec<Joint>>,
-    preceded!(
-        tag!("joints"),
-        delimited!(
-            ws!(tag!("{")),
-            many0!(
-                do_parse!(
-                    name: ws!(escaped_string) >>
-                    parent_index: ws!(parse_i32) >>
-                    position: ws!(parse_vector3f32) >>
-                    orientation: ws!(parse_quaternionf32) >>
-                    opt!(comments) >>
-                    (Joint {
-                        name: name,
-                        parent_index: parent_index,
-                        position: position,
-                        orientation: orientation
-                    })
+                    ws(keyword("commandline")),
+                    ws(escaped_string)
+                )),
+                |(_, version, _, command_line)| (version, command_line)
+            )
+        )
+    )(input)
+}
+
+fn parse_joint(input: &[u8]) -> ParseResult<'_, Joint> {
+    context("joint",
+        map(
+            tuple((
+                ws(escaped_string),
+                ws(parse_i32),
+                ws(parse_vector3f32),
+                ws(parse_quaternionf32)
+            )),
+            |(name, parent_index, position, orientation)| Joint {
+                name,
+                parent_index,
+                position,
+                orientation
+            }
+        )
+    )(input)
+}
+
+pub fn parse_joints(input: &[u8]) -> ParseResult<'_, Vec<Joint>> {
+    context("joints",
+        preceded(
+            keyword("joints"),
+            cut(
+                delimited(
+                    ws(keyword("{")),
+                    many0(parse_joint),
+                    ws(keyword("}"))
+                )
+            )
+        )
+    )(input)
+}
+
+pub fn parse_vertex(input: &[u8]) -> ParseResult<'_, Vertex> {
+    context("vert",
+        map(
+            preceded(
+                ws(keyword("vert")),
+                cut(
+                    tuple((
+                        ws(parse_u32),
+                        ws(parse_vector2f32),
+                        ws(parse_u32),
+                        ws(parse_u32)
+                    ))
                 )
             ),
-            ws!(tag!("}"))
+            |(index, tex_coords, start_weight, weight_count)| Vertex {
+                index,
+                tex_coords,
+                start_weight,
+                weight_count
+            }
         )
-    )
-);
-
-named!(pub parse_vertex<&[u8], Vertex>,
-    do_parse!(
-        ws!(tag!("vert")) >>
-        index: ws!(parse_u32) >>
-        tex_coords: ws!(parse_vector2f32) >>
-        start_weight: ws!(parse_u32) >>
-        weight_count: ws!(parse_u32) >>
-        (Vertex {
-            index: index,
-            tex_coords: tex_coords,
-            start_weight: start_weight,
-            weight_count: weight_count
-        })
-    )
-);
-
-named!(pub parse_vertices<&[u8], Vec<Vertex>>,
-    map!(
-        do_parse!(
-            ws!(tag!("numverts")) >>
-            ws!(parse_u32) >>
-            vertices: many0!(parse_vertex) >>
-            (vertices)
+    )(input)
+}
+
+pub fn parse_vertices(input: &[u8]) -> ParseResult<'_, Vec<Vertex>> {
+    map(
+        preceded(
+            tuple((ws(keyword("numverts")), ws(parse_u32))),
+            many0(parse_vertex)
         ),
         |mut vertices : Vec<Vertex>| {
             vertices.sort_by_key(|v| v.index);
             vertices
         }
-    )
-);
-
-named!(pub parse_triangle<&[u8], Triangle>,
-    do_parse!(
-        ws!(tag!("tri")) >>
-        index: ws!(parse_u32) >>
-        vertex_indices: ws!(parse_tuple3u32) >>
-        (Triangle {index: index, vertex_indices: vertex_indices})
-    )
-);
-
-named!(pub parse_triangles<&[u8], Vec<Triangle>>,
-    map!(
-        do_parse!(
-            ws!(tag!("numtris")) >>
-            ws!(parse_u32) >>
-            triangles: many0!(parse_triangle) >>
-            (triangles)
+    )(input)
+}
+
+pub fn parse_triangle(input: &[u8]) -> ParseResult<'_, Triangle> {
+    context("tri",
+        map(
+            preceded(
+                ws(keyword("tri")),
+                cut(tuple((ws(parse_u32), ws(parse_tuple3u32))))
+            ),
+            |(index, vertex_indices)| Triangle {index, vertex_indices}
+        )
+    )(input)
+}
+
+pub fn parse_triangles(input: &[u8]) -> ParseResult<'_, Vec<Triangle>> {
+    map(
+        preceded(
+            tuple((ws(keyword("numtris")), ws(parse_u32))),
+            many0(parse_triangle)
         ),
         |mut triangles : Vec<Triangle>| {
             triangles.sort_by_key(|t| t.index);
             triangles
         }
-    )
-);
-
-named!(pub parse_bias<&[u8], f32>,
-    ws!(
-        map_res!(
-            parse_f32,
-            |v: f32| {
-                if v.abs() <= 1.0 {
-                    Ok(v)
-                } else {
-                    Err("Invalid bias")
+    )(input)
+}
+
+pub fn parse_bias(input: &[u8]) -> ParseResult<'_, f32> {
+    context("bias between -1 and 1",
+        ws(
+            map_res(
+                parse_f32,
+                |v: f32| {
+                    if v.abs() <= 1.0 {
+                        Ok(v)
+                    } else {
+                        Err("Invalid bias")
+                    }
                 }
-            }
+            )
         )
-    )
-);
-
-named!(pub parse_weight<&[u8], Weight>,
-    do_parse!(
-        ws!(tag!("weight")) >>
-        index: ws!(parse_u32) >>
-        joint_index: ws!(parse_u32) >>
-        bias: ws!(parse_bias) >>
-        position: ws!(parse_vector3f32) >>
-        (Weight {index: index, joint_index: joint_index, bias: bias, position: position})
-    )
-);
-
-named!(pub parse_weights<&[u8], Vec<Weight>>,
-    map!(
-        do_parse!(
-            ws!(tag!("numweights")) >>
-            ws!(parse_u32) >>
-            weights: many0!(parse_weight) >>
-            (weights)
+    )(input)
+}
+
+pub fn parse_weight(input: &[u8]) -> ParseResult<'_, Weight> {
+    context("weight",
+        map(
+            preceded(
+                ws(keyword("weight")),
+                cut(
+                    tuple((
+                        ws(parse_u32),
+                        ws(parse_u32),
+                        ws(parse_bias),
+                        ws(parse_vector3f32)
+                    ))
+                )
+            ),
+            |(index, joint_index, bias, position)| Weight {index, joint_index, bias, position}
+        )
+    )(input)
+}
+
+pub fn parse_weights(input: &[u8]) -> ParseResult<'_, Vec<Weight>> {
+    map(
+        preceded(
+            tuple((ws(keyword("numweights")), ws(parse_u32))),
+            many0(parse_weight)
         ),
         |mut weights : Vec<Weight>| {
             weights.sort_by_key(|w| w.index);
             weights
         }
-    )
-);
-
-named!(pub parse_mesh<&[u8], Mesh>,
-    preceded!(
-        tag!("mesh"),
-        delimited!(
-            ws!(tag!("{")),
-            do_parse!(
-                shader:
-                    preceded!(
-                        tag!("shader"),
-                        ws!(escaped_string)
-                    ) >>
-                verts: ws!(parse_vertices) >>
-                tris: ws!(parse_triangles) >>
-                weights: ws!(parse_weights) >>
-                (Mesh {
-                    shader: shader,
-                    vertices: verts,
-                    triangles: tris,
-                    weights: weights
-                })
-            ),
-            ws!(tag!("}"))
+    )(input)
+}
+
+pub fn parse_mesh(input: &[u8]) -> ParseResult<'_, Mesh> {
+    context("mesh",
+        preceded(
+            keyword("mesh"),
+            cut(
+                delimited(
+                    ws(keyword("{")),
+                    map(
+                        tuple((
+                            preceded(
+                                keyword("shader"),
+                                ws(escaped_string)
+                            ),
+                            ws(parse_vertices),
+                            ws(parse_triangles),
+                            ws(parse_weights)
+                        )),
+                        |(shader, verts, tris, weights)| Mesh {
+                            shader,
+                            vertices: verts,
+                            triangles: tris,
+                            weights
+                        }
+                    ),
+                    ws(keyword("}"))
+                )
+            )
         )
-    )
-);
-
-named!(pub parse_meshes<&[u8], Vec<Mesh>>,
-    ws!(many0!(ws!(parse_mesh)))
-);
-
-named!(pub parse_md5mesh<&[u8], Md5Mesh>,
-    do_parse!(
-        header: ws!(parse_header) >>
-        ws!(tag!("numJoints")) >>
-        ws!(parse_u32) >>
-        ws!(tag!("numMeshes")) >>
-        ws!(parse_u32) >>
-        joints: ws!(parse_joints) >>
-        meshes: ws!(parse_meshes) >>
-        (Md5Mesh {
+    )(input)
+}
+
+pub fn parse_meshes(input: &[u8]) -> ParseResult<'_, Vec<Mesh>> {
+    ws(many0(ws(parse_mesh)))(input)
+}
+
+pub fn parse_md5mesh(input: &[u8]) -> ParseResult<'_, Md5Mesh> {
+    map(
+        tuple((
+            opt(byte_order_mark),
+            ws(parse_header),
+            ws(keyword("numJoints")),
+            ws(parse_u32),
+            ws(keyword("numMeshes")),
+            ws(parse_u32),
+            ws(parse_joints),
+            ws(parse_meshes)
+        )),
+        |(_, header, _, _, _, _, joints, meshes)| Md5Mesh {
             version: header.0,
             command_line: header.1,
-            joints: joints,
-            meshes: meshes
-        })
-    )
-);
+            joints,
+            meshes
+        }
+    )(input)
+}
 
 #[cfg(test)]
 mod tests {
     extern crate cgmath;
 
-    use nom::IResult::Done;
-    use std::str;
+    use std::fs;
     use cgmath::{Vector3, Vector2, Quaternion};
     use md5::md5mesh::{Md5Mesh, Joint, Vertex, Mesh, Triangle, Weight};
+    use test_support::{BOB_MESH, with_tabs, parses_like};
 
     #[test]
     fn parse_header() {
@@ -213,7 +248,7 @@ mod tests {
             commandline \"Exported from Blender by io_export_md5.py by Paul Zirkle\"";
 
         let header = (10, String::from("Exported from Blender by io_export_md5.py by Paul Zirkle"));
-        assert_eq!(super::parse_header(string), Done(&b""[..], header));
+        assert_eq!(super::parse_header(string), Ok((&b""[..], header)));
     }
 
     #[test]
@@ -237,12 +272,12 @@ mod tests {
                 name: String::from("sheath"),
                 parent_index: 0,
                 position: Vector3::new(1.100481, -0.317714, 3.170247),
-                orientation: Quaternion::new(0.4454863, 0.307041, -0.578615, 0.354181)
+                orientation: Quaternion::new(-0.6674476, 0.307041, -0.578615, 0.354181)
             };
 
         let joints = vec![joint1, joint2];
 
-        assert_eq!(super::parse_joints(string), Done(&b""[..], joints));
+        assert_eq!(super::parse_joints(string), Ok((&b""[..], joints)));
     }
 
     #[test]
@@ -257,7 +292,7 @@ mod tests {
                 weight_count: 3
             };
 
-        assert_eq!(super::parse_vertex(string), Done(&b""[..], vertex));
+        assert_eq!(super::parse_vertex(string), Ok((&b""[..], vertex)));
     }
 
     #[test]
@@ -306,7 +341,7 @@ mod tests {
                 weights: vec![weight]
             };
 
-        assert_eq!(super::parse_mesh(string), Done(&b""[..], mesh));
+        assert_eq!(super::parse_mesh(string), Ok((&b""[..], mesh)));
     }
 
     #[test]
@@ -383,6 +418,34 @@ mod tests {
                 meshes: vec![mesh]
             };
 
-        assert_eq!(super::parse_md5mesh(string), Done(&b""[..], md5mesh));
+        assert_eq!(super::parse_md5mesh(string), Ok((&b""[..], md5mesh)));
+    }
+
+    #[test]
+    fn tolerant_layout() {
+        let original = fs::read_to_string(BOB_MESH).unwrap();
+
+        parses_like(super::parse_md5mesh, &original, &original.replace("\n", "\r\n"));
+        parses_like(super::parse_md5mesh, &original, &format!("\u{feff}{}", original));
+        parses_like(super::parse_md5mesh, &original, &original.replace("MD5Version ", "MD5Version\t").replace("numMeshes ", "numMeshes\t\t"));
+        parses_like(super::parse_md5mesh, &original, &with_tabs(&original));
+        parses_like(super::parse_md5mesh, &original, &original.replace("\n", "\n\n \t\n"));
+        parses_like(super::parse_md5mesh, &original, &format!("// exported by hand\n{}\n// end", original.replace("mesh {", "// next mesh\nmesh { // body\n")
+                                                                                                         .replace("numverts", "// vertices\nnumverts")));
+    }
+
+    #[test]
+    fn error_context() {
+        let original = fs::read_to_string(BOB_MESH).unwrap();
+        let message = |text: String| {
+            let error = super::parse_md5mesh(text.as_bytes()).unwrap_err();
+            ::md5::md5common_parser::error_message(text.as_bytes(), &error)
+        };
+
+        assert_eq!(message(original.replacen("numtris", "numtri", 1)),
+                   "line 537, column 2: expected numtris in mesh, found \"numtri 628\"");
+        assert_eq!(message(original.replacen("( 0.683594", "( x", 1)),
+                   "line 47, column 11: expected number in vector, in vert, in mesh, found \"x 0.455078 ) 0 3\"");
+        assert!(message(original.replacen("\"origin\"", "origin", 1)).starts_with("line 8, column 2: expected } in joints"));
     }
 }
@@ -3,5 +3,6 @@ pub mod md5mesh_parser;
 
 pub mod md5anim;
 pub mod md5anim_parser;
+pub mod md5anim_writer;
 
 pub mod md5common_parser;
\ No newline at end of file
@@ -0,0 +1,39 @@
+use cgmath::Vector3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Channel {
+    XPosition,
+    YPosition,
+    ZPosition,
+    XRotation,
+    YRotation,
+    ZRotation,
+}
+
+/// Joint as written in the HIERARCHY section, children nested.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BvhNode {
+    pub name: String,
+    pub offset: Vector3<f32>,
+    pub channels: Vec<Channel>,
+    pub children: Vec<BvhNode>,
+    pub end_site: Option<Vector3<f32>>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct BvhJoint {
+    pub name: String,
+    pub parent_index: i32,
+    pub offset: Vector3<f32>,
+    pub channels: Vec<Channel>,
+    /// Index of the first value of this joint in a frame.
+    pub start_index: usize,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Bvh {
+    /// Joints in depth first order, parents before their children.
+    pub joints: Vec<BvhJoint>,
+    pub frame_time: f32,
+    pub frames: Vec<Vec<f32>>,
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use cgmath::{Vector3, Quaternion, InnerSpace, Rotation3, Deg};
+use md5::md5anim::{Md5Anim, Joint as AnimJoint};
+use md5::md5mesh::{Md5Mesh, Joint};
+use mocap::bvh::{Bvh, Channel};
+use animation::pose::{skeleton_pose, rebuild_skeleton, resample_poses, encode_anim, local_transform};
+use vertex_computation::compute::mesh_bounds;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct BvhImportOptions {
+    /// MD5 joint driven by a BVH joint, keyed by BVH joint name, for joints
+    /// whose names differ.
+    pub joint_map: HashMap<String, String>,
+    pub frame_rate: i32,
+    /// Factor applied to BVH translations, e.g. to go from centimeters to
+    /// model units.
+    pub scale: f32,
+    /// Rotation from the BVH space to the MD5 one.
+    pub axis_conversion: Quaternion<f32>,
+}
+
+impl Default for BvhImportOptions {
+    /// BVH files are Y up, MD5 ones Z up.
+    fn default() -> BvhImportOptions {
+        BvhImportOptions {
+            joint_map: HashMap::new(),
+            frame_rate: 24,
+            scale: 1.0,
+            axis_conversion: Quaternion::from_angle_x(Deg(90.0))
+        }
+    }
+}
+
+/// Object space (position, orientation) of every BVH joint in frame `frame`.
+pub fn bvh_frame_transforms(bvh: &Bvh, frame: usize) -> Vec<(Vector3<f32>, Quaternion<f32>)> {
+    let values = &bvh.frames[frame];
+    let mut transforms: Vec<(Vector3<f32>, Quaternion<f32>)> = Vec::with_capacity(bvh.joints.len());
+
+    for joint in &bvh.joints {
+        let mut position = joint.offset;
+        let mut rotation = Quaternion::new(1.0, 0.0, 0.0, 0.0);
+
+        // Rotation channels apply in the order they are listed in.
+        for (k, channel) in joint.channels.iter().enumerate() {
+            let value = values[joint.start_index + k];
+            match *channel {
+                Channel::XPosition => position.x = value,
+                Channel::YPosition => position.y = value,
+                Channel::ZPosition => position.z = value,
+                Channel::XRotation => rotation = rotation * Quaternion::from_angle_x(Deg(value)),
+                Channel::YRotation => rotation = rotation * Quaternion::from_angle_y(Deg(value)),
+                Channel::ZRotation => rotation = rotation * Quaternion::from_angle_z(Deg(value)),
+            }
+        }
+
+        let transform =
+            if joint.parent_index < 0 {
+                (position, rotation.normalize())
+            } else {
+                let (parent_position, parent_rotation) = transforms[joint.parent_index as usize];
+                (parent_position + parent_rotation * position, (parent_rotation * rotation).normalize())
+            };
+        transforms.push(transform);
+    }
+
+    transforms
+}
+
+/// Converts motion capture onto the skeleton of `mesh`.
+///
+/// The BVH rest pose, where every rotation is zero, is assumed to match the
+/// md5mesh bind pose. Each mapped MD5 joint gets the object space rotation
+/// of its BVH joint relative to that rest pose, while bone lengths stay those
+/// of the mesh. Translations of the BVH root relative to its rest offset move
+/// the MD5 joint it maps to. Unmapped MD5 joints keep their bind pose relative
+/// to their parent.
+///
+/// Frames are resampled to `options.frame_rate`; the base frame is the bind
+/// pose and bounds are those of the skinned mesh.
+pub fn convert_bvh(bvh: &Bvh, mesh: &Md5Mesh, options: &BvhImportOptions) -> Md5Anim {
+    let conversion = options.axis_conversion.normalize();
+    let conversion_inverse = conversion.conjugate();
+
+    // BVH joint driving each MD5 joint.
+    let sources: Vec<Option<usize>> = mesh.joints.iter().map(|joint| {
+        bvh.joints.iter().position(|b| options.joint_map.get(&b.name).unwrap_or(&b.name) == &joint.name)
+    }).collect();
+
+    let bind_pose = skeleton_pose(&mesh.joints);
+
+    let poses: Vec<_> = (0..bvh.frames.len()).map(|f| {
+        let transforms = bvh_frame_transforms(bvh, f);
+        let mut skeleton: Vec<Joint> = Vec::with_capacity(mesh.joints.len());
+
+        for (i, bind) in mesh.joints.iter().enumerate() {
+            let (bind_position, bind_orientation) = local_transform(&mesh.joints, i);
+            let (parent_position, parent_orientation) =
+                if bind.parent_index < 0 {
+                    (Vector3::new(0.0, 0.0, 0.0), Quaternion::new(1.0, 0.0, 0.0, 0.0))
+                } else {
+                    let parent = &skeleton[bind.parent_index as usize];
+                    (parent.position, parent.orientation)
+                };
+            let mut position = parent_position + parent_orientation * bind_position;
+            let mut orientation = (parent_orientation * bind_orientation).normalize();
+
+            if let Some(b) = sources[i] {
+                let (bvh_position, bvh_rotation) = transforms[b];
+                orientation = (conversion * bvh_rotation * conversion_inverse * bind.orientation).normalize();
+
+                if bvh.joints[b].parent_index < 0 {
+                    position = bind.position + conversion * (bvh_position - bvh.joints[b].offset) * options.scale;
+                }
+            }
+
+            skeleton.push(Joint {
+                name: bind.name.clone(),
+                parent_index: bind.parent_index,
+                position,
+                orientation
+            });
+        }
+
+        skeleton_pose(&skeleton)
+    }).collect();
+
+    let source_rate = if bvh.frame_time > 0.0 { 1.0 / bvh.frame_time } else { options.frame_rate as f32 };
+    let poses = resample_poses(&poses, source_rate, options.frame_rate as f32);
+
+    let hierarchy: Vec<AnimJoint> = mesh.joints.iter().map(|j| AnimJoint {
+        name: j.name.clone(),
+        index: j.parent_index,
+        flag: 0,
+        start_index: 0
+    }).collect();
+
+    let encoded = encode_anim(&hierarchy, &bind_pose, &poses);
+
+    Md5Anim {
+        frame_rate: options.frame_rate,
+        bounds: poses.iter().map(|p| mesh_bounds(mesh, &rebuild_skeleton(&mesh.joints, p))).collect(),
+        .. encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use cgmath::{Vector3, Quaternion, InnerSpace};
+    use md5::md5mesh::{Md5Mesh, Joint};
+    use mocap::bvh::{Bvh, BvhJoint, Channel};
+    use animation::pose::frame_skeleton;
+    use super::BvhImportOptions;
+
+    fn bvh() -> Bvh {
+        Bvh {
+            joints: vec![
+                BvhJoint {
+                    name: String::from("Hips"),
+                    parent_index: -1,
+                    offset: Vector3::new(0.0, 0.0, 0.0),
+                    channels: vec![Channel::XPosition, Channel::YPosition, Channel::ZPosition,
+                                   Channel::ZRotation, Channel::XRotation, Channel::YRotation],
+                    start_index: 0
+                },
+                BvhJoint {
+                    name: String::from("LeftUpLeg"),
+                    parent_index: 0,
+                    offset: Vector3::new(0.0, -10.0, 0.0),
+                    channels: vec![Channel::ZRotation, Channel::XRotation, Channel::YRotation],
+                    start_index: 6
+                }
+            ],
+            frame_time: 0.5,
+            frames: vec![
+                vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                vec![0.0, 10.0, 0.0, 0.0, 0.0, 0.0, 0.0, 90.0, 0.0]
+            ]
+        }
+    }
+
+    fn mesh() -> Md5Mesh {
+        let identity = Quaternion::new(1.0, 0.0, 0.0, 0.0);
+        Md5Mesh {
+            version: 10,
+            command_line: String::new(),
+            joints: vec![
+                Joint { name: String::from("pelvis"), parent_index: -1, position: Vector3::new(0.0, 0.0, 1.0), orientation: identity },
+                Joint { name: String::from("thigh.L"), parent_index: 0, position: Vector3::new(0.0, 0.0, 0.0), orientation: identity }
+            ],
+            meshes: vec![]
+        }
+    }
+
+    #[test]
+    fn convert_bvh() {
+        let mut joint_map = HashMap::new();
+        joint_map.insert(String::from("Hips"), String::from("pelvis"));
+        joint_map.insert(String::from("LeftUpLeg"), String::from("thigh.L"));
+        let options = BvhImportOptions { joint_map, frame_rate: 4, scale: 0.1, .. BvhImportOptions::default() };
+
+        let anim = super::convert_bvh(&bvh(), &mesh(), &options);
+        assert_eq!(anim.frame_rate, 4);
+        assert_eq!(anim.num_frames, 3);
+        assert_eq!(anim.bounds.len(), 3);
+        assert!(anim.hierarchies[0].flag & 4 != 0);
+        assert_eq!(anim.hierarchies[1].flag & 7, 0);
+
+        // Hips raised by 10 units along BVH Y, i.e. 1 unit along MD5 Z.
+        let last = frame_skeleton(&anim, 2);
+        assert!((last[0].position - Vector3::new(0.0, 0.0, 2.0)).magnitude() < 1e-5);
+
+        // A quarter turn around BVH X is a quarter turn around MD5 X.
+        let forward = last[1].orientation * Vector3::unit_y();
+        assert!((forward - Vector3::unit_z()).magnitude() < 1e-5);
+
+        let middle = frame_skeleton(&anim, 1);
+        assert!((middle[0].position - Vector3::new(0.0, 0.0, 1.5)).magnitude() < 1e-5);
+    }
+}
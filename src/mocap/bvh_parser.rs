@@ -0,0 +1,217 @@
+use std::str;
+use cgmath::Vector3;
+use nom::branch::alt;
+use nom::bytes::complete::is_not;
+use nom::combinator::{opt, map, map_res, map_opt, value};
+use nom::error::context;
+use nom::multi::{many0, count as multi_count};
+use nom::sequence::{delimited, preceded, tuple};
+use md5::md5common_parser::*;
+use mocap::bvh::{Bvh, BvhNode, BvhJoint, Channel};
+
+pub fn joint_name(input: &[u8]) -> ParseResult<'_, String> {
+    context("joint name",
+        map(
+            map_res(
+                is_not(" \t\r\n{"),
+                str::from_utf8
+            ),
+            String::from
+        )
+    )(input)
+}
+
+pub fn parse_channel(input: &[u8]) -> ParseResult<'_, Channel> {
+    context("channel",
+        alt((
+            value(Channel::XPosition, keyword("Xposition")),
+            value(Channel::YPosition, keyword("Yposition")),
+            value(Channel::ZPosition, keyword("Zposition")),
+            value(Channel::XRotation, keyword("Xrotation")),
+            value(Channel::YRotation, keyword("Yrotation")),
+            value(Channel::ZRotation, keyword("Zrotation"))
+        ))
+    )(input)
+}
+
+pub fn parse_channels(input: &[u8]) -> ParseResult<'_, Vec<Channel>> {
+    let (input, _) = ws(keyword("CHANNELS"))(input)?;
+    let (input, count) = ws(parse_u32)(input)?;
+    context("channels", multi_count(ws(parse_channel), count as usize))(input)
+}
+
+pub fn parse_offset(input: &[u8]) -> ParseResult<'_, Vector3<f32>> {
+    preceded(
+        ws(keyword("OFFSET")),
+        ws(parse_vector3f32)
+    )(input)
+}
+
+pub fn parse_end_site(input: &[u8]) -> ParseResult<'_, Vector3<f32>> {
+    context("End Site",
+        delimited(
+            tuple((ws(keyword("End")), ws(keyword("Site")), ws(keyword("{")))),
+            ws(parse_offset),
+            ws(keyword("}"))
+        )
+    )(input)
+}
+
+pub fn parse_node(input: &[u8]) -> ParseResult<'_, BvhNode> {
+    context("joint",
+        map(
+            tuple((
+                ws(alt((keyword("ROOT"), keyword("JOINT")))),
+                ws(joint_name),
+                ws(keyword("{")),
+                ws(parse_offset),
+                ws(parse_channels),
+                many0(ws(parse_node)),
+                opt(ws(parse_end_site)),
+                ws(keyword("}"))
+            )),
+            |(_, name, _, offset, channels, children, end_site, _)| BvhNode {
+                name,
+                offset,
+                channels,
+                children,
+                end_site
+            }
+        )
+    )(input)
+}
+
+pub fn parse_motion(input: &[u8]) -> ParseResult<'_, (u32, f32, Vec<f32>)> {
+    context("MOTION",
+        map(
+            tuple((
+                ws(keyword("MOTION")),
+                ws(keyword("Frames:")),
+                ws(parse_u32),
+                ws(keyword("Frame")),
+                ws(keyword("Time:")),
+                ws(parse_f32),
+                many0(ws(parse_f32))
+            )),
+            |(_, _, frame_count, _, _, frame_time, values)| (frame_count, frame_time, values)
+        )
+    )(input)
+}
+
+pub fn parse_bvh(input: &[u8]) -> ParseResult<'_, Bvh> {
+    context("a value for every channel of every frame",
+        map_opt(
+            tuple((
+                ws(keyword("HIERARCHY")),
+                ws(parse_node),
+                parse_motion
+            )),
+            |(_, root, (frame_count, frame_time, values)): (&[u8], BvhNode, (u32, f32, Vec<f32>))| {
+                let mut joints = Vec::new();
+                flatten(&root, -1, &mut joints);
+
+                let channel_count: usize = joints.iter().map(|j: &BvhJoint| j.channels.len()).sum();
+                if channel_count == 0 || values.len() != channel_count * frame_count as usize {
+                    return None;
+                }
+
+                Some(Bvh {
+                    joints,
+                    frame_time,
+                    frames: values.chunks(channel_count).map(|c| c.to_vec()).collect()
+                })
+            }
+        )
+    )(input)
+}
+
+fn flatten(node: &BvhNode, parent_index: i32, joints: &mut Vec<BvhJoint>) {
+    let start_index = joints.last().map(|j| j.start_index + j.channels.len()).unwrap_or(0);
+    let index = joints.len() as i32;
+
+    joints.push(BvhJoint {
+        name: node.name.clone(),
+        parent_index,
+        offset: node.offset,
+        channels: node.channels.clone(),
+        start_index
+    });
+
+    for child in &node.children {
+        flatten(child, index, joints);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+    use mocap::bvh::{BvhNode, BvhJoint, Channel};
+
+    #[test]
+    fn parse_channels() {
+        let string = b"CHANNELS 3 Zrotation Xrotation Yrotation";
+        let channels = vec![Channel::ZRotation, Channel::XRotation, Channel::YRotation];
+        assert_eq!(super::parse_channels(string), Ok((&b""[..], channels)));
+    }
+
+    #[test]
+    fn parse_node() {
+        let string = b"JOINT LeftUpLeg
+        {
+            OFFSET 3.5 0.0 0.0
+            CHANNELS 3 Zrotation Xrotation Yrotation
+            End Site
+            {
+                OFFSET 0.0 -17.0 0.0
+            }
+        }";
+
+        let node = BvhNode {
+            name: String::from("LeftUpLeg"),
+            offset: Vector3::new(3.5, 0.0, 0.0),
+            channels: vec![Channel::ZRotation, Channel::XRotation, Channel::YRotation],
+            children: vec![],
+            end_site: Some(Vector3::new(0.0, -17.0, 0.0))
+        };
+
+        assert_eq!(super::parse_node(string), Ok((&b""[..], node)));
+    }
+
+    #[test]
+    fn parse_bvh() {
+        let string = b"HIERARCHY
+        ROOT Hips
+        {
+            OFFSET 0.0 0.0 0.0
+            CHANNELS 6 Xposition Yposition Zposition Zrotation Xrotation Yrotation
+            JOINT LeftUpLeg
+            {
+                OFFSET 3.5 0.0 0.0
+                CHANNELS 3 Zrotation Xrotation Yrotation
+                End Site
+                {
+                    OFFSET 0.0 -17.0 0.0
+                }
+            }
+        }
+        MOTION
+        Frames: 2
+        Frame Time: 0.033333
+        0.0 35.0 0.0 0.0 0.0 0.0 0.0 0.0 0.0
+        0.0 36.5 0.5 0.0 -10.0 0.0 0.0 45.0 0.0
+        ";
+
+        let bvh = super::parse_bvh(string);
+        let bvh = match bvh { Ok((_, bvh)) => bvh, _ => panic!("{:?}", bvh) };
+
+        assert_eq!(bvh.joints[1], BvhJoint {
+            name: String::from("LeftUpLeg"),
+            parent_index: 0,
+            offset: Vector3::new(3.5, 0.0, 0.0),
+            channels: vec![Channel::ZRotation, Channel::XRotation, Channel::YRotation],
+            start_index: 6
+        });
+        assert_eq!(bvh.frame_time, 0.033333);
+        assert_eq!(bvh.frames[1], vec![0.0, 36.5, 0.5, 0.0, -10.0, 0.0, 0.0, 45.0, 0.0]);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod bvh;
+pub mod bvh_parser;
+pub mod bvh_convert;
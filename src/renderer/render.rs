@@ -10,8 +10,15 @@ use std;
 
 use vertex_computation::convert::{Vertex, Normal};
 
-
-pub fn render_model(v_vertices: &[Vertex], v_normal: &[Normal], v_index: &[u16]) {
+/// Vertices, normals and triangle indices of a model to draw.
+pub type MeshData = (Vec<Vertex>, Vec<Normal>, Vec<u16>);
+
+/// Draws the model until its window is closed. `reload` is called once per
+/// frame; when it returns new data, for instance after the file changed on
+/// disk, the buffers are replaced and the next frame draws it.
+pub fn render_model<F>(v_vertices: &[Vertex], v_normal: &[Normal], v_index: &[u16], mut reload: F)
+    where F: FnMut() -> Option<MeshData>
+{
     // The start of this example is exactly the same as `triangle`. You should read the
     // `triangle` example if you haven't done so yet.
 
@@ -59,17 +66,25 @@ pub fn render_model(v_vertices: &[Vertex], v_normal: &[Normal], v_index: &[u16])
 
     let mut depth_buffer = vulkano::image::attachment::AttachmentImage::transient(device.clone(), dimensions, vulkano::format::D16Unorm).unwrap();
 
-    let vertex_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
-                                ::from_iter(device.clone(), vulkano::buffer::BufferUsage::all(), v_vertices.iter().cloned())
-                                .expect("failed to create buffer");
+    // Frames still in flight keep the buffers they were recorded with alive,
+    // so reloaded data can go to new buffers at any time.
+    let upload = |v_vertices: &[Vertex], v_normal: &[Normal], v_index: &[u16]| {
+        let vertex_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
+                                    ::from_iter(device.clone(), vulkano::buffer::BufferUsage::all(), v_vertices.iter().cloned())
+                                    .expect("failed to create buffer");
+
+        let normals_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
+                                    ::from_iter(device.clone(), vulkano::buffer::BufferUsage::all(), v_normal.iter().cloned())
+                                    .expect("failed to create buffer");
+
+        let index_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
+                                    ::from_iter(device.clone(), vulkano::buffer::BufferUsage::all(), v_index.iter().cloned())
+                                    .expect("failed to create buffer");
 
-    let normals_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
-                                ::from_iter(device.clone(), vulkano::buffer::BufferUsage::all(), v_normal.iter().cloned())
-                                .expect("failed to create buffer");
+        (vertex_buffer, normals_buffer, index_buffer)
+    };
 
-    let index_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
-                                ::from_iter(device.clone(), vulkano::buffer::BufferUsage::all(), v_index.iter().cloned())
-                                .expect("failed to create buffer");
+    let (mut vertex_buffer, mut normals_buffer, mut index_buffer) = upload(v_vertices, v_normal, v_index);
 
     // note: this teapot was meant for OpenGL where the origin is at the lower left
     //       instead the origin is at the upper left in vulkan, so we reverse the Y axis
@@ -126,6 +141,13 @@ pub fn render_model(v_vertices: &[Vertex], v_normal: &[Normal], v_index: &[u16])
     loop {
         previous_frame.cleanup_finished();
 
+        if let Some((v_vertices, v_normal, v_index)) = reload() {
+            let (new_vertex_buffer, new_normals_buffer, new_index_buffer) = upload(&v_vertices, &v_normal, &v_index);
+            vertex_buffer = new_vertex_buffer;
+            normals_buffer = new_normals_buffer;
+            index_buffer = new_index_buffer;
+        }
+
         if recreate_swapchain {
 
         dimensions = surface.capabilities(physical)
@@ -0,0 +1,62 @@
+//! Fixtures shared by the unit tests: the `bob` model and animation that
+//! ship in `Resources`, and helpers for the tests of the parsers.
+
+use std::fmt::Debug;
+use std::fs;
+use std::str;
+use md5::md5mesh::{Md5Mesh, Joint};
+use md5::md5anim::Md5Anim;
+use md5::md5mesh_parser::parse_md5mesh;
+use md5::md5anim_parser::parse_anim;
+use md5::md5common_parser::ParseResult;
+use animation::pose::frame_skeleton;
+
+pub const BOB_MESH: &str = "./Resources/bob_lamp_update/bob_lamp_update_export.md5mesh";
+pub const BOB_ANIM: &str = "./Resources/bob_lamp_update/bob_lamp_update_export.md5anim";
+
+pub fn bob_mesh() -> Md5Mesh {
+    match parse_md5mesh(&fs::read(BOB_MESH).unwrap()) {
+        Ok((_, mesh)) => mesh,
+        _ => panic!("could not parse {}", BOB_MESH)
+    }
+}
+
+pub fn bob_anim() -> Md5Anim {
+    match parse_anim(&fs::read(BOB_ANIM).unwrap()) {
+        Ok((_, anim)) => anim,
+        _ => panic!("could not parse {}", BOB_ANIM)
+    }
+}
+
+/// The skeleton of `bob_anim` at `frame`.
+pub fn bob_skeleton(frame: usize) -> Vec<Joint> {
+    frame_skeleton(&bob_anim(), frame)
+}
+
+/// `text` with every space outside quotes turned into a tab.
+pub fn with_tabs(text: &str) -> String {
+    let mut quoted = false;
+    text.chars().map(|c| match c {
+        '"' => { quoted = !quoted; c },
+        ' ' if !quoted => '\t',
+        _ => c
+    }).collect()
+}
+
+/// Checks that `parser` reads all of `variant`, blanks aside, as the same
+/// value as `original`.
+pub fn parses_like<T, P>(parser: P, original: &str, variant: &str)
+    where T: PartialEq + Debug, P: Fn(&[u8]) -> ParseResult<'_, T>
+{
+    let expected = match parser(original.as_bytes()) {
+        Ok((_, value)) => value,
+        other => panic!("{:?}", other.map(|_| ()))
+    };
+    match parser(variant.as_bytes()) {
+        Ok((rest, value)) => {
+            assert!(str::from_utf8(rest).unwrap().trim().is_empty());
+            assert_eq!(value, expected);
+        },
+        other => panic!("{:?}", other.map(|_| ()))
+    }
+}
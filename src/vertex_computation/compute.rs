@@ -1,6 +1,8 @@
  use md5::md5mesh::*;
- use cgmath::{Vector3, InnerSpace};
+ use md5::md5anim::Bound;
+ use cgmath::Vector3;
  use vertex_computation::convert::generate_indices;
+ use vertex_computation::normals::finite_normal;
 
  pub fn prepare_mesh(m: &Mesh, v_joints: &Vec<Joint>) -> Vec<Vector3<f32>> {
      let mut position_buffer : Vec<Vector3<f32>> = Vec::new();
@@ -48,8 +50,9 @@
     }
 
     // println!("{:?}", normal_buffer);
+    // Vertices without area around them get FALLBACK_NORMAL rather than NaN.
     for i in 0..normal_buffer.len() {
-       normal_buffer[ i ] = normal_buffer[ i ].normalize();
+       normal_buffer[ i ] = finite_normal(normal_buffer[ i ]);
     }
 
     normal_buffer
@@ -74,4 +77,24 @@
         res_i.append(&mut tmp_i);
     }
     (res_v, res_n, res_i)
- }
\ No newline at end of file
+ }
+
+ /// Axis aligned box around every vertex of `ms` skinned with `joints`.
+ pub fn mesh_bounds(ms: &Md5Mesh, joints: &Vec<Joint>) -> Bound {
+    let mut bound_min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+    let mut bound_max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+
+    for m in &ms.meshes {
+        for v in prepare_mesh(m, joints) {
+            bound_min = Vector3::new(bound_min.x.min(v.x), bound_min.y.min(v.y), bound_min.z.min(v.z));
+            bound_max = Vector3::new(bound_max.x.max(v.x), bound_max.y.max(v.y), bound_max.z.max(v.z));
+        }
+    }
+
+    if bound_min.x > bound_max.x {
+        bound_min = Vector3::new(0., 0., 0.);
+        bound_max = Vector3::new(0., 0., 0.);
+    }
+
+    Bound { bound_min, bound_max }
+ }
@@ -1,14 +1,19 @@
+#[cfg(feature = "viewer")]
 use vulkano;
+#[cfg(feature = "viewer")]
 use cgmath::{Vector3, InnerSpace};
 use md5::md5mesh::{Mesh};
 
+#[cfg(feature = "viewer")]
 #[derive(Copy, Clone, Debug)]
 pub struct Vertex {
     position: (f32, f32, f32)
 }
 
+#[cfg(feature = "viewer")]
 impl_vertex!(Vertex, position);
 
+#[cfg(feature = "viewer")]
 pub fn posvec3_to_posvulkano( v_p: &Vec<Vector3<f32>> ) -> Vec<Vertex> {
     let mut res : Vec<Vertex> = Vec::new();
     //res.push(Vertex { position: (0., 0., 0.) });
@@ -20,13 +25,16 @@ pub fn posvec3_to_posvulkano( v_p: &Vec<Vector3<f32>> ) -> Vec<Vertex> {
 }
 
 
+#[cfg(feature = "viewer")]
 #[derive(Copy, Clone, Debug)]
 pub struct Normal {
     normal: (f32, f32, f32)
 }
 
+#[cfg(feature = "viewer")]
 impl_vertex!(Normal, normal);
 
+#[cfg(feature = "viewer")]
 pub fn normvec3_to_normvulkano (v_n: &Vec<Vector3<f32>> ) -> Vec<Normal> {
     let mut res : Vec<Normal> = Vec::new();
     //res.push(Normal { normal: (0., 0., 0.) });
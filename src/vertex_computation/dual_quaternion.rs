@@ -0,0 +1,108 @@
+use cgmath::{Vector3, Quaternion, InnerSpace};
+
+/// Rigid transform stored as a unit dual quaternion: `real` is the rotation,
+/// `dual` is half the translation times the rotation.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DualQuaternion {
+    pub real: Quaternion<f32>,
+    pub dual: Quaternion<f32>,
+}
+
+impl DualQuaternion {
+    pub fn identity() -> DualQuaternion {
+        DualQuaternion {
+            real: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            dual: Quaternion::new(0.0, 0.0, 0.0, 0.0)
+        }
+    }
+
+    /// Rotation by `rotation` followed by a translation by `translation`.
+    pub fn from_rotation_translation(rotation: Quaternion<f32>, translation: Vector3<f32>) -> DualQuaternion {
+        let real = rotation.normalize();
+        DualQuaternion {
+            real,
+            dual: Quaternion::from_sv(0.0, translation) * real * 0.5
+        }
+    }
+
+    pub fn rotation(&self) -> Quaternion<f32> {
+        self.real
+    }
+
+    pub fn translation(&self) -> Vector3<f32> {
+        (self.dual * self.real.conjugate()).v * 2.0
+    }
+
+    /// Brings a blend of dual quaternions back to a rigid transform.
+    pub fn normalize(&self) -> DualQuaternion {
+        let length = self.real.magnitude();
+        if length < 1e-8 {
+            return DualQuaternion::identity();
+        }
+        let real = self.real / length;
+        let dual = self.dual / length;
+        // Removes the part of the dual orthogonal to rigid transforms.
+        DualQuaternion { real, dual: dual - real * real.dot(dual) }
+    }
+
+    pub fn transform_point(&self, point: Vector3<f32>) -> Vector3<f32> {
+        self.real * point + self.translation()
+    }
+
+    /// Components as two xyzw vectors, the layout expected by shaders.
+    pub fn to_array(&self) -> [f32; 8] {
+        [self.real.v.x, self.real.v.y, self.real.v.z, self.real.s,
+         self.dual.v.x, self.dual.v.y, self.dual.v.z, self.dual.s]
+    }
+}
+
+/// Normalized blend of `transforms` (transform, weight). The first one
+/// decides the hemisphere the others are flipped to.
+pub fn blend_dual_quaternions<I>(transforms: I) -> DualQuaternion
+    where I: IntoIterator<Item = (DualQuaternion, f32)> {
+    let mut iter = transforms.into_iter();
+    let (first, first_weight) = match iter.next() {
+        Some(first) => first,
+        None => return DualQuaternion::identity()
+    };
+
+    let mut sum = DualQuaternion { real: first.real * first_weight, dual: first.dual * first_weight };
+    for (transform, weight) in iter {
+        let weight = if first.real.dot(transform.real) < 0.0 { -weight } else { weight };
+        sum = DualQuaternion {
+            real: sum.real + transform.real * weight,
+            dual: sum.dual + transform.dual * weight
+        };
+    }
+
+    sum.normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Quaternion, Rotation3, Rad, InnerSpace};
+    use super::DualQuaternion;
+
+    #[test]
+    fn transform_point() {
+        let rotation = Quaternion::from_angle_z(Rad(::std::f32::consts::FRAC_PI_2));
+        let dq = DualQuaternion::from_rotation_translation(rotation, Vector3::new(1.0, 2.0, 3.0));
+        assert!((dq.translation() - Vector3::new(1.0, 2.0, 3.0)).magnitude() < 1e-6);
+        assert!((dq.transform_point(Vector3::new(1.0, 0.0, 0.0)) - Vector3::new(1.0, 3.0, 3.0)).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn blend_dual_quaternions() {
+        let a = DualQuaternion::from_rotation_translation(Quaternion::from_angle_x(Rad(0.0)), Vector3::new(0.0, 0.0, 0.0));
+        let b = DualQuaternion::from_rotation_translation(Quaternion::from_angle_x(Rad(2.0)), Vector3::new(0.0, 0.0, 0.0));
+        // Opposite sign, same rotation.
+        let c = DualQuaternion { real: -b.real, dual: -b.dual };
+
+        let blend = super::blend_dual_quaternions(vec![(a, 0.5), (c, 0.5)]);
+        let expected = Quaternion::from_angle_x(Rad(1.0));
+        assert!(blend.rotation().dot(expected).abs() > 0.9999);
+        // A pure rotation blend keeps points at the same distance.
+        let p = blend.transform_point(Vector3::new(0.0, 1.0, 0.0));
+        assert!((p.magnitude() - 1.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,97 @@
+use cgmath::{Vector3, InnerSpace};
+use md5::md5mesh::{Mesh, Joint};
+use vertex_computation::compute::prepare_mesh;
+use vertex_computation::normals::{vertex_normals, finite_normal, NormalWeighting};
+
+/// Bind pose normals moved into the space of the joints, one per entry of
+/// `Mesh::weights`, the way Doom 3 stored them. Skinning then rotates them
+/// like weight positions instead of rebuilding normals from triangles.
+///
+/// Every skinning path can rotate them: `skinning::skin_full_mesh_joint_normals`,
+/// `PackedModel::with_joint_normals` and `BindPoseMesh::with_joint_normals`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct JointSpaceNormals {
+    pub normals: Vec<Vector3<f32>>,
+}
+
+impl JointSpaceNormals {
+    /// From one normal per vertex of `m` in the `bind` pose, e.g. welded
+    /// across UV seams with `generate_normals`.
+    pub fn new(m: &Mesh, bind: &[Joint], bind_normals: &[Vector3<f32>]) -> JointSpaceNormals {
+        let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); m.weights.len()];
+
+        for (vertex, normal) in m.vertices.iter().zip(bind_normals) {
+            for w in vertex.start_weight..vertex.start_weight + vertex.weight_count {
+                let joint = &bind[m.weights[w as usize].joint_index as usize];
+                normals[w as usize] = joint.orientation.normalize().conjugate() * normal;
+            }
+        }
+
+        JointSpaceNormals { normals }
+    }
+
+    /// From the area weighted normals of the bind pose, as `prepare_normals`
+    /// computes them.
+    pub fn from_bind_pose(m: &Mesh, bind: &Vec<Joint>) -> JointSpaceNormals {
+        let positions = prepare_mesh(m, bind);
+        JointSpaceNormals::new(m, bind, &vertex_normals(m, &positions, NormalWeighting::Area))
+    }
+}
+
+/// Normals of `m` posed by `joints` into `out`, one per vertex.
+pub fn skin_normals_into(m: &Mesh, normals: &JointSpaceNormals, joints: &[Joint], out: &mut [Vector3<f32>]) {
+    for (vertex, skinned) in m.vertices.iter().zip(out.iter_mut()) {
+        let mut sum = Vector3::new(0.0, 0.0, 0.0);
+
+        for w in vertex.start_weight..vertex.start_weight + vertex.weight_count {
+            let weight = &m.weights[w as usize];
+            sum += (joints[weight.joint_index as usize].orientation * normals.normals[w as usize]) * weight.bias;
+        }
+
+        *skinned = finite_normal(sum);
+    }
+}
+
+pub fn skin_normals(m: &Mesh, normals: &JointSpaceNormals, joints: &[Joint]) -> Vec<Vector3<f32>> {
+    let mut skinned = vec![Vector3::new(0.0, 0.0, 0.0); m.vertices.len()];
+    skin_normals_into(m, normals, joints, &mut skinned);
+    skinned
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+    use vertex_computation::compute::{prepare_mesh, prepare_normals};
+    use test_support::{bob_mesh, bob_skeleton};
+    use super::JointSpaceNormals;
+
+    #[test]
+    fn bind_pose() {
+        let mesh = bob_mesh();
+        for m in &mesh.meshes {
+            let normals = JointSpaceNormals::from_bind_pose(m, &mesh.joints);
+            let skinned = super::skin_normals(m, &normals, &mesh.joints);
+            let expected = prepare_normals(m, &prepare_mesh(m, &mesh.joints));
+
+            for (a, b) in skinned.iter().zip(&expected) {
+                assert!((a - b).magnitude() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn animated() {
+        let mesh = bob_mesh();
+        let skeleton = bob_skeleton(60);
+
+        // Close to normals rebuilt from the posed triangles, which differ
+        // where the surface stretches.
+        let m = &mesh.meshes[0];
+        let normals = JointSpaceNormals::from_bind_pose(m, &mesh.joints);
+        let skinned = super::skin_normals(m, &normals, &skeleton);
+        let rebuilt = prepare_normals(m, &prepare_mesh(m, &skeleton));
+
+        let agreement: f32 = skinned.iter().zip(&rebuilt).map(|(a, b)| a.dot(*b)).sum::<f32>() / skinned.len() as f32;
+        assert!(agreement > 0.95);
+    }
+}
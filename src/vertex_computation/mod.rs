@@ -1,2 +1,9 @@
 pub mod compute;
-pub mod convert;
\ No newline at end of file
+pub mod convert;
+pub mod dual_quaternion;
+pub mod skinning;
+pub mod parallel;
+pub mod palette;
+pub mod normals;
+pub mod joint_normals;
+pub mod picking;
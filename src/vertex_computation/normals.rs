@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use cgmath::{Vector3, InnerSpace, Rad};
+use md5::md5mesh::Mesh;
+
+/// How much each triangle around a vertex contributes to its normal.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NormalWeighting {
+    /// Every triangle counts the same.
+    Uniform,
+    /// Large triangles count more, as in `prepare_normals`.
+    Area,
+    /// Triangles count by the angle of their corner at the vertex, which
+    /// does not depend on how the surface around it is tessellated.
+    Angle,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NormalOptions {
+    pub weighting: NormalWeighting,
+    /// Triangles meeting at a larger angle than this are not smoothed
+    /// together, their shared vertices are split instead. `None` smooths
+    /// everything.
+    pub smoothing_angle: Option<Rad<f32>>,
+    /// Smooths vertices at the same position together even though they are
+    /// distinct, as exporters duplicate vertices along UV seams.
+    pub weld_uv_seams: bool,
+    /// Distance under which vertices are considered at the same position.
+    pub weld_distance: f32,
+}
+
+impl Default for NormalOptions {
+    fn default() -> NormalOptions {
+        NormalOptions {
+            weighting: NormalWeighting::Area,
+            smoothing_angle: None,
+            weld_uv_seams: false,
+            weld_distance: 1e-5
+        }
+    }
+}
+
+/// Output of `generate_normals`. The first vertices are those of the mesh,
+/// in order; vertices split at hard edges are appended after them.
+#[derive(Clone, PartialEq, Debug)]
+pub struct NormalMesh {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    /// Mesh vertex each vertex comes from, to look up texture coordinates.
+    pub source_vertices: Vec<u32>,
+    pub indices: Vec<u32>,
+}
+
+/// Normal given to vertices no triangle with an area touches, by
+/// `prepare_normals`, `parallel::normals_into` and the functions below.
+pub const FALLBACK_NORMAL: Vector3<f32> = Vector3 { x: 0.0, y: 0.0, z: 1.0 };
+
+/// Unit normal of each triangle, oriented as in `prepare_normals`, or `None`
+/// when the triangle has no area.
+fn face_normals(m: &Mesh, positions: &[Vector3<f32>]) -> Vec<Option<Vector3<f32>>> {
+    m.triangles.iter().map(|t| {
+        let (i0, i1, i2) = t.vertex_indices;
+        let v0 = positions[i0 as usize];
+        let cross_product = (positions[i2 as usize] - v0).cross(positions[i1 as usize] - v0);
+        let length = cross_product.magnitude();
+        if length > 0.0 && length.is_finite() { Some(cross_product / length) } else { None }
+    }).collect()
+}
+
+/// Weight of corner `corner` (0 to 2) of triangle `triangle`.
+fn corner_weight(m: &Mesh, positions: &[Vector3<f32>], triangle: usize, corner: usize, weighting: NormalWeighting) -> f32 {
+    let (i0, i1, i2) = m.triangles[triangle].vertex_indices;
+    let v = [positions[i0 as usize], positions[i1 as usize], positions[i2 as usize]];
+
+    match weighting {
+        NormalWeighting::Uniform => 1.0,
+        NormalWeighting::Area => (v[2] - v[0]).cross(v[1] - v[0]).magnitude() * 0.5,
+        NormalWeighting::Angle => {
+            let a = v[(corner + 1) % 3] - v[corner];
+            let b = v[(corner + 2) % 3] - v[corner];
+            let (la, lb) = (a.magnitude(), b.magnitude());
+            if la > 0.0 && lb > 0.0 { (a.dot(b) / (la * lb)).clamp(-1.0, 1.0).acos() } else { 0.0 }
+        }
+    }
+}
+
+/// Groups of vertices sharing a position: every vertex alone, or, when
+/// welding, every vertex with the first one within `weld_distance` of it.
+fn position_groups(positions: &[Vector3<f32>], options: &NormalOptions) -> Vec<usize> {
+    if !options.weld_uv_seams {
+        return (0..positions.len()).collect();
+    }
+
+    // Vertices are bucketed in cells `weld_distance` wide. Those close
+    // enough to weld are in the same cell or in neighbouring ones.
+    let cell = options.weld_distance.max(1e-12);
+    let mut cells: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+    let mut groups = Vec::with_capacity(positions.len());
+    for (i, p) in positions.iter().enumerate() {
+        let key = ((p.x / cell).floor() as i64, (p.y / cell).floor() as i64, (p.z / cell).floor() as i64);
+        let mut group = i;
+        for dx in -1..2 {
+            for dy in -1..2 {
+                for dz in -1..2 {
+                    let neighbours = match cells.get(&(key.0 + dx, key.1 + dy, key.2 + dz)) {
+                        Some(neighbours) => neighbours,
+                        None => continue
+                    };
+                    for &j in neighbours {
+                        if j < group && (positions[j] - p).magnitude() <= options.weld_distance {
+                            group = groups[j];
+                        }
+                    }
+                }
+            }
+        }
+        groups.push(group);
+        cells.entry(key).or_default().push(i);
+    }
+    groups
+}
+
+/// Smooth per vertex normals of `m` skinned at `positions`, one per vertex.
+pub fn vertex_normals(m: &Mesh, positions: &[Vector3<f32>], weighting: NormalWeighting) -> Vec<Vector3<f32>> {
+    let faces = face_normals(m, positions);
+    let mut sums = vec![Vector3::new(0.0, 0.0, 0.0); positions.len()];
+
+    for (t, triangle) in m.triangles.iter().enumerate() {
+        if let Some(face) = faces[t] {
+            let (i0, i1, i2) = triangle.vertex_indices;
+            for (corner, &v) in [i0, i1, i2].iter().enumerate() {
+                sums[v as usize] += face * corner_weight(m, positions, t, corner, weighting);
+            }
+        }
+    }
+
+    sums.into_iter().map(finite_normal).collect()
+}
+
+/// `sum` normalized, or `FALLBACK_NORMAL` when it is too short or not
+/// finite. Every normal computation goes through it, so that vertices
+/// without area around them get the same normal everywhere.
+pub fn finite_normal(sum: Vector3<f32>) -> Vector3<f32> {
+    let length = sum.magnitude();
+    if length > 1e-12 && length.is_finite() { sum / length } else { FALLBACK_NORMAL }
+}
+
+/// Normals of `m` skinned at `positions` following `options`. Vertices are
+/// split wherever their corners end up with different normals, so that hard
+/// edges stay sharp. Every normal is finite and of unit length.
+pub fn generate_normals(m: &Mesh, positions: &[Vector3<f32>], options: &NormalOptions) -> NormalMesh {
+    let faces = face_normals(m, positions);
+    let groups = position_groups(positions, options);
+    let threshold = options.smoothing_angle.map(|angle| angle.0.cos());
+
+    // Corners (triangle, corner) around each group of vertices.
+    let mut group_corners: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+    for (t, triangle) in m.triangles.iter().enumerate() {
+        let (i0, i1, i2) = triangle.vertex_indices;
+        for (corner, &v) in [i0, i1, i2].iter().enumerate() {
+            group_corners.entry(groups[v as usize]).or_default().push((t, corner));
+        }
+    }
+
+    let mut result = NormalMesh {
+        positions: positions.to_vec(),
+        normals: vec![FALLBACK_NORMAL; positions.len()],
+        source_vertices: (0..positions.len() as u32).collect(),
+        indices: Vec::with_capacity(m.triangles.len() * 3)
+    };
+    // Normals already given to each mesh vertex, with the vertex holding them.
+    let mut assigned: Vec<Vec<(Vector3<f32>, u32)>> = vec![Vec::new(); positions.len()];
+
+    for (t, triangle) in m.triangles.iter().enumerate() {
+        let (i0, i1, i2) = triangle.vertex_indices;
+
+        for &v in &[i0, i1, i2] {
+            let mut sum = Vector3::new(0.0, 0.0, 0.0);
+            for &(other, corner) in &group_corners[&groups[v as usize]] {
+                let other_face = match faces[other] {
+                    Some(face) => face,
+                    None => continue
+                };
+                let smoothed = match (threshold, faces[t]) {
+                    (Some(threshold), Some(face)) => face.dot(other_face) >= threshold,
+                    _ => true
+                };
+                if smoothed {
+                    sum += other_face * corner_weight(m, positions, other, corner, options.weighting);
+                }
+            }
+            let normal = finite_normal(sum);
+
+            let existing = assigned[v as usize].iter()
+                .find(|&&(n, _)| n.dot(normal) > 1.0 - 1e-6)
+                .map(|&(_, index)| index);
+            let index = match existing {
+                Some(index) => index,
+                None => {
+                    let index = if assigned[v as usize].is_empty() {
+                        result.normals[v as usize] = normal;
+                        v
+                    } else {
+                        result.positions.push(positions[v as usize]);
+                        result.normals.push(normal);
+                        result.source_vertices.push(v);
+                        (result.positions.len() - 1) as u32
+                    };
+                    assigned[v as usize].push((normal, index));
+                    index
+                }
+            };
+            result.indices.push(index);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2, Vector3, InnerSpace, Deg, Rad};
+    use md5::md5mesh::{Mesh, Vertex, Triangle};
+    use vertex_computation::compute::prepare_normals;
+    use vertex_computation::parallel::{PackedMesh, normals_into};
+    use super::{NormalOptions, NormalWeighting, FALLBACK_NORMAL};
+
+    /// Triangles from vertex index triples, no weights.
+    fn mesh(vertex_count: u32, triangles: &[(u32, u32, u32)]) -> Mesh {
+        Mesh {
+            shader: String::new(),
+            vertices: (0..vertex_count).map(|index| Vertex {
+                index,
+                tex_coords: Vector2::new(0.0, 0.0),
+                start_weight: 0,
+                weight_count: 0
+            }).collect(),
+            triangles: triangles.iter().enumerate().map(|(index, &vertex_indices)| Triangle {
+                index: index as u32,
+                vertex_indices
+            }).collect(),
+            weights: vec![]
+        }
+    }
+
+    // Two triangles folded at a right angle along the edge from 0 to 1.
+    fn fold() -> (Mesh, Vec<Vector3<f32>>) {
+        let positions = vec![
+            Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 1.0)
+        ];
+        (mesh(4, &[(0, 1, 2), (0, 3, 1)]), positions)
+    }
+
+    #[test]
+    fn smoothing_angle() {
+        let (m, positions) = fold();
+
+        let smooth = super::generate_normals(&m, &positions, &NormalOptions::default());
+        assert_eq!(smooth.positions.len(), 4);
+        let expected = Vector3::new(0.0, -1.0, -1.0).normalize();
+        assert!((smooth.normals[0] - expected).magnitude() < 1e-6);
+
+        let options = NormalOptions { smoothing_angle: Some(Rad::from(Deg(60.0))), .. NormalOptions::default() };
+        let hard = super::generate_normals(&m, &positions, &options);
+        // Both ends of the shared edge are split.
+        assert_eq!(hard.positions.len(), 6);
+        assert_eq!(hard.source_vertices[4..], [0, 1]);
+        for (i, &v) in hard.indices.iter().enumerate() {
+            let face = if i < 3 { Vector3::new(0.0, 0.0, -1.0) } else { Vector3::new(0.0, -1.0, 0.0) };
+            assert!((hard.normals[v as usize] - face).magnitude() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn weld_uv_seams() {
+        // The fold, with vertices 0 and 1 duplicated for the second
+        // triangle as 4 and 5.
+        let (_, mut positions) = fold();
+        positions.push(positions[0]);
+        positions.push(positions[1]);
+        let m = mesh(6, &[(0, 1, 2), (4, 3, 5)]);
+
+        let split = super::generate_normals(&m, &positions, &NormalOptions::default());
+        assert!((split.normals[0] - split.normals[4]).magnitude() > 0.1);
+
+        let options = NormalOptions { weld_uv_seams: true, .. NormalOptions::default() };
+        let welded = super::generate_normals(&m, &positions, &options);
+        assert!((welded.normals[0] - welded.normals[4]).magnitude() < 1e-6);
+        assert_eq!(welded.positions.len(), 6);
+    }
+
+    #[test]
+    fn weld_across_cells() {
+        // Closer than the weld distance, but on both sides of a cell
+        // boundary, and a vertex just too far from them.
+        let positions = vec![
+            Vector3::new(0.99e-5, 0.0, 0.0), Vector3::new(1.01e-5, 0.0, 0.0),
+            Vector3::new(2.2e-5, 0.0, 0.0)
+        ];
+        let options = NormalOptions { weld_uv_seams: true, .. NormalOptions::default() };
+        assert_eq!(super::position_groups(&positions, &options), [0, 0, 2]);
+    }
+
+    #[test]
+    fn angle_weighting() {
+        // Vertex 0 has a right angle in the first triangle and a narrow one
+        // in the second, which counts for less by angle than by area.
+        let positions = vec![
+            Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0), Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.1)
+        ];
+        let m = mesh(5, &[(0, 1, 2), (0, 4, 3)]);
+
+        let area = super::vertex_normals(&m, &positions, NormalWeighting::Area);
+        let angle = super::vertex_normals(&m, &positions, NormalWeighting::Angle);
+        let first_face = Vector3::new(0.0, 0.0, -1.0);
+        assert!(angle[0].dot(first_face) > area[0].dot(first_face));
+    }
+
+    #[test]
+    fn finite_output() {
+        // A triangle without area and a vertex used by no triangle.
+        let positions = vec![
+            Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(2.0, 0.0, 0.0), Vector3::new(5.0, 5.0, 5.0)
+        ];
+        let m = mesh(4, &[(0, 1, 2)]);
+
+        let normals = super::generate_normals(&m, &positions, &NormalOptions::default());
+        for n in normals.normals.iter().chain(&super::vertex_normals(&m, &positions, NormalWeighting::Angle)) {
+            assert!(n.x.is_finite() && n.y.is_finite() && n.z.is_finite());
+            assert!((n.magnitude() - 1.0).abs() < 1e-6);
+        }
+
+        // The same fallback everywhere.
+        assert_eq!(normals.normals[3], FALLBACK_NORMAL);
+        assert_eq!(prepare_normals(&m, &positions), vec![FALLBACK_NORMAL; 4]);
+        let mut packed = vec![Vector3::new(0.0, 0.0, 0.0); 4];
+        normals_into(&PackedMesh::new(&m), &positions, &mut packed);
+        assert_eq!(packed, vec![FALLBACK_NORMAL; 4]);
+    }
+}
@@ -0,0 +1,180 @@
+use cgmath::{Vector3, Vector4, Matrix4, InnerSpace};
+use md5::md5mesh::{Mesh, Joint};
+use vertex_computation::compute::prepare_mesh;
+use vertex_computation::normals::finite_normal;
+use vertex_computation::joint_normals::JointSpaceNormals;
+
+/// A mesh converted once from MD5 weights to the classic skinning layout:
+/// bind pose vertices, each following a few joints with blend weights.
+///
+/// MD5 weights store one position per joint; they are expected to agree on
+/// the bind pose vertex, as they do in exported files. When they do not, the
+/// weighted average is used.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BindPoseMesh {
+    pub positions: Vec<Vector3<f32>>,
+    /// Influences of vertex `i` are at
+    /// `influence_start[i]..influence_start[i + 1]`.
+    pub influence_start: Vec<u32>,
+    pub influence_joint: Vec<u32>,
+    pub influence_weight: Vec<f32>,
+    /// Joint space normal of each influence taken back to the bind pose,
+    /// set by `with_joint_normals` for `apply_palette_normals_into`.
+    pub influence_normal: Vec<Vector3<f32>>,
+}
+
+impl BindPoseMesh {
+    pub fn new(m: &Mesh, bind: &Vec<Joint>) -> BindPoseMesh {
+        let mut mesh = BindPoseMesh {
+            positions: prepare_mesh(m, bind),
+            influence_start: Vec::with_capacity(m.vertices.len() + 1),
+            influence_joint: Vec::with_capacity(m.weights.len()),
+            influence_weight: Vec::with_capacity(m.weights.len()),
+            influence_normal: Vec::new()
+        };
+
+        mesh.influence_start.push(0);
+        for vertex in &m.vertices {
+            for w in &m.weights[vertex.start_weight as usize..(vertex.start_weight + vertex.weight_count) as usize] {
+                mesh.influence_joint.push(w.joint_index);
+                mesh.influence_weight.push(w.bias);
+            }
+            mesh.influence_start.push(mesh.influence_joint.len() as u32);
+        }
+
+        mesh
+    }
+
+    /// Adds `normals`, computed for `m` in the `bind` pose, so that the
+    /// palette rotates them as `skin_normals` does.
+    pub fn with_joint_normals(mut self, m: &Mesh, bind: &[Joint], normals: &JointSpaceNormals) -> BindPoseMesh {
+        self.influence_normal.clear();
+        for vertex in &m.vertices {
+            for w in vertex.start_weight as usize..(vertex.start_weight + vertex.weight_count) as usize {
+                let joint = &bind[m.weights[w].joint_index as usize];
+                self.influence_normal.push(joint.orientation.normalize() * normals.normals[w]);
+            }
+        }
+        self
+    }
+}
+
+/// Object space transform of a joint.
+pub fn joint_matrix(joint: &Joint) -> Matrix4<f32> {
+    Matrix4::from_translation(joint.position) * Matrix4::from(joint.orientation.normalize())
+}
+
+/// Inverse of the transform of every bind pose joint, computed once per
+/// model.
+pub fn inverse_bind_matrices(bind: &[Joint]) -> Vec<Matrix4<f32>> {
+    bind.iter().map(|joint| {
+        let inverse = joint.orientation.normalize().conjugate();
+        Matrix4::from(inverse) * Matrix4::from_translation(-joint.position)
+    }).collect()
+}
+
+/// Matrix palette taking bind pose vertices to `posed`, one matrix per
+/// joint, written into `out`.
+pub fn skinning_matrices_into(inverse_bind: &[Matrix4<f32>], posed: &[Joint], out: &mut Vec<Matrix4<f32>>) {
+    out.clear();
+    out.extend(posed.iter().zip(inverse_bind).map(|(joint, inverse)| joint_matrix(joint) * inverse));
+}
+
+pub fn skinning_matrices(inverse_bind: &[Matrix4<f32>], posed: &[Joint]) -> Vec<Matrix4<f32>> {
+    let mut palette = Vec::with_capacity(posed.len());
+    skinning_matrices_into(inverse_bind, posed, &mut palette);
+    palette
+}
+
+/// Shader data for a palette, column-major like GLSL matrices.
+pub fn palette_to_arrays(palette: &[Matrix4<f32>]) -> Vec<[[f32; 4]; 4]> {
+    palette.iter().map(|&m| m.into()).collect()
+}
+
+/// Skins `mesh` with `palette` into `out`, `mesh.positions.len()` long.
+pub fn apply_palette_into(mesh: &BindPoseMesh, palette: &[Matrix4<f32>], out: &mut [Vector3<f32>]) {
+    for (i, (position, skinned)) in mesh.positions.iter().zip(out.iter_mut()).enumerate() {
+        let point = Vector4::new(position.x, position.y, position.z, 1.0);
+        let mut sum = Vector4::new(0.0, 0.0, 0.0, 0.0);
+
+        for k in mesh.influence_start[i] as usize..mesh.influence_start[i + 1] as usize {
+            sum += (palette[mesh.influence_joint[k] as usize] * point) * mesh.influence_weight[k];
+        }
+
+        *skinned = sum.truncate();
+    }
+}
+
+/// Normals of `mesh`, which needs `with_joint_normals`, rotated by
+/// `palette` into `out`.
+pub fn apply_palette_normals_into(mesh: &BindPoseMesh, palette: &[Matrix4<f32>], out: &mut [Vector3<f32>]) {
+    for (i, skinned) in out.iter_mut().enumerate() {
+        let mut sum = Vector4::new(0.0, 0.0, 0.0, 0.0);
+
+        for k in mesh.influence_start[i] as usize..mesh.influence_start[i + 1] as usize {
+            sum += (palette[mesh.influence_joint[k] as usize] * mesh.influence_normal[k].extend(0.0)) * mesh.influence_weight[k];
+        }
+
+        *skinned = finite_normal(sum.truncate());
+    }
+}
+
+pub fn apply_palette_normals(mesh: &BindPoseMesh, palette: &[Matrix4<f32>]) -> Vec<Vector3<f32>> {
+    let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); mesh.positions.len()];
+    apply_palette_normals_into(mesh, palette, &mut normals);
+    normals
+}
+
+/// Matrix palette counterpart of `prepare_mesh`.
+pub fn apply_palette(mesh: &BindPoseMesh, palette: &[Matrix4<f32>]) -> Vec<Vector3<f32>> {
+    let mut positions = vec![Vector3::new(0.0, 0.0, 0.0); mesh.positions.len()];
+    apply_palette_into(mesh, palette, &mut positions);
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+    use animation::pose::frame_skeleton;
+    use vertex_computation::compute::prepare_mesh;
+    use test_support::{bob_mesh, bob_anim, bob_skeleton};
+    use vertex_computation::joint_normals::{JointSpaceNormals, skin_normals};
+    use super::{BindPoseMesh, inverse_bind_matrices, skinning_matrices};
+
+    #[test]
+    fn apply_palette() {
+        let mesh = bob_mesh();
+        let anim = bob_anim();
+
+        let bind_meshes: Vec<BindPoseMesh> = mesh.meshes.iter().map(|m| BindPoseMesh::new(m, &mesh.joints)).collect();
+        let inverse_bind = inverse_bind_matrices(&mesh.joints);
+
+        for &frame in &[0, 40, 100] {
+            let skeleton = frame_skeleton(&anim, frame);
+            let palette = skinning_matrices(&inverse_bind, &skeleton);
+
+            for (m, bind_mesh) in mesh.meshes.iter().zip(&bind_meshes) {
+                let expected = prepare_mesh(m, &skeleton);
+                for (a, b) in super::apply_palette(bind_mesh, &palette).iter().zip(&expected) {
+                    assert!((a - b).magnitude() < 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn apply_palette_normals() {
+        let mesh = bob_mesh();
+        let skeleton = bob_skeleton(40);
+        let palette = skinning_matrices(&inverse_bind_matrices(&mesh.joints), &skeleton);
+
+        for m in &mesh.meshes {
+            let normals = JointSpaceNormals::from_bind_pose(m, &mesh.joints);
+            let bind_mesh = BindPoseMesh::new(m, &mesh.joints).with_joint_normals(m, &mesh.joints, &normals);
+            let expected = skin_normals(m, &normals, &skeleton);
+            for (a, b) in super::apply_palette_normals(&bind_mesh, &palette).iter().zip(&expected) {
+                assert!((a - b).magnitude() < 1e-4);
+            }
+        }
+    }
+}
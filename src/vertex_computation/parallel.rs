@@ -0,0 +1,327 @@
+use rayon::prelude::*;
+use cgmath::{Vector3, Matrix3, InnerSpace};
+use md5::md5mesh::{Md5Mesh, Mesh, Joint};
+use vertex_computation::normals::finite_normal;
+use vertex_computation::joint_normals::JointSpaceNormals;
+
+/// Vertices skinned by one rayon task.
+pub const CHUNK_SIZE: usize = 256;
+
+/// A mesh laid out for skinning in bulk: weights are stored as a structure
+/// of arrays, in vertex order.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PackedMesh {
+    /// Weights of vertex `i` are at `weight_start[i]..weight_start[i + 1]`.
+    pub weight_start: Vec<u32>,
+    pub weight_joint: Vec<u32>,
+    pub weight_bias: Vec<f32>,
+    pub weight_x: Vec<f32>,
+    pub weight_y: Vec<f32>,
+    pub weight_z: Vec<f32>,
+    /// Joint space normal of each weight, set by `with_joint_normals`. When
+    /// empty, normals are rebuilt from the skinned triangles instead.
+    pub weight_nx: Vec<f32>,
+    pub weight_ny: Vec<f32>,
+    pub weight_nz: Vec<f32>,
+    /// Three vertex indices per triangle.
+    pub indices: Vec<u32>,
+}
+
+impl PackedMesh {
+    pub fn new(m: &Mesh) -> PackedMesh {
+        let weight_count: usize = m.vertices.iter().map(|v| v.weight_count as usize).sum();
+        let mut packed = PackedMesh {
+            weight_start: Vec::with_capacity(m.vertices.len() + 1),
+            weight_joint: Vec::with_capacity(weight_count),
+            weight_bias: Vec::with_capacity(weight_count),
+            weight_x: Vec::with_capacity(weight_count),
+            weight_y: Vec::with_capacity(weight_count),
+            weight_z: Vec::with_capacity(weight_count),
+            weight_nx: Vec::new(),
+            weight_ny: Vec::new(),
+            weight_nz: Vec::new(),
+            indices: Vec::with_capacity(m.triangles.len() * 3)
+        };
+
+        packed.weight_start.push(0);
+        for vertex in &m.vertices {
+            for w in &m.weights[vertex.start_weight as usize..(vertex.start_weight + vertex.weight_count) as usize] {
+                packed.weight_joint.push(w.joint_index);
+                packed.weight_bias.push(w.bias);
+                packed.weight_x.push(w.position.x);
+                packed.weight_y.push(w.position.y);
+                packed.weight_z.push(w.position.z);
+            }
+            packed.weight_start.push(packed.weight_joint.len() as u32);
+        }
+
+        for t in &m.triangles {
+            let (a, b, c) = t.vertex_indices;
+            packed.indices.extend_from_slice(&[a, b, c]);
+        }
+
+        packed
+    }
+
+    /// Skins normals by rotating `normals`, computed for `m`, the mesh this
+    /// was packed from, rather than rebuilding them from the triangles.
+    pub fn with_joint_normals(mut self, m: &Mesh, normals: &JointSpaceNormals) -> PackedMesh {
+        self.weight_nx.clear();
+        self.weight_ny.clear();
+        self.weight_nz.clear();
+        for vertex in &m.vertices {
+            for n in &normals.normals[vertex.start_weight as usize..(vertex.start_weight + vertex.weight_count) as usize] {
+                self.weight_nx.push(n.x);
+                self.weight_ny.push(n.y);
+                self.weight_nz.push(n.z);
+            }
+        }
+        self
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.weight_start.len() - 1
+    }
+}
+
+/// Every mesh of a model, packed, with the offset of its first vertex in
+/// buffers holding the whole model.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PackedModel {
+    pub meshes: Vec<PackedMesh>,
+    pub offsets: Vec<usize>,
+    pub vertex_count: usize,
+}
+
+impl PackedModel {
+    pub fn new(ms: &Md5Mesh) -> PackedModel {
+        let meshes: Vec<PackedMesh> = ms.meshes.iter().map(PackedMesh::new).collect();
+        let mut offsets = Vec::with_capacity(meshes.len());
+        let mut vertex_count = 0;
+        for mesh in &meshes {
+            offsets.push(vertex_count);
+            vertex_count += mesh.vertex_count();
+        }
+        PackedModel { meshes, offsets, vertex_count }
+    }
+
+    /// `PackedMesh::with_joint_normals` for every mesh, `normals` being in
+    /// the order of `ms.meshes`.
+    pub fn with_joint_normals(mut self, ms: &Md5Mesh, normals: &[JointSpaceNormals]) -> PackedModel {
+        self.meshes = self.meshes.into_iter().zip(ms.meshes.iter().zip(normals))
+            .map(|(packed, (m, n))| packed.with_joint_normals(m, n))
+            .collect();
+        self
+    }
+}
+
+/// Joint transforms with rotations as row-major 3x3 matrices, cheaper to
+/// apply to many weights than quaternions.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct JointPalette {
+    pub rotation: Vec<[f32; 9]>,
+    pub translation: Vec<[f32; 3]>,
+}
+
+impl JointPalette {
+    pub fn new(joints: &[Joint]) -> JointPalette {
+        let mut palette = JointPalette::default();
+        palette.update(joints);
+        palette
+    }
+
+    /// Refills the palette for a new pose, reusing its storage.
+    pub fn update(&mut self, joints: &[Joint]) {
+        self.rotation.clear();
+        self.translation.clear();
+        for joint in joints {
+            let m = Matrix3::from(joint.orientation.normalize());
+            // cgmath matrices are column-major.
+            self.rotation.push([m.x.x, m.y.x, m.z.x,
+                                m.x.y, m.y.y, m.z.y,
+                                m.x.z, m.y.z, m.z.z]);
+            self.translation.push([joint.position.x, joint.position.y, joint.position.z]);
+        }
+    }
+}
+
+/// Skins vertices `first..first + out.len()` of `mesh` into `out`.
+pub fn skin_vertices_into(mesh: &PackedMesh, palette: &JointPalette, first: usize, out: &mut [Vector3<f32>]) {
+    for (i, position) in out.iter_mut().enumerate() {
+        let start = mesh.weight_start[first + i] as usize;
+        let end = mesh.weight_start[first + i + 1] as usize;
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+
+        for k in start..end {
+            let joint = mesh.weight_joint[k] as usize;
+            let r = &palette.rotation[joint];
+            let t = &palette.translation[joint];
+            let (wx, wy, wz, bias) = (mesh.weight_x[k], mesh.weight_y[k], mesh.weight_z[k], mesh.weight_bias[k]);
+
+            x += (t[0] + r[0] * wx + r[1] * wy + r[2] * wz) * bias;
+            y += (t[1] + r[3] * wx + r[4] * wy + r[5] * wz) * bias;
+            z += (t[2] + r[6] * wx + r[7] * wy + r[8] * wz) * bias;
+        }
+
+        *position = Vector3::new(x, y, z);
+    }
+}
+
+/// Normals of vertices `first..first + out.len()` of `mesh`, rotated from
+/// its joint space normals.
+pub fn skin_joint_normals_into(mesh: &PackedMesh, palette: &JointPalette, first: usize, out: &mut [Vector3<f32>]) {
+    for (i, normal) in out.iter_mut().enumerate() {
+        let start = mesh.weight_start[first + i] as usize;
+        let end = mesh.weight_start[first + i + 1] as usize;
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+
+        for k in start..end {
+            let r = &palette.rotation[mesh.weight_joint[k] as usize];
+            let (nx, ny, nz, bias) = (mesh.weight_nx[k], mesh.weight_ny[k], mesh.weight_nz[k], mesh.weight_bias[k]);
+
+            x += (r[0] * nx + r[1] * ny + r[2] * nz) * bias;
+            y += (r[3] * nx + r[4] * ny + r[5] * nz) * bias;
+            z += (r[6] * nx + r[7] * ny + r[8] * nz) * bias;
+        }
+
+        *normal = finite_normal(Vector3::new(x, y, z));
+    }
+}
+
+/// Normals of `mesh` skinned at `positions`, weighted by the area of the
+/// triangles around them as `prepare_normals` does. Vertices without area
+/// get `FALLBACK_NORMAL`.
+pub fn normals_into(mesh: &PackedMesh, positions: &[Vector3<f32>], out: &mut [Vector3<f32>]) {
+    for normal in out.iter_mut() {
+        *normal = Vector3::new(0.0, 0.0, 0.0);
+    }
+
+    for triangle in mesh.indices.chunks(3) {
+        let (i0, i1, i2) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+        let v0 = positions[i0];
+        let cross_product = (positions[i2] - v0).cross(positions[i1] - v0);
+        out[i0] += cross_product;
+        out[i1] += cross_product;
+        out[i2] += cross_product;
+    }
+
+    for normal in out.iter_mut() {
+        *normal = finite_normal(*normal);
+    }
+}
+
+/// Skins one mesh into `positions` and `normals`, both `vertex_count` long.
+/// Positions are computed in parallel chunks of `CHUNK_SIZE` vertices,
+/// normals, which need the positions of neighbouring chunks, afterwards.
+/// Joint space normals, when the mesh has them, are rotated along with the
+/// positions instead.
+pub fn skin_mesh_into(mesh: &PackedMesh, palette: &JointPalette,
+                      positions: &mut [Vector3<f32>], normals: &mut [Vector3<f32>]) {
+    if !mesh.weight_nx.is_empty() {
+        positions.par_chunks_mut(CHUNK_SIZE).zip(normals.par_chunks_mut(CHUNK_SIZE)).enumerate()
+            .for_each(|(c, (p, n))| {
+                skin_vertices_into(mesh, palette, c * CHUNK_SIZE, p);
+                skin_joint_normals_into(mesh, palette, c * CHUNK_SIZE, n);
+            });
+        return;
+    }
+
+    if positions.len() <= CHUNK_SIZE {
+        skin_vertices_into(mesh, palette, 0, positions);
+    } else {
+        positions.par_chunks_mut(CHUNK_SIZE).enumerate().for_each(|(c, chunk)| {
+            skin_vertices_into(mesh, palette, c * CHUNK_SIZE, chunk);
+        });
+    }
+    normals_into(mesh, positions, normals);
+}
+
+/// `prepare_full_mesh` into caller buffers of `model.vertex_count` elements,
+/// which can be kept from one frame to the next. Meshes are skinned in
+/// parallel.
+pub fn skin_model_into(model: &PackedModel, palette: &JointPalette,
+                       positions: &mut [Vector3<f32>], normals: &mut [Vector3<f32>]) {
+    assert!(positions.len() >= model.vertex_count && normals.len() >= model.vertex_count);
+
+    let mut jobs = Vec::with_capacity(model.meshes.len());
+    let (mut positions, mut normals) = (positions, normals);
+    for mesh in &model.meshes {
+        let (p, rest_p) = positions.split_at_mut(mesh.vertex_count());
+        let (n, rest_n) = normals.split_at_mut(mesh.vertex_count());
+        jobs.push((mesh, p, n));
+        positions = rest_p;
+        normals = rest_n;
+    }
+
+    jobs.into_par_iter().for_each(|(mesh, p, n)| skin_mesh_into(mesh, palette, p, n));
+}
+
+/// One character to skin as part of a batch.
+pub struct SkinTarget<'a> {
+    pub model: &'a PackedModel,
+    pub palette: &'a JointPalette,
+    pub positions: &'a mut [Vector3<f32>],
+    pub normals: &'a mut [Vector3<f32>],
+}
+
+/// Skins many characters at once, in parallel. Characters are a better unit
+/// of work than the vertices of a single one, which are too few to make up
+/// for the cost of spreading them over threads.
+pub fn skin_batch_into(targets: &mut [SkinTarget]) {
+    targets.par_iter_mut().for_each(|target| {
+        skin_model_into(target.model, target.palette, target.positions, target.normals);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, InnerSpace};
+    use vertex_computation::compute::{prepare_mesh, prepare_normals};
+    use test_support::{bob_mesh, bob_skeleton};
+    use vertex_computation::joint_normals::{JointSpaceNormals, skin_normals};
+    use super::{PackedModel, JointPalette};
+
+    #[test]
+    fn skin_model_into() {
+        let mesh = bob_mesh();
+        let skeleton = bob_skeleton(30);
+        let model = PackedModel::new(&mesh);
+        let palette = JointPalette::new(&skeleton);
+
+        let mut positions = vec![Vector3::new(0.0, 0.0, 0.0); model.vertex_count];
+        let mut normals = positions.clone();
+        super::skin_model_into(&model, &palette, &mut positions, &mut normals);
+
+        for (m, &offset) in mesh.meshes.iter().zip(&model.offsets) {
+            let expected = prepare_mesh(m, &skeleton);
+            let expected_normals = prepare_normals(m, &expected);
+            for (i, (p, n)) in expected.iter().zip(&expected_normals).enumerate() {
+                assert!((positions[offset + i] - p).magnitude() < 1e-4);
+                assert!((normals[offset + i] - n).magnitude() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn joint_normals() {
+        let mesh = bob_mesh();
+        let skeleton = bob_skeleton(30);
+        let joint_normals: Vec<JointSpaceNormals> = mesh.meshes.iter()
+            .map(|m| JointSpaceNormals::from_bind_pose(m, &mesh.joints))
+            .collect();
+        let model = PackedModel::new(&mesh).with_joint_normals(&mesh, &joint_normals);
+        let palette = JointPalette::new(&skeleton);
+
+        let mut positions = vec![Vector3::new(0.0, 0.0, 0.0); model.vertex_count];
+        let mut normals = positions.clone();
+        super::skin_model_into(&model, &palette, &mut positions, &mut normals);
+
+        for ((m, n), &offset) in mesh.meshes.iter().zip(&joint_normals).zip(&model.offsets) {
+            let expected = prepare_mesh(m, &skeleton);
+            for (i, (p, n)) in expected.iter().zip(&skin_normals(m, n, &skeleton)).enumerate() {
+                assert!((positions[offset + i] - p).magnitude() < 1e-4);
+                assert!((normals[offset + i] - n).magnitude() < 1e-4);
+            }
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use cgmath::{Vector3, InnerSpace};
+use md5::md5mesh::{Md5Mesh, Mesh};
+
+/// Triangles per BVH leaf.
+pub const LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    /// Need not be normalized; hit distances are in multiples of it.
+    pub direction: Vector3<f32>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct RayHit<'a> {
+    pub distance: f32,
+    /// Weights of the three triangle vertices at the hit point.
+    pub barycentrics: Vector3<f32>,
+    pub mesh: usize,
+    pub triangle: usize,
+    pub shader: &'a str,
+    /// Joint with the most influence at the hit point.
+    pub joint: u32,
+}
+
+/// Distance along `ray` and barycentrics of its hit with a triangle, both
+/// sides counting (Möller–Trumbore).
+pub fn intersect_triangle(ray: &Ray, v0: Vector3<f32>, v1: Vector3<f32>, v2: Vector3<f32>) -> Option<(f32, Vector3<f32>)> {
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let p = ray.direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+
+    let inverse = 1.0 / determinant;
+    let s = ray.origin - v0;
+    let u = s.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = edge2.dot(q) * inverse;
+    if distance < 0.0 {
+        return None;
+    }
+    Some((distance, Vector3::new(1.0 - u - v, u, v)))
+}
+
+/// Joint weighing the most on triangle `triangle` of `m` at `barycentrics`,
+/// summing the biases of its vertices.
+pub fn dominant_joint(m: &Mesh, triangle: usize, barycentrics: Vector3<f32>) -> u32 {
+    let (a, b, c) = m.triangles[triangle].vertex_indices;
+    let mut influences: Vec<(u32, f32)> = Vec::new();
+
+    for (&vertex, &share) in [a, b, c].iter().zip(&[barycentrics.x, barycentrics.y, barycentrics.z]) {
+        let vertex = &m.vertices[vertex as usize];
+        for w in &m.weights[vertex.start_weight as usize..(vertex.start_weight + vertex.weight_count) as usize] {
+            match influences.iter_mut().find(|&&mut (joint, _)| joint == w.joint_index) {
+                Some(influence) => influence.1 += w.bias * share,
+                None => influences.push((w.joint_index, w.bias * share))
+            }
+        }
+    }
+
+    influences.iter().fold((0, -1.0), |best, &influence| if influence.1 > best.1 { influence } else { best }).0
+}
+
+fn triangle_positions(m: &Mesh, positions: &[Vector3<f32>], triangle: usize) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+    let (a, b, c) = m.triangles[triangle].vertex_indices;
+    (positions[a as usize], positions[b as usize], positions[c as usize])
+}
+
+fn make_hit(ms: &Md5Mesh, mesh: usize, triangle: usize, distance: f32, barycentrics: Vector3<f32>) -> RayHit<'_> {
+    let m = &ms.meshes[mesh];
+    RayHit {
+        distance,
+        barycentrics,
+        mesh,
+        triangle,
+        shader: &m.shader,
+        joint: dominant_joint(m, triangle, barycentrics)
+    }
+}
+
+/// Closest hit of `ray` with `ms` skinned at `positions`, one vector per
+/// mesh as `prepare_mesh` returns them, testing every triangle.
+pub fn pick<'a, P: AsRef<[Vector3<f32>]>>(ms: &'a Md5Mesh, positions: &[P], ray: &Ray) -> Option<RayHit<'a>> {
+    let mut closest: Option<(usize, usize, f32, Vector3<f32>)> = None;
+
+    for (mesh, (m, p)) in ms.meshes.iter().zip(positions).enumerate() {
+        for triangle in 0..m.triangles.len() {
+            let (v0, v1, v2) = triangle_positions(m, p.as_ref(), triangle);
+            if let Some((distance, barycentrics)) = intersect_triangle(ray, v0, v1, v2) {
+                if closest.is_none_or(|c| distance < c.2) {
+                    closest = Some((mesh, triangle, distance, barycentrics));
+                }
+            }
+        }
+    }
+
+    closest.map(|(mesh, triangle, distance, barycentrics)| make_hit(ms, mesh, triangle, distance, barycentrics))
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct PickNode {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+    /// Leaves hold `count` triangles from `start`; inner nodes have their
+    /// left child right after them and their right child at `start`.
+    pub start: usize,
+    pub count: usize,
+}
+
+/// Bounding volume hierarchy over the triangles of a skinned model. Built
+/// once, its topology stays valid from one pose to the next: `refit` only
+/// updates the boxes, which is much cheaper than `new` but looser when the
+/// pose is far from the one it was built in.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TriangleBvh {
+    pub nodes: Vec<PickNode>,
+    /// (mesh, triangle) pairs, ordered by leaf.
+    pub triangles: Vec<(usize, usize)>,
+}
+
+fn triangle_box(ms: &Md5Mesh, positions: &[&[Vector3<f32>]], (mesh, triangle): (usize, usize)) -> (Vector3<f32>, Vector3<f32>) {
+    let (v0, v1, v2) = triangle_positions(&ms.meshes[mesh], positions[mesh], triangle);
+    (Vector3::new(v0.x.min(v1.x).min(v2.x), v0.y.min(v1.y).min(v2.y), v0.z.min(v1.z).min(v2.z)),
+     Vector3::new(v0.x.max(v1.x).max(v2.x), v0.y.max(v1.y).max(v2.y), v0.z.max(v1.z).max(v2.z)))
+}
+
+fn union(a: (Vector3<f32>, Vector3<f32>), b: (Vector3<f32>, Vector3<f32>)) -> (Vector3<f32>, Vector3<f32>) {
+    (Vector3::new(a.0.x.min(b.0.x), a.0.y.min(b.0.y), a.0.z.min(b.0.z)),
+     Vector3::new(a.1.x.max(b.1.x), a.1.y.max(b.1.y), a.1.z.max(b.1.z)))
+}
+
+/// Distance along `ray` at which it enters the box, if it does.
+fn intersect_box(ray: &Ray, min: Vector3<f32>, max: Vector3<f32>) -> Option<f32> {
+    let (mut near, mut far) = (0.0f32, f32::INFINITY);
+    for axis in 0..3 {
+        // A ray parallel to a slab never crosses it: it is inside for its
+        // whole length or never.
+        if ray.direction[axis] == 0.0 {
+            if ray.origin[axis] < min[axis] || ray.origin[axis] > max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let inverse = 1.0 / ray.direction[axis];
+        let t0 = (min[axis] - ray.origin[axis]) * inverse;
+        let t1 = (max[axis] - ray.origin[axis]) * inverse;
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+    }
+    if near <= far { Some(near) } else { None }
+}
+
+fn as_slices<P: AsRef<[Vector3<f32>]>>(positions: &[P]) -> Vec<&[Vector3<f32>]> {
+    positions.iter().map(|p| p.as_ref()).collect()
+}
+
+impl TriangleBvh {
+    pub fn new<P: AsRef<[Vector3<f32>]>>(ms: &Md5Mesh, positions: &[P]) -> TriangleBvh {
+        let positions = as_slices(positions);
+        let mut bvh = TriangleBvh {
+            nodes: Vec::new(),
+            triangles: ms.meshes.iter().enumerate()
+                .flat_map(|(mesh, m)| (0..m.triangles.len()).map(move |triangle| (mesh, triangle)))
+                .collect()
+        };
+        let count = bvh.triangles.len();
+        if count > 0 {
+            bvh.build(ms, &positions, 0, count);
+        }
+        bvh
+    }
+
+    /// Splits `triangles[start..start + count]` at the median of their
+    /// centers along the longest axis.
+    fn build(&mut self, ms: &Md5Mesh, positions: &[&[Vector3<f32>]], start: usize, count: usize) -> usize {
+        let bounds = self.triangles[start..start + count].iter()
+            .map(|&t| triangle_box(ms, positions, t))
+            .fold(None, |acc, b| Some(acc.map_or(b, |acc| union(acc, b)))).unwrap();
+        let index = self.nodes.len();
+        self.nodes.push(PickNode { min: bounds.0, max: bounds.1, start, count });
+        if count <= LEAF_SIZE {
+            return index;
+        }
+
+        let extent = bounds.1 - bounds.0;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+        let center = |t: (usize, usize)| {
+            let (min, max) = triangle_box(ms, positions, t);
+            min[axis] + max[axis]
+        };
+        self.triangles[start..start + count].sort_by(|&a, &b| {
+            center(a).partial_cmp(&center(b)).unwrap_or(::std::cmp::Ordering::Equal)
+        });
+
+        let half = count / 2;
+        self.build(ms, positions, start, half);
+        let right = self.build(ms, positions, start + half, count - half);
+        self.nodes[index].start = right;
+        self.nodes[index].count = 0;
+        index
+    }
+
+    /// Updates the boxes for the model skinned at `positions`.
+    pub fn refit<P: AsRef<[Vector3<f32>]>>(&mut self, ms: &Md5Mesh, positions: &[P]) {
+        let positions = as_slices(positions);
+        // Children always come after their parent.
+        for index in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[index];
+            let bounds = if node.count > 0 {
+                self.triangles[node.start..node.start + node.count].iter()
+                    .map(|&t| triangle_box(ms, &positions, t))
+                    .fold(None, |acc, b| Some(acc.map_or(b, |acc| union(acc, b)))).unwrap()
+            } else {
+                let (left, right) = (&self.nodes[index + 1], &self.nodes[node.start]);
+                union((left.min, left.max), (right.min, right.max))
+            };
+            self.nodes[index].min = bounds.0;
+            self.nodes[index].max = bounds.1;
+        }
+    }
+
+    /// `pick`, skipping the triangles in boxes the ray misses or enters
+    /// beyond the closest hit so far. `positions` must be those the
+    /// hierarchy was built or last refit with.
+    pub fn pick<'a, P: AsRef<[Vector3<f32>]>>(&self, ms: &'a Md5Mesh, positions: &[P], ray: &Ray) -> Option<RayHit<'a>> {
+        let positions = as_slices(positions);
+        let mut closest: Option<(usize, usize, f32, Vector3<f32>)> = None;
+        let mut stack = if self.nodes.is_empty() { vec![] } else { vec![0] };
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            match intersect_box(ray, node.min, node.max) {
+                Some(near) if closest.is_none_or(|c| near <= c.2) => (),
+                _ => continue
+            }
+
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(index + 1);
+                continue;
+            }
+
+            for &(mesh, triangle) in &self.triangles[node.start..node.start + node.count] {
+                let (v0, v1, v2) = triangle_positions(&ms.meshes[mesh], positions[mesh], triangle);
+                if let Some((distance, barycentrics)) = intersect_triangle(ray, v0, v1, v2) {
+                    if closest.is_none_or(|c| distance < c.2) {
+                        closest = Some((mesh, triangle, distance, barycentrics));
+                    }
+                }
+            }
+        }
+
+        closest.map(|(mesh, triangle, distance, barycentrics)| make_hit(ms, mesh, triangle, distance, barycentrics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, InnerSpace};
+    use animation::pose::frame_skeleton;
+    use vertex_computation::compute::prepare_mesh;
+    use test_support::{bob_mesh, bob_anim};
+    use super::{Ray, TriangleBvh};
+
+    #[test]
+    fn intersect_triangle() {
+        let ray = Ray { origin: Vector3::new(0.25, 0.25, 1.0), direction: Vector3::new(0.0, 0.0, -1.0) };
+        let (distance, barycentrics) = super::intersect_triangle(&ray, Vector3::new(0.0, 0.0, 0.0),
+                                                                  Vector3::new(1.0, 0.0, 0.0),
+                                                                  Vector3::new(0.0, 1.0, 0.0)).unwrap();
+        assert!((distance - 1.0).abs() < 1e-6);
+        assert!((barycentrics - Vector3::new(0.5, 0.25, 0.25)).magnitude() < 1e-6);
+
+        let away = Ray { direction: Vector3::new(0.0, 0.0, 1.0), ..ray };
+        assert_eq!(super::intersect_triangle(&away, Vector3::new(0.0, 0.0, 0.0),
+                                             Vector3::new(1.0, 0.0, 0.0),
+                                             Vector3::new(0.0, 1.0, 0.0)), None);
+    }
+
+    #[test]
+    fn intersect_box() {
+        let (min, max) = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0));
+        let ray = Ray { origin: Vector3::new(-1.0, 0.5, 0.5), direction: Vector3::new(1.0, 0.0, 0.0) };
+        assert_eq!(super::intersect_box(&ray, min, max), Some(1.0));
+
+        // Along the face y = 0, and along its edge with z = 1.
+        let on_face = Ray { origin: Vector3::new(-1.0, 0.0, 0.5), ..ray };
+        assert_eq!(super::intersect_box(&on_face, min, max), Some(1.0));
+        let on_edge = Ray { origin: Vector3::new(-1.0, 0.0, 1.0), ..ray };
+        assert_eq!(super::intersect_box(&on_edge, min, max), Some(1.0));
+
+        let beside = Ray { origin: Vector3::new(-1.0, -0.5, 0.5), ..ray };
+        assert_eq!(super::intersect_box(&beside, min, max), None);
+    }
+
+    #[test]
+    fn pick_vertex() {
+        let mesh = bob_mesh();
+        let positions: Vec<Vec<Vector3<f32>>> = mesh.meshes.iter().map(|m| prepare_mesh(m, &mesh.joints)).collect();
+
+        // Aim at the center of a triangle from outside the model.
+        let m = &mesh.meshes[0];
+        let (a, b, c) = m.triangles[100].vertex_indices;
+        let target = (positions[0][a as usize] + positions[0][b as usize] + positions[0][c as usize]) / 3.0;
+        let normal = (positions[0][c as usize] - positions[0][a as usize]).cross(positions[0][b as usize] - positions[0][a as usize]).normalize();
+        let ray = Ray { origin: target + normal * 10.0, direction: -normal };
+
+        let hit = super::pick(&mesh, &positions, &ray).unwrap();
+        assert!(hit.distance <= 10.0 + 1e-3);
+        assert_eq!(hit.shader, mesh.meshes[hit.mesh].shader);
+        assert!((hit.barycentrics.x + hit.barycentrics.y + hit.barycentrics.z - 1.0).abs() < 1e-5);
+        assert!((hit.joint as usize) < mesh.joints.len());
+    }
+
+    #[test]
+    fn bvh_matches_brute_force() {
+        let mesh = bob_mesh();
+        let anim = bob_anim();
+        let bind: Vec<Vec<Vector3<f32>>> = mesh.meshes.iter().map(|m| prepare_mesh(m, &mesh.joints)).collect();
+        let mut bvh = TriangleBvh::new(&mesh, &bind);
+
+        for &frame in &[0, 50, 100] {
+            let skeleton = frame_skeleton(&anim, frame);
+            let positions: Vec<Vec<Vector3<f32>>> = mesh.meshes.iter().map(|m| prepare_mesh(m, &skeleton)).collect();
+            bvh.refit(&mesh, &positions);
+            let rebuilt = TriangleBvh::new(&mesh, &positions);
+
+            for i in 0..50 {
+                let angle = i as f32 * 0.37;
+                let ray = Ray {
+                    origin: Vector3::new(angle.cos() * 20.0, angle.sin() * 20.0, 1.0 + (i % 10) as f32 * 0.5),
+                    direction: Vector3::new(-angle.cos(), -angle.sin(), 0.0)
+                };
+                let expected = super::pick(&mesh, &positions, &ray);
+                assert_eq!(bvh.pick(&mesh, &positions, &ray), expected);
+                assert_eq!(rebuilt.pick(&mesh, &positions, &ray), expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use cgmath::{Vector3, InnerSpace};
+use md5::md5mesh::{Md5Mesh, Mesh, Joint};
+use vertex_computation::compute::{prepare_mesh, prepare_normals};
+use vertex_computation::convert::generate_indices;
+use vertex_computation::dual_quaternion::{DualQuaternion, blend_dual_quaternions};
+use vertex_computation::joint_normals::{JointSpaceNormals, skin_normals};
+
+/// How vertices follow the joints they are weighted to, chosen per model.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SkinningMode {
+    /// Weighted sum of the positions given by every joint, as `prepare_mesh`
+    /// does. Cheap, but collapses around twisting joints.
+    #[default]
+    LinearBlend,
+    /// Blend of the joint transforms as dual quaternions, which stays rigid
+    /// and keeps the volume around twisting joints.
+    DualQuaternion,
+}
+
+/// Maximum number of joints influencing a vertex on the GPU.
+pub const MAX_INFLUENCES: usize = 4;
+
+/// Vertex as consumed by a skinning shader: bind pose position and the
+/// strongest joints it follows, weights summing to one. Unused slots have a
+/// zero weight.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SkinningVertex {
+    pub position: [f32; 3],
+    pub joints: [u32; MAX_INFLUENCES],
+    pub weights: [f32; MAX_INFLUENCES],
+}
+
+/// Transform taking every joint from `bind` to `posed`, as a dual quaternion.
+pub fn dual_quaternion_transforms(bind: &[Joint], posed: &[Joint]) -> Vec<DualQuaternion> {
+    bind.iter().zip(posed).map(|(b, p)| {
+        let rotation = (p.orientation * b.orientation.conjugate()).normalize();
+        DualQuaternion::from_rotation_translation(rotation, p.position - rotation * b.position)
+    }).collect()
+}
+
+/// Shader data for `dual_quaternion_transforms`, eight floats per joint.
+pub fn dual_quaternion_palette(bind: &[Joint], posed: &[Joint]) -> Vec<[f32; 8]> {
+    dual_quaternion_transforms(bind, posed).iter().map(|dq| dq.to_array()).collect()
+}
+
+/// Dual quaternion counterpart of `prepare_mesh`: every vertex is taken at
+/// its `bind` position and moved by the blend of the transforms of its
+/// joints.
+pub fn prepare_mesh_dual_quaternion(m: &Mesh, bind: &Vec<Joint>, posed: &[Joint]) -> Vec<Vector3<f32>> {
+    let transforms = dual_quaternion_transforms(bind, posed);
+    let bind_positions = prepare_mesh(m, bind);
+
+    m.vertices.iter().zip(bind_positions).map(|(vertex, position)| {
+        let weights = &m.weights[vertex.start_weight as usize..(vertex.start_weight + vertex.weight_count) as usize];
+        let blend = blend_dual_quaternions(weights.iter().map(|w| (transforms[w.joint_index as usize], w.bias)));
+        blend.transform_point(position)
+    }).collect()
+}
+
+/// Positions of `m` posed by `posed`, with `bind` the joints of the md5mesh.
+pub fn skin_mesh(m: &Mesh, bind: &Vec<Joint>, posed: &Vec<Joint>, mode: SkinningMode) -> Vec<Vector3<f32>> {
+    match mode {
+        SkinningMode::LinearBlend => prepare_mesh(m, posed),
+        SkinningMode::DualQuaternion => prepare_mesh_dual_quaternion(m, bind, posed),
+    }
+}
+
+/// Positions, normals and triangle indices of a whole model, as returned by
+/// `prepare_full_mesh`.
+pub type FullMesh = (Vec<Vector3<f32>>, Vec<Vector3<f32>>, Vec<u16>);
+
+/// `prepare_full_mesh` for a posed skeleton and a skinning mode.
+pub fn skin_full_mesh(ms: &Md5Mesh, posed: &Vec<Joint>, mode: SkinningMode) -> FullMesh {
+    full_mesh(ms, posed, mode, None)
+}
+
+/// `skin_full_mesh` with the normals rotated from `joint_normals`, in the
+/// order of `ms.meshes`, rather than rebuilt from the skinned triangles.
+/// They follow the joints by linear blend whatever the mode.
+pub fn skin_full_mesh_joint_normals(ms: &Md5Mesh, posed: &Vec<Joint>, mode: SkinningMode,
+                                    joint_normals: &[JointSpaceNormals]) -> FullMesh {
+    full_mesh(ms, posed, mode, Some(joint_normals))
+}
+
+fn full_mesh(ms: &Md5Mesh, posed: &Vec<Joint>, mode: SkinningMode, joint_normals: Option<&[JointSpaceNormals]>) -> FullMesh {
+    let mut res_v: Vec<Vector3<f32>> = Vec::new();
+    let mut res_n: Vec<Vector3<f32>> = Vec::new();
+    let mut res_i: Vec<u16> = Vec::new();
+
+    for (i, m) in ms.meshes.iter().enumerate() {
+        let mut positions = skin_mesh(m, &ms.joints, posed, mode);
+        let mut normals = match joint_normals {
+            Some(joint_normals) => skin_normals(m, &joint_normals[i], posed),
+            None => prepare_normals(m, &positions)
+        };
+        let offset = res_v.len() as u16;
+
+        res_i.extend(generate_indices(m).into_iter().map(|i| i + offset));
+        res_v.append(&mut positions);
+        res_n.append(&mut normals);
+    }
+
+    (res_v, res_n, res_i)
+}
+
+/// Bind pose vertices of `m` with their `MAX_INFLUENCES` strongest joints,
+/// for shaders skinning with either mode.
+pub fn skinning_vertices(m: &Mesh, bind: &Vec<Joint>) -> Vec<SkinningVertex> {
+    let positions = prepare_mesh(m, bind);
+
+    m.vertices.iter().zip(positions).map(|(vertex, position)| {
+        let mut weights: Vec<(u32, f32)> = m.weights
+            [vertex.start_weight as usize..(vertex.start_weight + vertex.weight_count) as usize]
+            .iter()
+            .map(|w| (w.joint_index, w.bias))
+            .collect();
+        weights.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(::std::cmp::Ordering::Equal));
+        weights.truncate(MAX_INFLUENCES);
+
+        let total: f32 = weights.iter().map(|w| w.1).sum();
+        let mut result = SkinningVertex {
+            position: [position.x, position.y, position.z],
+            joints: [0; MAX_INFLUENCES],
+            weights: [0.0; MAX_INFLUENCES]
+        };
+        for (k, &(joint, bias)) in weights.iter().enumerate() {
+            result.joints[k] = joint;
+            result.weights[k] = if total > 0.0 { bias / total } else { 0.0 };
+        }
+        result
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Quaternion, Rotation3, Deg, InnerSpace};
+    use md5::md5mesh::Mesh;
+    use animation::skeleton::{find_joint, rotate_joint};
+    use vertex_computation::joint_normals::{JointSpaceNormals, skin_normals};
+    use test_support::{bob_mesh, bob_skeleton};
+    use super::SkinningMode;
+
+    /// Signed volume enclosed by the triangles of `m`.
+    fn volume(m: &Mesh, positions: &[Vector3<f32>]) -> f32 {
+        m.triangles.iter().map(|t| {
+            let (a, b, c) = t.vertex_indices;
+            positions[a as usize].dot(positions[b as usize].cross(positions[c as usize])) / 6.0
+        }).sum()
+    }
+
+    #[test]
+    fn bind_pose() {
+        let mesh = bob_mesh();
+        for mode in &[SkinningMode::LinearBlend, SkinningMode::DualQuaternion] {
+            let skinned = super::skin_mesh(&mesh.meshes[0], &mesh.joints, &mesh.joints, *mode);
+            let reference = super::prepare_mesh(&mesh.meshes[0], &mesh.joints);
+            for (a, b) in skinned.iter().zip(&reference) {
+                assert!((a - b).magnitude() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn volume_preservation() {
+        let mesh = bob_mesh();
+        let body = &mesh.meshes[0];
+
+        // Twist the left forearm around itself.
+        let mut posed = mesh.joints.clone();
+        let forearm = find_joint(&posed, "forearm.L").unwrap();
+        let wrist = find_joint(&posed, "wrist.L").unwrap();
+        let axis = (posed[wrist].position - posed[forearm].position).normalize();
+        rotate_joint(&mut posed, forearm, Quaternion::from_axis_angle(axis, Deg(150.0)));
+
+        let bind_volume = volume(body, &super::prepare_mesh(body, &mesh.joints));
+        let linear = volume(body, &super::skin_mesh(body, &mesh.joints, &posed, SkinningMode::LinearBlend));
+        let dual_quaternion = volume(body, &super::skin_mesh(body, &mesh.joints, &posed, SkinningMode::DualQuaternion));
+
+        assert!((dual_quaternion - bind_volume).abs() < (linear - bind_volume).abs());
+    }
+
+    #[test]
+    fn skinning_vertices() {
+        let mesh = bob_mesh();
+        for vertex in super::skinning_vertices(&mesh.meshes[0], &mesh.joints) {
+            let total: f32 = vertex.weights.iter().sum();
+            assert!((total - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn joint_normals() {
+        let mesh = bob_mesh();
+        let skeleton = bob_skeleton(60);
+        let joint_normals: Vec<JointSpaceNormals> = mesh.meshes.iter()
+            .map(|m| JointSpaceNormals::from_bind_pose(m, &mesh.joints))
+            .collect();
+
+        let (positions, normals, _) = super::skin_full_mesh_joint_normals(&mesh, &skeleton, SkinningMode::DualQuaternion, &joint_normals);
+        assert_eq!(positions, super::skin_full_mesh(&mesh, &skeleton, SkinningMode::DualQuaternion).0);
+        let expected: Vec<Vector3<f32>> = mesh.meshes.iter().zip(&joint_normals)
+            .flat_map(|(m, n)| skin_normals(m, n, &skeleton))
+            .collect();
+        assert_eq!(normals, expected);
+    }
+}